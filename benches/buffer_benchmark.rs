@@ -0,0 +1,122 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use jetstream::emulator::Emulator;
+use jetstream::testcase::{create_emulator, create_input_data, TESTS};
+use jetstream::Encoder;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+// Counts the reallocations made by the benchmark, which grow a buffer in place or by copying.
+struct CountingAllocator;
+
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Test cases without gzip, which allocates its own buffers for each message.
+const TEST_NAMES: [&str; 4] = ["a10-2", "b4000-80", "b4000-4000", "c4800-20"];
+
+pub fn steady_state_benchmark(c: &mut Criterion) {
+    for name in TEST_NAMES {
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(&mut ied, test.samples, test.count_of_variables, false);
+
+        let mut enc = Encoder::new(
+            Uuid::new_v4(),
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.store_channel_ranges = true;
+        enc.set_sample_timestamps(true);
+
+        // warm up with a message in each buffer, then check that encoding the data again
+        // does not grow any buffers
+        let encode_all = |enc: &mut Encoder| {
+            for d in &data {
+                enc.encode(d).unwrap();
+            }
+        };
+        encode_all(&mut enc);
+        let reallocs = REALLOCS.load(Ordering::Relaxed);
+        encode_all(&mut enc);
+        assert_eq!(
+            REALLOCS.load(Ordering::Relaxed),
+            reallocs,
+            "{} reallocated after warm-up",
+            name
+        );
+
+        c.bench_function(&format!("steady state {}", name), |b| {
+            b.iter(|| encode_all(&mut enc))
+        });
+    }
+}
+
+// Measures the latency of the first message from a new encoder, whose buffers have not yet
+// been written, with and without prewarming them.
+pub fn first_message_benchmark(c: &mut Criterion) {
+    for name in TEST_NAMES {
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            false,
+        );
+
+        for prewarm in [false, true] {
+            let new_encoder = || {
+                let mut enc = Encoder::new(
+                    Uuid::new_v4(),
+                    test.count_of_variables,
+                    test.sampling_rate,
+                    test.samples_per_message,
+                );
+                if prewarm {
+                    enc.prewarm().unwrap();
+                }
+                enc
+            };
+
+            let label = if prewarm { "prewarmed" } else { "cold" };
+            c.bench_function(&format!("first message {} {}", label, name), |b| {
+                b.iter_batched(
+                    new_encoder,
+                    |mut enc| {
+                        for d in &data {
+                            if let Some(msg) = enc.encode(d).unwrap() {
+                                return msg;
+                            }
+                        }
+                        unreachable!("a full message is emitted")
+                    },
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+}
+
+criterion_group!(benches, steady_state_benchmark, first_message_benchmark);
+criterion_main!(benches);
@@ -13,17 +13,23 @@ pub fn emulator_benchmark(c: &mut Criterion) {
         phase_offset: PHASE_OFFSET_DEG * PI / 180.0,
         ..Default::default()
     });
-    emu.i = Some(ThreePhaseEmulation {
+    let mut i = ThreePhaseEmulation {
         pos_seq_mag: 500.0,
         phase_offset: PHASE_OFFSET_DEG * PI / 180.0,
-        harmonic_numbers: vec![5.0, 7.0, 11.0, 13.0, 17.0, 19.0, 23.0, 25.0],
-        harmonic_mags: vec![
-            0.2164, 0.1242, 0.0892, 0.0693, 0.0541, 0.0458, 0.0370, 0.0332,
-        ],
-        harmonic_angs: vec![171.5, 100.4, -52.4, 128.3, 80.0, 2.9, -146.8, 133.9],
         noise_max: 0.000001,
         ..Default::default()
-    });
+    };
+    i.set_harmonics(&[
+        (5.0, 0.2164, 171.5),
+        (7.0, 0.1242, 100.4),
+        (11.0, 0.0892, -52.4),
+        (13.0, 0.0693, 128.3),
+        (17.0, 0.0541, 80.0),
+        (19.0, 0.0458, 2.9),
+        (23.0, 0.0370, -146.8),
+        (25.0, 0.0332, 133.9),
+    ]);
+    emu.i = Some(i);
 
     c.bench_function("step", |b| {
         b.iter(|| {
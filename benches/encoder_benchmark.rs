@@ -1,9 +1,18 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use jetstream::emulator::Emulator;
 use jetstream::testcase::{create_emulator, create_input_data, encode_and_decode, TESTS};
-use jetstream::{DatasetWithQuality, Decoder, Encoder};
+use jetstream::{encode_all_messages, DatasetWithQuality, Datasets, Decoder, Encoder};
 use uuid::Uuid;
 
+// Names the XOR delta variant of each test case separately.
+fn bench_name(name: &str, use_xor: bool) -> String {
+    if use_xor {
+        format!("{} xor", name)
+    } else {
+        name.to_string()
+    }
+}
+
 pub fn encode_decode_benchmark(c: &mut Criterion) {
     let mut keys = TESTS.keys().map(|k| k.to_string()).collect::<Vec<String>>();
     keys.sort();
@@ -22,37 +31,57 @@ pub fn encode_decode_benchmark(c: &mut Criterion) {
             test.quality_change,
         );
 
-        // create encoder and decoder
-        let id = Uuid::new_v4();
-        let mut stream = Encoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-        let mut stream_decoder = Decoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-
-        c.bench_function(name, |b| {
-            b.iter(|| {
-                // encode the data
-                // when each message is complete, decode
-                encode_and_decode(
-                    false,
-                    &mut data,
-                    &mut stream,
-                    &mut stream_decoder,
-                    test.count_of_variables,
-                    test.samples_per_message,
-                    test.early_encoding_stop,
-                )
-                .unwrap();
+        for use_xor in [false, true] {
+            // create encoder and decoder
+            let id = Uuid::new_v4();
+            let mut stream = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            let mut stream_decoder = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            stream.use_xor = use_xor;
+            stream_decoder.use_xor = use_xor;
+
+            // check the output once, and report the size for comparing delta transforms
+            let encode_stats = encode_and_decode(
+                true,
+                &mut data,
+                &mut stream,
+                &mut stream_decoder,
+                test.count_of_variables,
+                test.samples_per_message,
+                test.early_encoding_stop,
+                false,
+            )
+            .unwrap();
+            let bench_name = bench_name(name, use_xor);
+            println!("{}: {} bytes", bench_name, encode_stats.total_bytes);
+
+            c.bench_function(&bench_name, |b| {
+                b.iter(|| {
+                    // encode the data
+                    // when each message is complete, decode
+                    encode_and_decode(
+                        false,
+                        &mut data,
+                        &mut stream,
+                        &mut stream_decoder,
+                        test.count_of_variables,
+                        test.samples_per_message,
+                        test.early_encoding_stop,
+                        false,
+                    )
+                    .unwrap();
+                });
             });
-        });
+        }
     });
 }
 
@@ -74,34 +103,35 @@ pub fn encode_benchmark(c: &mut Criterion) {
             test.quality_change,
         );
 
-        // create encoder and decoder
-        let id = Uuid::new_v4();
-        let mut enc = Encoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-        let _dec = Decoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-
-        c.bench_function(name, |b| {
-            b.iter(|| {
-                data.iter_mut().for_each(|d| {
-                    let (_buf, len) = enc.encode(d).unwrap();
-
-                    if len > 0 {
-                        // b.StopTimer();
-                        // dec.DecodeToBuffer(buf, len);
-                        // b.StartTimer();
-                    }
+        for use_xor in [false, true] {
+            // create encoder and decoder
+            let id = Uuid::new_v4();
+            let mut enc = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            let _dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            enc.use_xor = use_xor;
+
+            c.bench_function(&bench_name(name, use_xor), |b| {
+                b.iter(|| {
+                    data.iter_mut().for_each(|d| {
+                        if let Some(_msg) = enc.encode(d).unwrap() {
+                            // b.StopTimer();
+                            // dec.DecodeToBuffer(buf, len);
+                            // b.StartTimer();
+                        }
+                    });
                 });
             });
-        });
+        }
     });
 }
 
@@ -116,44 +146,58 @@ pub fn decode_benchmark(c: &mut Criterion) {
         let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
 
         // initialise data structure for input data
-        let mut data: Vec<DatasetWithQuality> = create_input_data(
+        let data: Vec<DatasetWithQuality> = create_input_data(
             &mut ied,
             test.samples,
             test.count_of_variables,
             test.quality_change,
         );
 
-        // create encoder and decoder
-        let id = Uuid::new_v4();
-        let mut enc = Encoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-        let mut dec = Decoder::new(
-            id,
-            test.count_of_variables,
-            test.sampling_rate,
-            test.samples_per_message,
-        );
-
-        c.bench_function(name, |b| {
-            b.iter(|| {
-                // b.StopTimer();
-
-                // for d := range data {
-                data.iter_mut().for_each(|d| {
-                    let (buf, len) = enc.encode(d).unwrap();
-
-                    if len > 0 {
-                        // b.StartTimer();
-                        dec.decode_to_buffer(&buf, len).unwrap();
-                        // b.StopTimer();
-                    }
+        for use_xor in [false, true] {
+            // create encoder and decoder
+            let id = Uuid::new_v4();
+            let mut enc = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            enc.use_xor = use_xor;
+            dec.use_xor = use_xor;
+
+            // encode every message once, so that only decoding is timed
+            let messages = encode_all_messages(&mut enc, &data).unwrap();
+
+            // each iteration decodes a single message, so the time reported is the latency
+            // per message, cycling through the messages in order
+            let mut next = 0;
+            c.bench_function(&format!("decode {}", bench_name(name, use_xor)), |b| {
+                b.iter(|| {
+                    let msg = &messages[next];
+                    next = (next + 1) % messages.len();
+                    dec.decode_to_buffer(black_box(msg.as_bytes()), msg.len())
+                        .unwrap();
                 });
             });
-        });
+        }
+    });
+}
+
+pub fn dataset_benchmark(c: &mut Criterion) {
+    const SAMPLES: usize = 150000;
+    const COUNT: usize = 8;
+
+    c.bench_function("dataset vec!", |b| {
+        b.iter(|| vec![DatasetWithQuality::new(black_box(COUNT)); black_box(SAMPLES)])
+    });
+    c.bench_function("dataset Datasets::new", |b| {
+        b.iter(|| Datasets::new(black_box(SAMPLES), black_box(COUNT)))
     });
 }
 
@@ -161,6 +205,7 @@ criterion_group!(
     benches,
     encode_decode_benchmark,
     encode_benchmark,
-    decode_benchmark
+    decode_benchmark,
+    dataset_benchmark
 );
 criterion_main!(benches);
@@ -1,5 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use jetstream::encoding::simple8b;
+use jetstream::encoding::{bitops, simple8b};
+use jetstream::testcase::{create_emulator, create_input_data, TESTS};
 
 pub fn encode_benchmark(c: &mut Criterion) {
     const N: usize = 1024;
@@ -36,5 +37,113 @@ pub fn encode_benchmark(c: &mut Criterion) {
 //     });
 // }
 
-criterion_group!(benches, encode_benchmark);
+// Returns the delta-delta encoded values of the first variable, as stored by the encoder.
+fn delta_delta(values: &[i32]) -> Vec<i64> {
+    let mut diffs: Vec<i64> = values.iter().map(|&v| v as i64).collect();
+    for layer in 0..3 {
+        for k in (layer + 1..diffs.len()).rev() {
+            diffs[k] -= diffs[k - 1];
+        }
+    }
+    diffs
+}
+
+pub fn encode_signed_benchmark(c: &mut Criterion) {
+    let test = TESTS.get("g150000-150000").unwrap();
+    let mut ied = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let values: Vec<i32> = data.iter().map(|d| d.i32s[0]).collect();
+    let diffs = delta_delta(&values);
+    let mut d: Vec<u64> = vec![0; diffs.len()];
+
+    c.bench_function("encode_all_signed g150000", |b| {
+        b.iter(|| {
+            simple8b::encode_all_signed(black_box(&mut d), black_box(&diffs)).unwrap();
+        })
+    });
+    c.bench_function("encode_signed g150000", |b| {
+        b.iter(|| {
+            simple8b::encode_signed(black_box(&mut d), black_box(&diffs)).unwrap();
+        })
+    });
+}
+
+// Zig-zag encodes the delta-delta encoded values of every variable, one after another as the
+// encoder stores them. Most values are small, but the first value of each variable and the
+// harmonics of the currents need many more bits, so every selector size is exercised.
+fn mixed_magnitudes(name: &str) -> Vec<u64> {
+    let test = TESTS.get(name).unwrap();
+    let mut ied = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    (0..test.count_of_variables)
+        .flat_map(|i| {
+            let values: Vec<i32> = data.iter().map(|d| d.i32s[i]).collect();
+            delta_delta(&values)
+        })
+        .map(bitops::zig_zag_encode64)
+        .collect()
+}
+
+pub fn encode_mixed_benchmark(c: &mut Criterion) {
+    let x = mixed_magnitudes("b4000-4000");
+    let mut d: Vec<u64> = vec![0; x.len()];
+
+    c.bench_function("encode_all mixed b4000", |b| {
+        b.iter(|| {
+            simple8b::encode_all_ref(black_box(&mut d), black_box(&x)).unwrap();
+        })
+    });
+}
+
+pub fn decode_i32_benchmark(c: &mut Criterion) {
+    let test = TESTS.get("g150000-150000").unwrap();
+    let mut ied = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let values: Vec<i32> = data.iter().map(|d| d.i32s[0]).collect();
+    let diffs = delta_delta(&values);
+    let mut words: Vec<u64> = vec![0; diffs.len()];
+    let n = simple8b::encode_signed(&mut words, &diffs).unwrap();
+    let buf: Vec<u8> = words[..n].iter().flat_map(|w| w.to_be_bytes()).collect();
+    let mut out: Vec<i32> = vec![0; diffs.len()];
+
+    c.bench_function("for_each zig-zag g150000", |b| {
+        b.iter(|| {
+            let mut k = 0;
+            simple8b::for_each(black_box(&buf), |v| {
+                out[k] = bitops::zig_zag_decode64(v) as i32;
+                k += 1;
+                k < out.len()
+            })
+            .unwrap();
+        })
+    });
+    c.bench_function("decode_all_i32 g150000", |b| {
+        b.iter(|| {
+            simple8b::decode_all_i32(black_box(&mut out), black_box(&buf)).unwrap();
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    encode_benchmark,
+    encode_mixed_benchmark,
+    encode_signed_benchmark,
+    decode_i32_benchmark
+);
 criterion_main!(benches);
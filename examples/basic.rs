@@ -24,6 +24,22 @@ fn main() {
     ));
     let mut enc = enc_lock.lock().unwrap();
 
+    // record the units of the 9-2 LE variables, with currents in mA and voltages in 10 mV
+    let scale_factors: Vec<f64> = [
+        Channel::PhaseAI,
+        Channel::PhaseBI,
+        Channel::PhaseCI,
+        Channel::NeutralI,
+        Channel::PhaseAV,
+        Channel::PhaseBV,
+        Channel::PhaseCV,
+        Channel::NeutralV,
+    ]
+    .iter()
+    .map(|channel| channel.scale_factor())
+    .collect();
+    enc.set_scale_factors(&scale_factors).unwrap();
+
     // use the Synaptec "emulator" library to generate three-phase voltage and current test signals
     let mut emu = emulator::Emulator::new(sampling_rate, system_frequency);
     emu.i = Some(emulator::ThreePhaseEmulation {
@@ -40,14 +56,22 @@ fn main() {
     let samples_to_encode = message_count * 480; // equates to 1 full message
     let mut data = create_input_data(&mut emu, samples_to_encode, variable_per_sample);
 
+    // initialise a decoder, which is reused for every message (and can be reconfigured for
+    // another stream with `reset`)
+    let mut dec = Decoder::new(
+        uuid,
+        variable_per_sample,
+        sampling_rate,
+        samples_per_message,
+    );
+    dec.set_scale_factors(enc.scale_factors());
+
     let t0 = Instant::now();
     // loop through data samples and encode into Slipstream format
     data.iter_mut().for_each(|d| {
-        let (buf, length) = enc.encode(d).unwrap();
-
         // check if message encoding has finished (or an error occurred)
-        if length > 0 {
-            // buf should now contain an encoded message, and can be send over the network or stored
+        if let Some(msg) = enc.encode(d).unwrap() {
+            // msg should now contain an encoded message, and can be send over the network or stored
 
             // print encoding performance results
             if !quiet {
@@ -55,28 +79,20 @@ fn main() {
                 println!("Original data size: {} bytes", theory_bytes);
                 println!(
                     "Encoded Slipstream message size: {} bytes ({:1.2} of original)",
-                    buf.len(),
-                    100.0 * (buf.len() as f64) / (theory_bytes as f64)
+                    msg.len(),
+                    100.0 * (msg.len() as f64) / (theory_bytes as f64)
                 );
             }
 
-            // initialise a decoder
-            let mut dec = Decoder::new(
-                uuid,
-                variable_per_sample,
-                sampling_rate,
-                samples_per_message,
-            );
-
             // decode the message
-            dec.decode_to_buffer(&buf, length).unwrap();
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
 
             // iterate through the decoded samples
             if !quiet {
                 let mut decoded_data = vec![0.0; samples_to_encode];
-                for i in 0..dec.out.len() {
-                    // extract the phase A current values (at index '0') and convert to Amps
-                    decoded_data[i] = (dec.out[i].i32s[0] as f64) / 1000.0;
+                for (i, sample) in dec.out_scaled().iter().enumerate() {
+                    // extract the phase A current values, in Amps
+                    decoded_data[i] = sample[Channel::PhaseAI.index()];
 
                     // extract individual values
                     // for j in 0..dec.i32_count {
@@ -124,14 +140,14 @@ fn create_input_data(
         let v = ied.v.as_mut().unwrap();
 
         // set waveform data for current and voltage
-        d.i32s[0] = (i.a * 1000.0) as i32;
-        d.i32s[1] = (i.b * 1000.0) as i32;
-        d.i32s[2] = (i.c * 1000.0) as i32;
-        d.i32s[3] = ((i.a + i.b + i.c) * 1000.0) as i32;
-        d.i32s[4] = (v.a * 100.0) as i32;
-        d.i32s[5] = (v.b * 100.0) as i32;
-        d.i32s[6] = (v.c * 100.0) as i32;
-        d.i32s[7] = ((v.a + v.b + v.c) * 100.0) as i32;
+        d.i32s[Channel::PhaseAI.index()] = (i.a * 1000.0) as i32;
+        d.i32s[Channel::PhaseBI.index()] = (i.b * 1000.0) as i32;
+        d.i32s[Channel::PhaseCI.index()] = (i.c * 1000.0) as i32;
+        d.i32s[Channel::NeutralI.index()] = ((i.a + i.b + i.c) * 1000.0) as i32;
+        d.i32s[Channel::PhaseAV.index()] = (v.a * 100.0) as i32;
+        d.i32s[Channel::PhaseBV.index()] = (v.b * 100.0) as i32;
+        d.i32s[Channel::PhaseCV.index()] = (v.c * 100.0) as i32;
+        d.i32s[Channel::NeutralV.index()] = ((v.a + v.b + v.c) * 100.0) as i32;
 
         // set quality data
         d.q[0] = 0;
@@ -0,0 +1,26 @@
+//! Encodes and decodes a stream without the standard library. Messages of more than 4096
+//! samples are compressed, which needs the `std` feature, so shorter messages are used.
+#![no_std]
+
+use jetstream::{DatasetWithQuality, Decoder, Encoder, JetstreamError};
+use uuid::Uuid;
+
+/// Encodes the samples of a stream of `i32_count` variables at 4 kHz, in messages of 80
+/// samples, and decodes each message, returning the number of samples decoded.
+pub fn round_trip(
+    id: Uuid,
+    i32_count: usize,
+    data: &[DatasetWithQuality],
+) -> Result<usize, JetstreamError> {
+    let mut enc = Encoder::new(id, i32_count, 4000, 80);
+    let mut dec = Decoder::new(id, i32_count, 4000, 80);
+
+    let mut decoded = 0;
+    for d in data {
+        if let Some(msg) = enc.encode(d)? {
+            dec.decode_to_buffer(msg.as_bytes(), msg.len())?;
+            decoded += dec.encoded_samples();
+        }
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,68 @@
+use crate::encoder::{encode_all_messages, Encoder};
+use crate::error::JetstreamError;
+use crate::jetstream::DatasetWithQuality;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+// The size of each value in the uncompressed representation: a 4 byte value, 4 byte quality
+// and 8 byte timestamp.
+const RAW_BYTES_PER_VALUE: usize = 16;
+
+/// The size of a dataset encoded with jetstream, compared with its uncompressed size and the
+/// size of the uncompressed data compressed with gzip.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressionReport {
+    pub samples: usize,
+    pub messages: usize,
+    /// The total size of the encoded messages, including headers.
+    pub jetstream_bytes: usize,
+    /// The size with a value, quality and timestamp stored for every variable and sample.
+    pub raw_bytes: usize,
+    /// The size of the raw representation compressed with gzip as a single stream.
+    pub gzip_bytes: usize,
+}
+
+impl CompressionReport {
+    /// The jetstream size as a fraction of the raw size.
+    pub fn jetstream_ratio(&self) -> f64 {
+        self.jetstream_bytes as f64 / self.raw_bytes as f64
+    }
+
+    /// The gzip size as a fraction of the raw size.
+    pub fn gzip_ratio(&self) -> f64 {
+        self.gzip_bytes as f64 / self.raw_bytes as f64
+    }
+}
+
+/// Encodes `data` with a configured encoder, as `encode_all_messages` does, and reports the
+/// size of the messages compared with the raw data and with gzip. The encoder is left ready
+/// for the next message.
+pub fn analyze(
+    enc: &mut Encoder,
+    data: &[DatasetWithQuality],
+) -> Result<CompressionReport, JetstreamError> {
+    let messages = encode_all_messages(enc, data)?;
+
+    // store the raw data in sample order, as a producer without jetstream would send it
+    let mut raw = Vec::with_capacity(data.len() * enc.i32_count * RAW_BYTES_PER_VALUE);
+    for d in data {
+        for (value, q) in d.i32s.iter().zip(&d.q) {
+            raw.extend_from_slice(&value.to_be_bytes());
+            raw.extend_from_slice(&q.to_be_bytes());
+            raw.extend_from_slice(&d.t.to_be_bytes());
+        }
+    }
+
+    let mut gz = GzEncoder::new(vec![], Compression::best());
+    gz.write_all(&raw).map_err(JetstreamError::Gzip)?;
+    let gzip_bytes = gz.finish().map_err(JetstreamError::Gzip)?.len();
+
+    Ok(CompressionReport {
+        samples: data.len(),
+        messages: messages.len(),
+        jetstream_bytes: messages.iter().map(|msg| msg.len()).sum(),
+        raw_bytes: raw.len(),
+        gzip_bytes,
+    })
+}
@@ -0,0 +1,90 @@
+use crate::decoder::Decoder;
+use crate::error::JetstreamError;
+use crate::framing::write_frame;
+use crate::jetstream::{id_from_bytes, put_uvarint32, read_at, read_id, uvarint32, EncodedMessage};
+
+/// Packs messages from several streams, each with its own ID and set of variables, into one
+/// container message. The container holds the number of messages, followed by a sub-header
+/// for each message with its number of variables, and then the message prefixed with its
+/// length.
+pub fn pack_messages(msgs: &[EncodedMessage]) -> Vec<u8> {
+    let mut buf = vec![0; 5];
+    let n = put_uvarint32(&mut buf, msgs.len() as u32);
+    buf.truncate(n);
+
+    let mut sub_header = [0; 5];
+    for msg in msgs {
+        let n = put_uvarint32(&mut sub_header, msg.i32_count() as u32);
+        buf.extend_from_slice(&sub_header[..n]);
+        write_frame(&mut buf, msg.as_bytes()).expect("writing to a Vec cannot fail");
+    }
+    buf
+}
+
+/// Splits a container message into the messages it holds, returning the number of variables
+/// in each message along with its bytes.
+pub fn unpack_messages(buf: &[u8]) -> Result<Vec<(usize, &[u8])>, JetstreamError> {
+    let (count, mut length) = uvarint32(buf)?;
+
+    // the count is untrusted, and each message takes at least two bytes
+    let mut msgs = Vec::with_capacity(usize::min(count as usize, buf.len() / 2));
+    for _ in 0..count {
+        let (i32_count, len_b) = read_at(buf, length, uvarint32)?;
+        length += len_b;
+        let (msg_len, len_b) = read_at(buf, length, uvarint32)?;
+        length += len_b;
+        let end = length
+            .checked_add(msg_len as usize)
+            .ok_or_else(|| JetstreamError::Corrupt("container message too long".to_string()))?;
+        if buf.len() < end {
+            return Err(JetstreamError::Truncated {
+                needed: end,
+                got: buf.len(),
+            });
+        }
+        msgs.push((i32_count as usize, &buf[length..end]));
+        length = end;
+    }
+    Ok(msgs)
+}
+
+/// Decodes each message in a container with the decoder matching its ID, returning the index
+/// of the decoder used for each message, in order. If a container holds more than one message
+/// for a stream, only the output of the last is left in the decoder.
+///
+/// The streams may have different numbers of variables. A message whose sub-header does not
+/// match the number of variables of its decoder is rejected as corrupt, rather than resizing
+/// the decoder from untrusted data.
+pub fn decode_container(
+    buf: &[u8],
+    decoders: &mut [Decoder],
+) -> Result<Vec<usize>, JetstreamError> {
+    let msgs = unpack_messages(buf)?;
+
+    let mut used = Vec::with_capacity(msgs.len());
+    for (i32_count, msg) in msgs {
+        let id = message_id(msg)?;
+        let index = decoders
+            .iter()
+            .position(|dec| dec.id.as_bytes()[..id.len()] == *id)
+            .ok_or(JetstreamError::UnknownStream(id_from_bytes(id)))?;
+
+        let dec = &mut decoders[index];
+        if dec.i32_count != i32_count {
+            return Err(JetstreamError::Corrupt(format!(
+                "container message has {} variables, but its decoder has {}",
+                i32_count, dec.i32_count
+            )));
+        }
+        dec.decode_to_buffer(msg, msg.len())?;
+        used.push(index);
+    }
+    Ok(used)
+}
+
+// Reads the ID which follows the header flags of a message, which is only the first 8 bytes
+// of the stream's UUID if compact.
+fn message_id(msg: &[u8]) -> Result<&[u8], JetstreamError> {
+    let (flags, length) = uvarint32(msg)?;
+    read_id(msg, length, flags)
+}
@@ -1,10 +1,24 @@
-use crate::encoding::{bitops, simple8b};
+use crate::encoding::simple8b;
+use crate::error::JetstreamError;
 use crate::jetstream::{
-    create_spatial_refs, get_delta_encoding, uvarint32, varint32, DatasetWithQuality,
-    SIMPLE8B_THRESHOLD_SAMPLES, USE_GZIP_THRESHOLD_SAMPLES,
+    create_spatial_refs, create_spatial_refs_with, get_delta_encoding, id_from_bytes, read_at,
+    read_id, uvarint32, uvarint64, varint32, Compression, DatasetWithQuality, FormatDescriptor,
+    NeutralRefs, QualityWidth, FLAG_ACTIVE_CHANNELS, FLAG_ALIGNED_WORDS, FLAG_BOOLS,
+    FLAG_CHANNEL_RANGES, FLAG_EPOCH_TIMESTAMP, FLAG_HEARTBEAT, FLAG_LITTLE_ENDIAN,
+    FLAG_QUALITY_FIRST, FLAG_QUALITY_RESET, FLAG_SAMPLE_TIMESTAMPS, FLAG_SAMPLING_RATE,
+    MAX_CHANNEL_RANGE_SIZE, MAX_DELTA_ENCODING_LAYERS, MAX_HEADER_SIZE, SIMPLE8B_THRESHOLD_SAMPLES,
 };
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Deref;
+#[cfg(feature = "std")]
 use flate2::read::GzDecoder;
-use std::io::Read;
+use log::warn;
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Read};
 use uuid::Uuid;
 
 /// A stream protocol instance for decoding.
@@ -16,12 +30,200 @@ pub struct Decoder {
     pub i32_count: usize,
     pub out: Vec<DatasetWithQuality>,
     start_timestamp: u64,
+    epoch: Option<u64>,
+    message_sampling_rate: usize,
+    channel_ranges: Option<Vec<(i32, i32)>>,
+    // the variables present in the most recently decoded message
+    active_channels: Vec<bool>,
+    bool_count: usize,
+    // the scale factor of each float channel, which are the last variables
+    float_scales: Vec<f64>,
+    heartbeat: bool,
+    /// Infer the timestamp of every sample in nanoseconds from the start timestamp and the
+    /// sampling rate, instead of reporting the sample number relative to the first sample.
+    pub infer_timestamps: bool,
+    /// Check that decoded timestamps are contiguous, recording the position of any gaps. Only
+    /// timestamps in nanoseconds are checked: those encoded for every sample, or inferred.
+    pub check_contiguity: bool,
+    gaps: Vec<usize>,
+    /// Whether to check that quality values only set defined bits.
+    pub quality_check: QualityCheck,
+    /// The policy for samples which are invalid or were not included in the message.
+    pub invalid_samples: InvalidSamples,
+    /// The largest number of samples a message header may claim, independent of
+    /// `samples_per_message`, so that a message from a faulty or malicious encoder is rejected
+    /// with `JetstreamError::Corrupt` before any memory is allocated for its samples.
+    pub max_message_samples: Option<usize>,
+    // the most recent valid value of each variable
+    last_valid: Vec<i32>,
+    // the inclusive range of values expected for each variable, if checked
+    plausible_ranges: Vec<Option<(i32, i32)>>,
+    // the factor converting each variable to engineering units, and the scaled output
+    scale_factors: Vec<f64>,
+    scaled_out: Vec<Vec<f64>>,
     using_simple8b: bool,
+    // the decoded simple-8b values, grouped by variable
+    simple8b_values: Vec<i32>,
     delta_encoding_layers: usize,
     delta_sum: Vec<Vec<i32>>,
-    /// Use XOR delta instead of arithmetic delta.
+    /// Use XOR delta instead of arithmetic delta. The header of each message records the
+    /// choice of the encoder, which takes precedence: if it differs, a warning is logged and
+    /// this setting is corrected.
     pub use_xor: bool,
     spatial_ref: Vec<Option<usize>>,
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+// The IEC 61850 validity bits of a quality value, and the value marking a sample as invalid.
+const QUALITY_VALIDITY_MASK: u32 = 0b11;
+const QUALITY_INVALID: u32 = 0b01;
+const QUALITY_RESERVED_VALIDITY: u32 = 0b10;
+
+// The bits of a quality value which are defined: validity, detail quality, source, test,
+// operator blocked and derived in the two least significant bytes, and time quality in the
+// most significant byte.
+const QUALITY_DEFINED_BITS: u32 = 0xff00_3fff;
+
+/// How the decoder treats quality values which set reserved bits, or the reserved validity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QualityCheck {
+    /// Quality values are opaque, and are not checked.
+    #[default]
+    Ignore,
+    /// Log a warning for each malformed quality value, but decode the message.
+    Warn,
+    /// Return `JetstreamError::InvalidQuality` for the first malformed quality value.
+    Reject,
+}
+
+/// What the decoder writes in place of samples which are marked invalid by their quality, and
+/// in the output beyond the samples of a message which was flushed before it was full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidSamples {
+    /// Leave the values as they were encoded, or from the previous message if not written.
+    #[default]
+    Keep,
+    /// Replace the values with zero.
+    Zero,
+    /// Replace the values with the most recent valid value of the same variable, which may be
+    /// from an earlier message.
+    HoldLast,
+}
+
+/// The encoding of a message, as reported by `Decoder::inspect`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageInfo {
+    /// The ID of the stream which produced the message. The last 8 bytes are zero if the
+    /// message holds a compact ID.
+    pub id: Uuid,
+    /// The message is a heartbeat, and has no other fields.
+    pub heartbeat: bool,
+    /// The number of samples in the message.
+    pub samples: usize,
+    /// The sampling rate of the message.
+    pub sampling_rate: usize,
+    /// The samples are packed into simple-8b words, rather than stored as varints.
+    pub simple8b: bool,
+    /// The algorithm which compresses the payload following the header.
+    pub compression: Compression,
+    /// The number of layers of delta encoding applied to each variable.
+    pub delta_encoding_layers: usize,
+    /// The timestamp and simple-8b words are little-endian.
+    pub little_endian: bool,
+}
+
+/// Identifies the output of a single call to `Decoder::decode_to_buffer`.
+///
+/// In debug builds the ticket records the decoder's generation counter, so that reading the
+/// output after a later message has overwritten it panics. In release builds it is zero-sized.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputTicket {
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+/// Decoded samples borrowed from a `Decoder`.
+pub struct DecodedOutput<'a> {
+    out: &'a [DatasetWithQuality],
+}
+
+impl<'a> Deref for DecodedOutput<'a> {
+    type Target = [DatasetWithQuality];
+
+    fn deref(&self) -> &Self::Target {
+        self.out
+    }
+}
+
+// Allocates the storage for delta-delta decoding, which is not needed if each message only
+// contains a single sample.
+fn delta_sum_storage(
+    i32_count: usize,
+    samples_per_message: usize,
+    delta_encoding_layers: usize,
+) -> Vec<Vec<i32>> {
+    if samples_per_message > 1 {
+        vec![vec![0; i32_count]; delta_encoding_layers - 1]
+    } else {
+        vec![]
+    }
+}
+
+// Decompresses the payload of a message, which starts at `offset` in `buf`.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn decompress(
+    buf: &[u8],
+    offset: usize,
+    compression: Compression,
+) -> Result<Vec<u8>, JetstreamError> {
+    match compression {
+        #[cfg(feature = "std")]
+        Compression::Gzip => decompress_gzip(buf, offset),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => decompress_zstd(buf, offset),
+        // without the feature for the algorithm, the message cannot be decoded
+        #[allow(unreachable_patterns)]
+        _ => Err(JetstreamError::UnsupportedCompression(compression)),
+    }
+}
+
+#[cfg(feature = "std")]
+fn decompress_gzip(buf: &[u8], offset: usize) -> Result<Vec<u8>, JetstreamError> {
+    let mut gr = GzDecoder::new(&buf[offset..]);
+
+    let mut gz_buf = Vec::new();
+    if let Err(err) = gr.read_to_end(&mut gz_buf) {
+        return Err(match err.kind() {
+            // the compressed length is unknown, so at least one more byte is needed
+            ErrorKind::UnexpectedEof => JetstreamError::Truncated {
+                needed: buf.len() + 1,
+                got: buf.len(),
+            },
+            _ => JetstreamError::Gzip(err),
+        });
+    }
+    Ok(gz_buf)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(buf: &[u8], offset: usize) -> Result<Vec<u8>, JetstreamError> {
+    zstd::stream::decode_all(&buf[offset..]).map_err(|err| match err.kind() {
+        // the compressed length is unknown, so at least one more byte is needed
+        ErrorKind::UnexpectedEof => JetstreamError::Truncated {
+            needed: buf.len() + 1,
+            got: buf.len(),
+        },
+        _ => JetstreamError::Zstd(err),
+    })
+}
+
+// Returns the index of the deepest running sum in `delta_sum` updated by the value `j` samples
+// after delta encoding started, which is then integrated into each shallower sum. Returns
+// `None` if the value is not integrated: the first value is stored directly, and with a single
+// layer each value is the difference from the previous sample.
+pub(crate) fn delta_sum_index(j: usize, delta_encoding_layers: usize) -> Option<usize> {
+    usize::min(j, delta_encoding_layers.checked_sub(1)?).checked_sub(1)
 }
 
 impl Decoder {
@@ -31,8 +233,65 @@ impl Decoder {
         i32_count: usize,
         sampling_rate: usize,
         samples_per_message: usize,
+    ) -> Self {
+        let mut dec = Self::without_buffers(id, i32_count, sampling_rate, samples_per_message);
+        dec.size_buffers();
+        dec
+    }
+
+    /// Creates a decoder for a stream which applies `layers` of delta encoding, from 1 to 4,
+    /// instead of the number chosen for the sampling rate. The number recorded in each message
+    /// header takes precedence, so this only sizes the decoding state up front.
+    pub fn with_delta_layers(
+        id: Uuid,
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
+        layers: usize,
+    ) -> Result<Self, JetstreamError> {
+        if !(1..=MAX_DELTA_ENCODING_LAYERS).contains(&layers) {
+            return Err(JetstreamError::InvalidInput(format!(
+                "{} delta encoding layers is not between 1 and {}",
+                layers, MAX_DELTA_ENCODING_LAYERS
+            )));
+        }
+
+        let mut dec = Self::without_buffers(id, i32_count, sampling_rate, samples_per_message);
+        dec.delta_encoding_layers = layers;
+        dec.size_buffers();
+        Ok(dec)
+    }
+
+    /// Reconfigures the decoder for another stream, leaving it as if it had been created by
+    /// `new` with these arguments: the start timestamp and the number of encoded samples are
+    /// cleared, and every setting returns to its default. The allocations for the output and
+    /// the decoding state are reused, and only grow if the new stream needs more space.
+    pub fn reset(
+        &mut self,
+        id: Uuid,
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
+    ) {
+        let mut dec = Self::without_buffers(id, i32_count, sampling_rate, samples_per_message);
+        dec.out = core::mem::take(&mut self.out);
+        dec.simple8b_values = core::mem::take(&mut self.simple8b_values);
+        dec.delta_sum = core::mem::take(&mut self.delta_sum);
+        dec.spatial_ref = core::mem::take(&mut self.spatial_ref);
+        dec.size_buffers();
+        *self = dec;
+    }
+
+    // Creates a decoder whose output and decoding state are empty, to be sized by
+    // `size_buffers`.
+    fn without_buffers(
+        id: Uuid,
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
     ) -> Self {
         let delta_encoding_layers = get_delta_encoding(sampling_rate);
+        let using_simple8b = samples_per_message > SIMPLE8B_THRESHOLD_SAMPLES;
 
         Self {
             id,
@@ -40,24 +299,126 @@ impl Decoder {
             samples_per_message,
             encoded_samples: 0,
             i32_count,
-            // initialise each set of outputs in data structure
-            out: vec![DatasetWithQuality::new(i32_count); samples_per_message],
+            out: vec![],
             start_timestamp: 0,
-            using_simple8b: samples_per_message > SIMPLE8B_THRESHOLD_SAMPLES,
+            epoch: None,
+            message_sampling_rate: sampling_rate,
+            channel_ranges: None,
+            active_channels: vec![true; i32_count],
+            bool_count: 0,
+            float_scales: vec![],
+            heartbeat: false,
+            infer_timestamps: false,
+            check_contiguity: false,
+            gaps: vec![],
+            quality_check: QualityCheck::Ignore,
+            invalid_samples: InvalidSamples::Keep,
+            max_message_samples: None,
+            last_valid: vec![0; i32_count],
+            plausible_ranges: vec![None; i32_count],
+            scale_factors: vec![1.0; i32_count],
+            scaled_out: vec![],
+            using_simple8b,
+            simple8b_values: vec![],
             delta_encoding_layers,
-            // storage for delta-delta decoding
-            delta_sum: vec![vec![0; i32_count]; delta_encoding_layers - 1],
+            delta_sum: vec![],
             use_xor: false,
-            spatial_ref: vec![None; i32_count],
+            spatial_ref: vec![],
+            #[cfg(debug_assertions)]
+            generation: 0,
+        }
+    }
+
+    // Sizes the output and the decoding state for the stream, zeroing any existing values
+    // while keeping their allocations.
+    fn size_buffers(&mut self) {
+        let (samples, count) = (self.samples_per_message, self.i32_count);
+
+        // initialise each set of outputs in data structure
+        self.out.truncate(samples);
+        for sample in &mut self.out {
+            sample.t = 0;
+            sample.i32s.clear();
+            sample.i32s.resize(count, 0);
+            sample.q.clear();
+            sample.q.resize(count, 0);
+            sample.bools.clear();
+            sample.f32s.clear();
+        }
+        self.out
+            .resize_with(samples, || DatasetWithQuality::new(count));
+
+        self.simple8b_values.clear();
+        if self.using_simple8b {
+            self.simple8b_values.resize(samples * count, 0);
+        }
+
+        // storage for delta-delta decoding, as allocated by `delta_sum_storage`
+        let layers = if samples > 1 {
+            self.delta_encoding_layers - 1
+        } else {
+            0
+        };
+        self.delta_sum.truncate(layers);
+        for sums in &mut self.delta_sum {
+            sums.clear();
+            sums.resize(count, 0);
+        }
+        self.delta_sum.resize(layers, vec![0; count]);
+
+        self.spatial_ref.clear();
+        self.spatial_ref.resize(count, None);
+    }
+
+    /// Creates a stream protocol decoder instance from a raw 16 byte ID.
+    pub fn from_id_bytes(
+        id: [u8; 16],
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
+    ) -> Self {
+        Self::new(
+            Uuid::from_bytes(id),
+            i32_count,
+            sampling_rate,
+            samples_per_message,
+        )
+    }
+
+    /// Returns a ticket for the output of the most recent decode.
+    pub fn ticket(&self) -> OutputTicket {
+        OutputTicket {
+            #[cfg(debug_assertions)]
+            generation: self.generation,
+        }
+    }
+
+    /// Borrows the decoded samples identified by `ticket`.
+    ///
+    /// Panics in debug builds if another message has been decoded since the ticket was taken.
+    pub fn output(&self, ticket: OutputTicket) -> DecodedOutput<'_> {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            ticket.generation, self.generation,
+            "decoder output was overwritten by a later decode"
+        );
+        #[cfg(not(debug_assertions))]
+        let _ = ticket;
+
+        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+        DecodedOutput {
+            out: &self.out[..actual_samples],
         }
     }
 
-    // /// Use XOR delta instead of arithmetic delta.
-    // pub fn set_xor(&mut self, xor: bool) {
-    //     self.use_xor = xor
-    // }
+    /// Use XOR delta instead of arithmetic delta. The header of each message takes precedence,
+    /// so this only avoids the warning logged when the first message uses XOR.
+    pub fn set_xor(&mut self, xor: bool) {
+        self.use_xor = xor
+    }
 
     /// Automatically maps adjacent sets of three-phase currents for spatial compression.
+    /// Logs a warning if the configuration does not map any variables.
     pub fn set_spatial_refs(
         &mut self,
         count: usize,
@@ -66,188 +427,331 @@ impl Decoder {
         include_neutral: bool,
     ) {
         self.spatial_ref = create_spatial_refs(count, count_v, count_i, include_neutral);
+        self.warn_if_no_spatial_refs(count, count_v, count_i);
     }
 
-    /// Decodes to a pre-allocated buffer.
-    pub fn decode_to_buffer(&mut self, buf: &[u8], _total_length: usize) -> Result<(), String> {
-        let mut length: usize = 16;
+    /// Like `set_spatial_refs`, matching `Encoder::set_spatial_refs_with`.
+    pub fn set_spatial_refs_with(
+        &mut self,
+        count: usize,
+        count_v: usize,
+        count_i: usize,
+        neutral: NeutralRefs,
+    ) {
+        self.spatial_ref = create_spatial_refs_with(count, count_v, count_i, neutral);
+        self.warn_if_no_spatial_refs(count, count_v, count_i);
+    }
 
-        // check ID
-        if buf[..length] != self.id.as_bytes()[..] {
-            return Err("IDs did not match".to_string());
+    fn warn_if_no_spatial_refs(&self, count: usize, count_v: usize, count_i: usize) {
+        if self.spatial_ref.iter().all(Option::is_none) {
+            warn!(
+                count = count, count_v = count_v, count_i = count_i;
+                "spatial references have no effect"
+            );
         }
+    }
 
-        // decode timestamp
-        self.start_timestamp = u64::from_be_bytes(buf[length..length + 8].try_into().unwrap());
-        length += 8;
+    /// Disables spatial compression, matching `Encoder::clear_spatial_refs`.
+    pub fn clear_spatial_refs(&mut self) {
+        self.spatial_ref = vec![None; self.i32_count];
+    }
 
-        // the first timestamp is the starting value encoded in the header
-        self.out[0].t = self.start_timestamp;
+    /// Sets the stream epoch, matching `Encoder::set_epoch`.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = Some(epoch);
+    }
 
-        // decode number of samples
-        let (val_signed, len_b) = varint32(&buf[length..]);
-        self.encoded_samples = val_signed as usize;
-        length += len_b;
+    /// Sets the inclusive range of physically plausible values for variable `i`, or `None` to
+    /// disable the check. A message with a value outside the range, after delta decoding and
+    /// spatial references are applied, is rejected with `JetstreamError::Implausible`.
+    pub fn set_plausible_range(&mut self, i: usize, range: Option<(i32, i32)>) {
+        self.plausible_ranges[i] = range;
+    }
 
-        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+    /// Sets the number of boolean channels in each dataset, matching `Encoder::set_bool_count`.
+    pub fn set_bool_count(&mut self, count: usize) {
+        self.bool_count = count;
+        for sample in self.out.iter_mut() {
+            sample.bools.resize(count, false);
+        }
+    }
 
-        let out_bytes = if actual_samples > USE_GZIP_THRESHOLD_SAMPLES {
-            let mut gr = GzDecoder::new(&buf[length..]);
+    /// Decodes the last `scales.len()` variables of the stream as float channels, multiplying
+    /// each by its scale factor into the `f32s` of the output, as configured for the encoder
+    /// with `Encoder::set_float_channels`. The quantized values remain in the last entries of
+    /// the `i32s`.
+    ///
+    /// Panics if there are more float channels than variables.
+    pub fn set_float_channels(&mut self, scales: &[f64]) {
+        assert!(
+            scales.len() <= self.i32_count,
+            "more float channels than variables"
+        );
+        self.float_scales = scales.to_vec();
+        for sample in self.out.iter_mut() {
+            sample.f32s.resize(scales.len(), 0.0);
+        }
+    }
 
-            let mut gz_buf = Vec::new();
-            if let Err(err) = gr.read_to_end(&mut gz_buf) {
-                return Err(format!("gzip error: {}", err));
-            }
-            gz_buf
-        } else {
-            buf[length..].to_vec()
-        };
-        length = 0;
+    /// Sets the factor by which each variable is multiplied by `decode_to_f64` and `out_scaled`,
+    /// such as `0.001` for a current encoded in mA, matching `Encoder::scale_factors`. The
+    /// factors default to one.
+    ///
+    /// Panics if there is not one factor for each variable.
+    pub fn set_scale_factors(&mut self, scale_factors: &[f64]) {
+        assert_eq!(
+            scale_factors.len(),
+            self.i32_count,
+            "expected one scale factor for each variable"
+        );
+        self.scale_factors.copy_from_slice(scale_factors);
+    }
 
-        if self.using_simple8b {
-            // for simple-8b encoding, iterate through every value
-            let mut decode_counter = 0;
-            let mut index_ts = 0;
-            let mut i = 0;
-
-            let decoded_u64s = simple8b::for_each(&out_bytes[length..], |v: u64| -> bool {
-                // manage 2D slice indices
-                index_ts = decode_counter % actual_samples;
-                if decode_counter > 0 && index_ts == 0 {
-                    i += 1;
-                }
+    /// Returns an upper bound on the size of a message for this decoder's stream, so that a
+    /// receive buffer can be allocated once. This allows for every optional header field, a
+    /// simple-8b word for every value, a change of quality at every sample, every sample
+    /// timestamp, and the overhead of compression, so typical messages are much smaller.
+    pub fn expected_message_bytes(&self) -> usize {
+        let values = self.samples_per_message * self.i32_count;
+        let payload = values * 8 // a simple-8b word or varint for each value
+            + values * 10 // a quality value and run length for each value
+            + self.bool_count * (1 + self.samples_per_message * 5) // a run for each sample
+            + self.samples_per_message * 10; // an offset for each sample timestamp
 
-                // get signed value back with zig-zag decoding
-                let decoded_value = bitops::zig_zag_decode64(v) as i32;
+        // the compression threshold is set by the encoder, so allow for the gzip header and
+        // trailer and stored blocks of up to 16 KiB, which exceed the overhead of zstd
+        let compression_overhead = 18 + 5 * (payload / 16384 + 1);
 
-                if index_ts == 0 {
-                    self.out[index_ts].i32s[i] = decoded_value;
-                } else {
-                    self.out[index_ts].t = index_ts as u64;
+        MAX_HEADER_SIZE
+            + self.i32_count * MAX_CHANNEL_RANGE_SIZE
+            + self.i32_count.div_ceil(8) // bitmap of active variables
+            + 7 // padding to align the simple-8b words
+            + payload
+            + compression_overhead
+    }
 
-                    // delta decoding
-                    let max_index = usize::min(index_ts, self.delta_encoding_layers - 1) - 1;
-                    if self.use_xor {
-                        self.delta_sum[max_index][i] ^= decoded_value;
-                    } else {
-                        self.delta_sum[max_index][i] += decoded_value;
-                    }
+    /// Reads the header of a message and reports how the payload is encoded, without decoding
+    /// the samples. The encoding depends on the decoder's settings as well as the message, so
+    /// this describes how `decode_to_buffer` would interpret it. Unlike `decode_to_buffer`,
+    /// a message from another stream is not rejected, so that its ID can be inspected.
+    pub fn inspect(&self, buf: &[u8]) -> Result<MessageInfo, JetstreamError> {
+        let (flags, mut length) = uvarint32(buf)?;
+        let id_bytes = read_id(buf, length, flags)?;
+        let id = id_from_bytes(id_bytes);
+        length += id_bytes.len();
 
-                    for k in (1..=max_index).rev() {
-                        if self.use_xor {
-                            self.delta_sum[k - 1][i] ^= self.delta_sum[k][i];
-                        } else {
-                            self.delta_sum[k - 1][i] += self.delta_sum[k][i];
-                        }
-                    }
+        if flags & FLAG_HEARTBEAT != 0 {
+            return Ok(MessageInfo {
+                id,
+                heartbeat: true,
+                samples: 0,
+                sampling_rate: self.sampling_rate,
+                simple8b: false,
+                compression: Compression::None,
+                delta_encoding_layers: 0,
+                little_endian: false,
+            });
+        }
+        let descriptor = FormatDescriptor::read(buf, length)?;
+        length += 1;
 
-                    if self.use_xor {
-                        self.out[index_ts].i32s[i] =
-                            self.out[index_ts - 1].i32s[i] ^ self.delta_sum[0][i];
-                    } else {
-                        self.out[index_ts].i32s[i] =
-                            self.out[index_ts - 1].i32s[i] + self.delta_sum[0][i];
-                    }
-                }
+        // skip the timestamp
+        if flags & FLAG_EPOCH_TIMESTAMP != 0 {
+            let (_, len_b) = read_at(buf, length, uvarint64)?;
+            length += len_b;
+        } else {
+            length += 8;
+        }
 
-                decode_counter += 1;
-
-                // all variables and time-steps have been decoded
-                if decode_counter == actual_samples * self.i32_count {
-                    // take care of spatial references (cannot do this piecemeal
-                    // above because it disrupts the previous value history)
-                    for index_ts in 0..self.out.len() {
-                        for i in 0..self.out[index_ts].i32s.len() {
-                            if let Some(spatial_ref_i) = self.spatial_ref[i] {
-                                self.out[index_ts].i32s[i] +=
-                                    self.out[index_ts].i32s[spatial_ref_i];
-                            }
-                        }
-                    }
-                    return false; // stop decoding
-                }
-                return true;
-            })
-            .unwrap_or(0);
+        let (samples, len_b) = read_at(buf, length, varint32)?;
+        length += len_b;
 
-            // add length of decoded unit64 blocks (8 bytes each)
-            length += decoded_u64s * 8;
+        let sampling_rate = if flags & FLAG_SAMPLING_RATE != 0 {
+            let (val_unsigned, _) = read_at(buf, length, uvarint32)?;
+            val_unsigned as usize
         } else {
-            // get first set of samples using delta-delta encoding
-            for i in 0..self.i32_count {
-                let (val_signed, len_b) = varint32(&out_bytes[length..]);
-                self.out[0].i32s[i] = val_signed as i32;
-                length += len_b;
-            }
+            self.sampling_rate
+        };
 
-            // decode remaining delta-delta encoded values
-            if actual_samples > 1 {
-                let mut total_samples: usize = 1;
-                loop {
-                    // encode the sample number relative to the starting timestamp
-                    self.out[total_samples].t = total_samples as u64;
+        Ok(MessageInfo {
+            id,
+            heartbeat: false,
+            samples: samples as usize,
+            sampling_rate,
+            simple8b: descriptor.simple8b,
+            compression: descriptor.compression,
+            delta_encoding_layers: descriptor.delta_layers,
+            little_endian: flags & FLAG_LITTLE_ENDIAN != 0,
+        })
+    }
 
-                    // delta decoding
-                    for i in 0..self.i32_count {
-                        let (decoded_value, len_b) = varint32(&out_bytes[length..]);
-                        length += len_b;
+    /// Whether the most recently decoded message was a heartbeat, which has no samples.
+    pub fn is_heartbeat(&self) -> bool {
+        self.heartbeat
+    }
 
-                        let max_index =
-                            usize::min(total_samples, self.delta_encoding_layers - 1) - 1;
-                        if self.use_xor {
-                            self.delta_sum[max_index][i] ^= decoded_value;
-                        } else {
-                            self.delta_sum[max_index][i] += decoded_value;
-                        }
+    /// The number of samples in the most recently decoded message.
+    pub fn encoded_samples(&self) -> usize {
+        self.encoded_samples
+    }
 
-                        for k in (1..=max_index).rev() {
-                            if self.use_xor {
-                                self.delta_sum[k - 1][i] ^= self.delta_sum[k][i];
-                            } else {
-                                self.delta_sum[k - 1][i] += self.delta_sum[k][i];
-                            }
-                        }
+    /// The sampling rate of the most recently decoded message.
+    pub fn message_sampling_rate(&self) -> usize {
+        self.message_sampling_rate
+    }
 
-                        if self.use_xor {
-                            self.out[total_samples].i32s[i] =
-                                self.out[total_samples - 1].i32s[i] ^ self.delta_sum[0][i];
-                        } else {
-                            self.out[total_samples].i32s[i] =
-                                self.out[total_samples - 1].i32s[i] + self.delta_sum[0][i];
-                        }
-                    }
-                    total_samples += 1;
-
-                    if total_samples >= actual_samples {
-                        // take care of spatial references (cannot do this piecemeal
-                        // above because it disrupts the previous value history)
-                        for index_ts in 0..self.out.len() {
-                            for i in 0..self.out[index_ts].i32s.len() {
-                                // skip the first time index
-                                if let Some(spatial_ref_i) = self.spatial_ref[i] {
-                                    self.out[index_ts].i32s[i] +=
-                                        self.out[index_ts].i32s[spatial_ref_i];
-                                }
-                            }
-                        }
-                        break; // end decoding
+    /// The minimum and maximum of variable `i` in the most recently decoded message, if the
+    /// encoder stored them in the header and the variable was present.
+    pub fn channel_range(&self, i: usize) -> Option<(i32, i32)> {
+        self.channel_ranges
+            .as_ref()
+            .filter(|_| self.active_channels[i])
+            .map(|ranges| ranges[i])
+    }
+
+    /// Whether variable `i` was present in the most recently decoded message. The output for
+    /// an absent variable is not decoded, and is treated as invalid by `invalid_samples`.
+    pub fn channel_active(&self, i: usize) -> bool {
+        self.active_channels[i]
+    }
+
+    /// The positions of samples in the most recently decoded message which do not directly
+    /// follow the previous sample, if `check_contiguity` is set. A gap is reported where the
+    /// timestamp advances by more than one and a half sampling intervals, in nanoseconds. The
+    /// sample numbers reported without `infer_timestamps` or sample timestamps have no gaps.
+    pub fn timestamp_gaps(&self) -> &[usize] {
+        &self.gaps
+    }
+
+    // Checks that a quality value only sets defined bits, if enabled.
+    fn check_quality(&self, q: u32, i: usize, index_ts: usize) -> Result<(), JetstreamError> {
+        if self.quality_check == QualityCheck::Ignore
+            || (q & !QUALITY_DEFINED_BITS == 0
+                && q & QUALITY_VALIDITY_MASK != QUALITY_RESERVED_VALIDITY)
+        {
+            return Ok(());
+        }
+        if self.quality_check == QualityCheck::Reject {
+            return Err(JetstreamError::InvalidQuality(q));
+        }
+        warn!(quality = q, variable = i, sample = index_ts; "quality sets reserved bits");
+        Ok(())
+    }
+
+    // Applies the policy for invalid samples to each variable, including the output beyond the
+    // samples in the current message.
+    fn replace_invalid_samples(&mut self, actual_samples: usize) {
+        if self.invalid_samples == InvalidSamples::Keep {
+            return;
+        }
+        for i in 0..self.i32_count {
+            for index_ts in 0..self.out.len() {
+                let sample = &mut self.out[index_ts];
+                if index_ts < actual_samples
+                    && self.active_channels[i]
+                    && sample.q[i] & QUALITY_VALIDITY_MASK != QUALITY_INVALID
+                {
+                    self.last_valid[i] = sample.i32s[i];
+                } else if self.invalid_samples == InvalidSamples::Zero {
+                    sample.i32s[i] = 0;
+                } else {
+                    sample.i32s[i] = self.last_valid[i];
+                }
+            }
+        }
+    }
+
+    // Reads the first sample of each active variable and the encoded deltas from `length` in
+    // the payload into the output, to be integrated once the quality values are known.
+    // Returns the length of the payload read so far.
+    fn read_samples(
+        &mut self,
+        bytes: &[u8],
+        mut length: usize,
+        flags: u32,
+        actual_samples: usize,
+    ) -> Result<usize, JetstreamError> {
+        if self.using_simple8b {
+            // for simple-8b encoding, values are grouped by variable
+            let active_count = self.active_channels.iter().filter(|&&a| a).count();
+            let total_values = actual_samples * active_count;
+            let decode_all = if flags & FLAG_LITTLE_ENDIAN != 0 {
+                simple8b::decode_all_i32_le
+            } else {
+                simple8b::decode_all_i32
+            };
+            // the words end early if they do not hold every value, so at least one more is needed
+            let words = &bytes[length..];
+            let decoded_u64s = decode_all(&mut self.simple8b_values[..total_values], words)
+                .map_err(|_| JetstreamError::Truncated {
+                    needed: length + (words.len() / 8 + 1) * 8,
+                    got: bytes.len(),
+                })?;
+
+            let active_channels = (0..self.i32_count).filter(|&i| self.active_channels[i]);
+            for (i, values) in active_channels
+                .zip(self.simple8b_values[..total_values].chunks_exact(actual_samples))
+            {
+                for (index_ts, &value) in values.iter().enumerate() {
+                    self.out[index_ts].i32s[i] = value;
+                }
+            }
+
+            // add length of decoded unit64 blocks (8 bytes each), which locates the quality
+            // section; the encoder never pads the final word, so no values are skipped
+            length += decoded_u64s * 8;
+        } else {
+            for index_ts in 0..actual_samples {
+                for i in 0..self.i32_count {
+                    if !self.active_channels[i] {
+                        continue;
                     }
+                    let (val_signed, len_b) = read_at(bytes, length, varint32)?;
+                    self.out[index_ts].i32s[i] = val_signed;
+                    length += len_b;
                 }
             }
         }
 
+        Ok(length)
+    }
+
+    // Reads the quality values and the boolean channels from `length` in the payload into the
+    // output. Returns the length of the payload read so far.
+    fn read_quality(
+        &mut self,
+        bytes: &[u8],
+        mut length: usize,
+        flags: u32,
+        actual_samples: usize,
+    ) -> Result<usize, JetstreamError> {
         // populate quality structure
+        let quality_width = QualityWidth::from_flags(flags)?;
         for i in 0..self.i32_count {
+            if !self.active_channels[i] {
+                continue;
+            }
             let mut sample_number = 0;
             while sample_number < actual_samples {
-                let (val_unsigned, len_b) = uvarint32(&out_bytes[length..]);
+                let (val_unsigned, len_b) = read_at(bytes, length, |b| quality_width.read(b))?;
                 length += len_b;
-                self.out[sample_number].q[i] = val_unsigned as u32;
+                self.out[sample_number].q[i] = val_unsigned;
+                self.check_quality(val_unsigned, i, sample_number)?;
 
-                let (val_unsigned, len_b) = uvarint32(&out_bytes[length..]);
+                let (val_unsigned, len_b) = read_at(bytes, length, uvarint32)?;
                 length += len_b;
 
-                if val_unsigned == 0 {
+                let remaining = actual_samples - sample_number;
+                if val_unsigned as usize > remaining {
+                    return Err(JetstreamError::Corrupt(format!(
+                        "quality run of {} samples exceeds the {} remaining",
+                        val_unsigned, remaining
+                    )));
+                }
+
+                // a run of zero, or a counted run to the end of the message, continues to the
+                // end of the output so that both forms decode identically
+                if val_unsigned == 0 || val_unsigned as usize == remaining {
                     // write all remaining Q values for this variable
                     for j in sample_number + 1..self.out.len() {
                         self.out[j].q[i] = self.out[sample_number].q[i]
@@ -255,22 +759,476 @@ impl Decoder {
                     sample_number = actual_samples;
                 } else {
                     // write up to val_unsigned remaining Q values for this variable
-                    for j in (sample_number + 1)..(val_unsigned as usize) {
-                        if sample_number < self.out.len() && j < self.out.len() {
-                            self.out[j].q[i] = self.out[sample_number].q[i];
-                        }
+                    let run_end = sample_number + val_unsigned as usize;
+                    for j in (sample_number + 1)..usize::min(run_end, self.out.len()) {
+                        self.out[j].q[i] = self.out[sample_number].q[i];
                     }
-                    sample_number += val_unsigned as usize
+                    sample_number = run_end;
                 }
             }
         }
 
-        for j in 0..self.delta_sum.len() {
+        // populate the boolean channels, whose runs alternate in state
+        if (flags & FLAG_BOOLS != 0) != (self.bool_count > 0) {
+            return Err(JetstreamError::Corrupt(
+                "boolean channels do not match the stream".to_string(),
+            ));
+        }
+        for i in 0..self.bool_count {
+            let mut state = *bytes.get(length).ok_or(JetstreamError::Truncated {
+                needed: length + 1,
+                got: bytes.len(),
+            })? != 0;
+            length += 1;
+
+            let mut sample_number = 0;
+            while sample_number < actual_samples {
+                let (val_unsigned, len_b) = read_at(bytes, length, uvarint32)?;
+                length += len_b;
+
+                // a run of zero continues to the end of the output
+                let run_end = if val_unsigned == 0 {
+                    self.out.len()
+                } else {
+                    usize::min(sample_number + val_unsigned as usize, self.out.len())
+                };
+                for sample in &mut self.out[sample_number..run_end] {
+                    sample.bools[i] = state;
+                }
+                sample_number = if val_unsigned == 0 {
+                    actual_samples
+                } else {
+                    sample_number + val_unsigned as usize
+                };
+                state = !state;
+            }
+        }
+
+        Ok(length)
+    }
+
+    // Returns the timestamp of the sample at `index` within the current message. An inferred
+    // timestamp wraps past `u64::MAX`, like the offsets of encoded sample timestamps.
+    fn sample_timestamp(&self, index: usize) -> u64 {
+        if self.infer_timestamps {
+            self.start_timestamp
+                .wrapping_add((index as u64 * 1_000_000_000) / (self.message_sampling_rate as u64))
+        } else {
+            index as u64
+        }
+    }
+
+    /// Decodes a message like `decode_to_buffer`, then returns an iterator over the samples it
+    /// holds, borrowed from the decoder's output rather than copied. Only the samples in the
+    /// message are yielded, so a message which was flushed early yields fewer than
+    /// `samples_per_message`, and a heartbeat yields none. The samples of each variable are
+    /// stored together, so the whole message is decoded before the first sample is yielded.
+    pub fn decode_iter<'a>(
+        &'a mut self,
+        buf: &[u8],
+    ) -> Result<impl ExactSizeIterator<Item = &'a DatasetWithQuality>, JetstreamError> {
+        self.decode_to_buffer(buf, buf.len())?;
+        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+        Ok(self.out[..actual_samples].iter())
+    }
+
+    /// Decodes a message like `decode_to_buffer`, then returns each sample of the message with
+    /// the variables multiplied by their scale factors, in engineering units. A heartbeat has
+    /// no samples.
+    pub fn decode_to_f64(&mut self, buf: &[u8]) -> Result<&[Vec<f64>], JetstreamError> {
+        self.decode_to_buffer(buf, buf.len())?;
+
+        if self.scaled_out.is_empty() {
+            self.scaled_out = vec![vec![0.0; self.i32_count]; self.samples_per_message];
+        }
+        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+        for (scaled, sample) in self.scaled_out.iter_mut().zip(&self.out[..actual_samples]) {
+            for ((value, &raw), &scale) in
+                scaled.iter_mut().zip(&sample.i32s).zip(&self.scale_factors)
+            {
+                *value = raw as f64 * scale;
+            }
+        }
+        Ok(&self.scaled_out[..actual_samples])
+    }
+
+    /// Returns each sample of the most recently decoded message with the variables multiplied
+    /// by their scale factors, like `decode_to_f64` but without decoding another message.
+    pub fn out_scaled(&self) -> Vec<Vec<f64>> {
+        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+        self.out[..actual_samples]
+            .iter()
+            .map(|sample| {
+                sample
+                    .i32s
+                    .iter()
+                    .zip(&self.scale_factors)
+                    .map(|(&raw, &scale)| raw as f64 * scale)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Decodes to a pre-allocated buffer.
+    pub fn decode_to_buffer(
+        &mut self,
+        buf: &[u8],
+        _total_length: usize,
+    ) -> Result<(), JetstreamError> {
+        self.decode_sections(buf, false)
+    }
+
+    /// Decodes only the header, quality values and boolean channels of a message into the
+    /// output, leaving the samples untouched. The samples are not parsed if the encoder placed
+    /// the quality section first; otherwise the whole message must be decoded to find the
+    /// quality section, as with `decode_to_buffer`.
+    pub fn decode_quality(&mut self, buf: &[u8]) -> Result<(), JetstreamError> {
+        self.decode_sections(buf, true)
+    }
+
+    // Decodes a message, stopping after the quality section if `quality_only` is set and the
+    // quality section comes first.
+    fn decode_sections(&mut self, buf: &[u8], quality_only: bool) -> Result<(), JetstreamError> {
+        // decode header flags
+        let (flags, mut length) = uvarint32(buf)?;
+
+        // check ID, comparing only the bytes present in a compact ID
+        let id_bytes = read_id(buf, length, flags)?;
+        if id_bytes != &self.id.as_bytes()[..id_bytes.len()] {
+            return Err(JetstreamError::IdMismatch {
+                expected: self.id,
+                got: id_from_bytes(id_bytes),
+            });
+        }
+        length += id_bytes.len();
+
+        // invalidate any tickets for the previous output
+        #[cfg(debug_assertions)]
+        {
+            self.generation = self.generation.wrapping_add(1);
+        }
+
+        // a heartbeat has no other fields, and leaves the output untouched
+        self.heartbeat = flags & FLAG_HEARTBEAT != 0;
+        if self.heartbeat {
+            self.encoded_samples = 0;
+            self.channel_ranges = None;
+            self.gaps.clear();
+            return Ok(());
+        }
+
+        // follow the format of the samples used by the encoder, even if configured otherwise
+        let descriptor = FormatDescriptor::read(buf, length)?;
+        length += 1;
+        self.using_simple8b = descriptor.simple8b;
+        if descriptor.xor != self.use_xor {
+            warn!(use_xor = descriptor.xor; "XOR delta setting does not match the stream, using the header");
+            self.use_xor = descriptor.xor;
+        }
+
+        // decode timestamp
+        if flags & FLAG_EPOCH_TIMESTAMP != 0 {
+            let epoch = self.epoch.ok_or(JetstreamError::MissingEpoch)?;
+            let (offset, len_b) = read_at(buf, length, uvarint64)?;
+            self.start_timestamp = epoch.checked_add(offset).ok_or_else(|| {
+                JetstreamError::Corrupt("timestamp overflows the epoch".to_string())
+            })?;
+            length += len_b;
+        } else {
+            let bytes = buf
+                .get(length..length + 8)
+                .ok_or(JetstreamError::Truncated {
+                    needed: length + 8,
+                    got: buf.len(),
+                })?
+                .try_into()
+                .unwrap();
+            self.start_timestamp = if flags & FLAG_LITTLE_ENDIAN != 0 {
+                u64::from_le_bytes(bytes)
+            } else {
+                u64::from_be_bytes(bytes)
+            };
+            length += 8;
+        }
+
+        // the first timestamp is the starting value encoded in the header
+        self.out[0].t = self.start_timestamp;
+
+        // decode number of samples
+        let (val_signed, len_b) = read_at(buf, length, varint32)?;
+        length += len_b;
+        if let Some(max) = self.max_message_samples {
+            if val_signed < 0 || val_signed as usize > max {
+                return Err(JetstreamError::Corrupt(format!(
+                    "{} samples exceeds the limit of {} per message",
+                    val_signed, max
+                )));
+            }
+        }
+        self.encoded_samples = val_signed as usize;
+
+        // the simple-8b values of each variable are stored together, so cannot be truncated
+        if self.using_simple8b {
+            if self.encoded_samples > self.samples_per_message {
+                return Err(JetstreamError::Corrupt(format!(
+                    "{} samples of simple-8b words do not fit a decoder for {} samples per message",
+                    self.encoded_samples, self.samples_per_message
+                )));
+            }
+            let values = self.samples_per_message * self.i32_count;
+            if self.simple8b_values.len() < values {
+                self.simple8b_values.resize(values, 0);
+            }
+        }
+
+        // decode the sampling rate, if it differs from the stream rate
+        self.message_sampling_rate = if flags & FLAG_SAMPLING_RATE != 0 {
+            let (val_unsigned, len_b) = read_at(buf, length, uvarint32)?;
+            length += len_b;
+            if val_unsigned == 0 {
+                return Err(JetstreamError::Corrupt("zero sampling rate".to_string()));
+            }
+            val_unsigned as usize
+        } else {
+            self.sampling_rate
+        };
+
+        // decode the bitmap of active variables, if present
+        if flags & FLAG_ACTIVE_CHANNELS != 0 {
+            if buf.len() < length + self.i32_count.div_ceil(8) {
+                return Err(JetstreamError::Truncated {
+                    needed: length + self.i32_count.div_ceil(8),
+                    got: buf.len(),
+                });
+            }
             for i in 0..self.i32_count {
-                self.delta_sum[j][i] = 0
+                self.active_channels[i] = buf[length + i / 8] & (1 << (i % 8)) != 0;
+            }
+            length += self.i32_count.div_ceil(8);
+        } else {
+            self.active_channels.fill(true);
+        }
+
+        // decode the range of each active variable, if present
+        if flags & FLAG_CHANNEL_RANGES != 0 {
+            let ranges = self
+                .channel_ranges
+                .get_or_insert_with(|| vec![(0, 0); self.i32_count]);
+            for (range, _) in ranges
+                .iter_mut()
+                .zip(&self.active_channels)
+                .filter(|(_, &active)| active)
+            {
+                let (min, len_b) = read_at(buf, length, varint32)?;
+                length += len_b;
+                let (max, len_b) = read_at(buf, length, varint32)?;
+                length += len_b;
+                *range = (min, max);
+            }
+        } else {
+            self.channel_ranges = None;
+        }
+
+        let delta_encoding_layers = descriptor.delta_layers;
+        if delta_encoding_layers != self.delta_encoding_layers {
+            self.delta_encoding_layers = delta_encoding_layers;
+            self.delta_sum = delta_sum_storage(
+                self.i32_count,
+                self.samples_per_message,
+                delta_encoding_layers,
+            );
+        }
+
+        let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
+
+        // a message without samples has no payload to decode
+        if actual_samples == 0 {
+            return Ok(());
+        }
+
+        // skip any padding which aligns the simple-8b words
+        if flags & FLAG_ALIGNED_WORDS != 0 {
+            length = length.next_multiple_of(8);
+        }
+        if buf.len() < length {
+            return Err(JetstreamError::Truncated {
+                needed: length,
+                got: buf.len(),
+            });
+        }
+
+        // a compressed payload is read from the start of the decompressed bytes, and otherwise
+        // the payload is read in place
+        let out_bytes: Cow<[u8]> = if descriptor.compression != Compression::None {
+            let gz_buf = decompress(buf, length, descriptor.compression)?;
+            length = 0;
+            Cow::Owned(gz_buf)
+        } else {
+            Cow::Borrowed(buf)
+        };
+
+        // the quality section comes first if it was placed there, so that it can be read alone
+        if flags & FLAG_QUALITY_FIRST != 0 {
+            length = self.read_quality(&out_bytes, length, flags, actual_samples)?;
+            if quality_only {
+                return Ok(());
+            }
+            length = self.read_samples(&out_bytes, length, flags, actual_samples)?;
+        } else {
+            length = self.read_samples(&out_bytes, length, flags, actual_samples)?;
+            length = self.read_quality(&out_bytes, length, flags, actual_samples)?;
+        }
+
+        // delta decoding
+        let quality_reset = flags & FLAG_QUALITY_RESET != 0;
+        for i in 0..self.i32_count {
+            if !self.active_channels[i] {
+                continue;
+            }
+
+            // the sample which restarted delta encoding for this variable
+            let mut segment_start = 0;
+
+            for index_ts in 0..actual_samples {
+                if quality_reset
+                    && index_ts > 0
+                    && self.out[index_ts].q[i] != self.out[index_ts - 1].q[i]
+                {
+                    segment_start = index_ts;
+                }
+                let j = index_ts - segment_start;
+
+                if j == 0 {
+                    // the value is stored directly, so restart the delta history
+                    for k in 0..self.delta_sum.len() {
+                        self.delta_sum[k][i] = 0;
+                    }
+                    continue;
+                }
+
+                // with a single layer, the value is the difference from the previous sample
+                let decoded_value = self.out[index_ts].i32s[i];
+                let delta = if let Some(max_index) = delta_sum_index(j, self.delta_encoding_layers)
+                {
+                    if self.use_xor {
+                        self.delta_sum[max_index][i] ^= decoded_value;
+                    } else {
+                        self.delta_sum[max_index][i] =
+                            self.delta_sum[max_index][i].wrapping_add(decoded_value);
+                    }
+
+                    for k in (1..=max_index).rev() {
+                        if self.use_xor {
+                            self.delta_sum[k - 1][i] ^= self.delta_sum[k][i];
+                        } else {
+                            self.delta_sum[k - 1][i] =
+                                self.delta_sum[k - 1][i].wrapping_add(self.delta_sum[k][i]);
+                        }
+                    }
+                    self.delta_sum[0][i]
+                } else {
+                    decoded_value
+                };
+
+                if self.use_xor {
+                    self.out[index_ts].i32s[i] = self.out[index_ts - 1].i32s[i] ^ delta;
+                } else {
+                    self.out[index_ts].i32s[i] = self.out[index_ts - 1].i32s[i].wrapping_add(delta);
+                }
+            }
+        }
+
+        // encode the sample number relative to the starting timestamp
+        for index_ts in 1..actual_samples {
+            self.out[index_ts].t = self.sample_timestamp(index_ts);
+        }
+
+        // take care of spatial references (cannot do this piecemeal
+        // above because it disrupts the previous value history)
+        for index_ts in 0..actual_samples {
+            for i in 0..self.out[index_ts].i32s.len() {
+                if !self.active_channels[i] {
+                    continue;
+                }
+                let active_channels = &self.active_channels;
+                if let Some(spatial_ref_i) = self.spatial_ref[i].filter(|&r| active_channels[r]) {
+                    self.out[index_ts].i32s[i] = self.out[index_ts].i32s[i]
+                        .wrapping_add(self.out[index_ts].i32s[spatial_ref_i]);
+                }
+            }
+        }
+
+        // reject values outside the plausible range of each variable
+        for i in 0..self.i32_count {
+            let (min, max) = match self.plausible_ranges[i] {
+                Some(range) if self.active_channels[i] => range,
+                _ => continue,
+            };
+            for (index_ts, sample) in self.out[..actual_samples].iter().enumerate() {
+                let value = sample.i32s[i];
+                if value < min || value > max {
+                    return Err(JetstreamError::Implausible {
+                        variable: i,
+                        sample: index_ts,
+                        value,
+                    });
+                }
+            }
+        }
+
+        self.replace_invalid_samples(actual_samples);
+
+        // convert the float channels, which follow the integer variables
+        if !self.float_scales.is_empty() {
+            let first_float = self.i32_count - self.float_scales.len();
+            for sample in self.out.iter_mut() {
+                let values = sample.i32s[first_float..].iter().zip(&self.float_scales);
+                for (f, (&value, &scale)) in sample.f32s.iter_mut().zip(values) {
+                    *f = (value as f64 * scale) as f32;
+                }
+            }
+        }
+
+        // decode every sample timestamp, if present
+        if flags & FLAG_SAMPLE_TIMESTAMPS != 0 {
+            for k in 1..actual_samples {
+                let (offset, len_b) = read_at(&out_bytes, length, uvarint64)?;
+                length += len_b;
+                self.out[k].t = self.out[k - 1].t.wrapping_add(offset);
+            }
+        }
+
+        // find any gaps in the timestamps
+        self.gaps.clear();
+        let nanoseconds = self.infer_timestamps || flags & FLAG_SAMPLE_TIMESTAMPS != 0;
+        if self.check_contiguity && nanoseconds {
+            let interval = 1_000_000_000 / self.message_sampling_rate as u64;
+            for k in 1..actual_samples {
+                let step = self.out[k].t.wrapping_sub(self.out[k - 1].t);
+                if step > interval + interval / 2 {
+                    self.gaps.push(k);
+                }
             }
         }
 
         Ok(())
     }
 }
+
+/// Decodes a single message with a new decoder, returning the samples it holds. This is a
+/// shortcut for decoding a captured message without keeping a `Decoder`; the arguments must
+/// match the encoder, and a heartbeat returns no samples.
+pub fn decode_message(
+    id: Uuid,
+    i32_count: usize,
+    sampling_rate: usize,
+    samples_per_message: usize,
+    buf: &[u8],
+) -> Result<Vec<DatasetWithQuality>, JetstreamError> {
+    let mut dec = Decoder::new(id, i32_count, sampling_rate, samples_per_message);
+    dec.decode_to_buffer(buf, buf.len())?;
+
+    let mut out = dec.out;
+    out.truncate(usize::min(dec.encoded_samples, samples_per_message));
+    Ok(out)
+}
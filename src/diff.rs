@@ -0,0 +1,43 @@
+use crate::jetstream::DatasetWithQuality;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A variable which differs between two lists of samples, as found by `diff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The position of the sample in both lists.
+    pub sample: usize,
+    /// The index of the variable within the sample.
+    pub channel: usize,
+    pub a_val: i32,
+    pub b_val: i32,
+    pub a_quality: u32,
+    pub b_quality: u32,
+}
+
+/// Compares two lists of samples, such as the input to an encoder and the decoded output, and
+/// returns every variable whose value or quality differs, in order of sample then variable.
+/// Only the samples and variables present in both lists are compared, and timestamps are
+/// ignored, so that the output of a decoder can be compared without inferring timestamps.
+pub fn diff(a: &[DatasetWithQuality], b: &[DatasetWithQuality]) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    for (sample, (a, b)) in a.iter().zip(b).enumerate() {
+        let a_vars = a.i32s.iter().zip(&a.q);
+        let b_vars = b.i32s.iter().zip(&b.q);
+        for (channel, ((&a_val, &a_quality), (&b_val, &b_quality))) in
+            a_vars.zip(b_vars).enumerate()
+        {
+            if a_val != b_val || a_quality != b_quality {
+                mismatches.push(Mismatch {
+                    sample,
+                    channel,
+                    a_val,
+                    b_val,
+                    a_quality,
+                    b_quality,
+                });
+            }
+        }
+    }
+    mismatches
+}
@@ -1,8 +1,14 @@
-use rand::{thread_rng, Rng};
+use crate::error::JetstreamError;
+use crate::jetstream::{Channel, DatasetWithQuality};
+use log::{debug, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rand_distr::StandardNormal;
 use std::f64::consts::PI;
+use std::fmt;
 
 /// Emulated event types
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EventType {
     SinglePhaseFault,
     ThreePhaseFault,
@@ -11,20 +17,109 @@ pub enum EventType {
     OverFrequency,
     UnderFrequency,
     CapacitorOverCurrent,
+    /// A sudden shift of the phase angle of the voltage, and of the current if emulated, by
+    /// the given number of degrees, as at islanding or reclosing. The waveform is
+    /// discontinuous at the next sample.
+    PhaseJump(f64),
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EventType::PhaseJump(degrees) => return write!(f, "phase jump of {} degrees", degrees),
+            EventType::SinglePhaseFault => "single-phase fault",
+            EventType::ThreePhaseFault => "three-phase fault",
+            EventType::OverVoltage => "over-voltage",
+            EventType::UnderVoltage => "under-voltage",
+            EventType::OverFrequency => "over-frequency",
+            EventType::UnderFrequency => "under-frequency",
+            EventType::CapacitorOverCurrent => "capacitor over-current",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 // The number of samples for emulating a fault.
 const MAX_EMULATED_FAULT_DURATION_SAMPLES: usize = 6000;
 
-// The number of samples for emulating a fault.
+// The number of samples for emulating a capacitor bank over-current.
 const MAX_EMULATED_CAPACITOR_OVER_CURRENT_SAMPLES: usize = 8000;
 
+// The initial over-current when a capacitor bank is energised, per unit, which decays to
+// nothing over the duration of the event.
+const EMULATED_CAPACITOR_OVER_CURRENT_MAGNITUDE: f64 = 0.5;
+
 // The number of samples for emulating frequency deviations.
 const MAX_EMULATED_FREQUENCY_DURATION_SAMPLES: usize = 8000;
 
 const TWO_PI_OVER_THREE: f64 = 2.0 * PI / 3.0;
 
-#[derive(Default)]
+// The growth rate of an exponential trend anomaly, over the anomaly duration.
+const EXPONENTIAL_TREND_RATE: f64 = 5.0;
+
+/// The reference for the angle of each harmonic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HarmonicReference {
+    /// The angle is relative to the fundamental, so the harmonic of order `n` moves by `n`
+    /// times any shift of the fundamental, including `phase_offset`.
+    #[default]
+    Fundamental,
+    /// The angle is relative to a fixed time reference, independent of `phase_offset`.
+    Absolute,
+}
+
+/// The resolution of an emulated analogue to digital converter, which rounds each output to a
+/// multiple of its least significant bit, saturating at the limits of its range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdcQuantization {
+    /// The number of bits of each sample, from 1 to 32.
+    pub bits: u32,
+    /// The largest magnitude which can be represented. The range from `-full_scale` to
+    /// `full_scale` is divided into `2^bits` steps.
+    pub full_scale: f64,
+}
+
+impl AdcQuantization {
+    /// The size of each step of the output.
+    pub fn lsb(&self) -> f64 {
+        2.0 * self.full_scale / f64::powi(2.0, self.bits as i32)
+    }
+
+    /// Returns `value` rounded to the nearest step, limited to the range of the two's
+    /// complement code of `bits` bits.
+    pub fn quantize(&self, value: f64) -> f64 {
+        let max_code = f64::powi(2.0, self.bits as i32 - 1);
+        let code = f64::round(value / self.lsb()).clamp(-max_code, max_code - 1.0);
+        code * self.lsb()
+    }
+}
+
+/// The profile of a temperature trend anomaly over its duration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrendShape {
+    /// The anomaly grows at a constant rate, reaching the magnitude at the end.
+    #[default]
+    Linear,
+    /// The anomaly grows slowly at first and then ever faster, reaching the magnitude at the
+    /// end, as in thermal runaway.
+    Exponential,
+    /// The full magnitude applies for the whole duration.
+    Step,
+}
+
+impl TrendShape {
+    // The fraction of the magnitude applied after `progress` through the anomaly, from 0 to 1.
+    fn fraction(self, progress: f64) -> f64 {
+        match self {
+            TrendShape::Linear => progress,
+            TrendShape::Exponential => {
+                f64::exp_m1(EXPONENTIAL_TREND_RATE * progress) / f64::exp_m1(EXPONENTIAL_TREND_RATE)
+            }
+            TrendShape::Step => 1.0,
+        }
+    }
+}
+
 pub struct ThreePhaseEmulation {
     // inputs
     pub pos_seq_mag: f64,
@@ -33,14 +128,32 @@ pub struct ThreePhaseEmulation {
     pub neg_seq_ang: f64,
     pub zero_seq_mag: f64,
     pub zero_seq_ang: f64,
-    pub harmonic_numbers: Vec<f64>,
+    /// The order, magnitude and angle of each harmonic, which must have the same length, as
+    /// checked by `validate`. Unlike `set_harmonics`, the angles are in radians.
+    pub harmonic_numbers: Vec<f64>, // order 0 is a DC offset, see `set_harmonics`
     pub harmonic_mags: Vec<f64>, // pu, relative to pos_seq_mag
-    pub harmonic_angs: Vec<f64>,
+    pub harmonic_angs: Vec<f64>, // radians
+    pub harmonic_reference: HarmonicReference,
     pub noise_max: f64,
+    /// Per-phase magnitude scaling for phases A, B and C, applied before noise.
+    pub phase_mag_scale: [f64; 3],
+    /// Phase angles of each phase's own waveform, in radians, at which a commutation notch
+    /// starts in every cycle.
+    pub notch_angles: Vec<f64>,
+    pub notch_depth: f64,   // pu, relative to the instantaneous value
+    pub notch_width: usize, // samples
+    /// Quantizes the output of each phase, including noise, as sampled by an ADC.
+    pub adc: Option<AdcQuantization>,
+    /// A sinusoidal modulation of the magnitude of the positive sequence, as from
+    /// sub-synchronous resonance or inter-area oscillations, such as at 0.5 to 5 Hz. Unlike a
+    /// harmonic, it appears in the envelope of the fundamental.
+    pub modulation_mag: f64, // pu, relative to pos_seq_mag
+    pub modulation_frequency: f64, // Hz
 
     // event emulation
-    pub fault_phase_a_mag: f64,
+    pub fault_phase_a_mag: f64, // added to phase A only
     pub fault_pos_seq_mag: f64,
+    pub fault_pos_seq_mag_ramp_rate: f64, // added to fault_pos_seq_mag every sample
     pub fault_remaining_samples: usize,
 
     // state change
@@ -48,7 +161,9 @@ pub struct ThreePhaseEmulation {
     pub pos_seq_mag_ramp_rate: f64,
 
     // internal state
-    pub p_angle: f64, // todo: private
+    pub p_angle: f64,                // todo: private
+    pub notch_remaining: [usize; 3], // todo: private
+    pub modulation_angle: f64,       // todo: private
 
     // outputs
     pub a: f64,
@@ -56,6 +171,47 @@ pub struct ThreePhaseEmulation {
     pub c: f64,
 }
 
+impl Default for ThreePhaseEmulation {
+    fn default() -> Self {
+        Self {
+            pos_seq_mag: 0.0,
+            phase_offset: 0.0,
+            neg_seq_mag: 0.0,
+            neg_seq_ang: 0.0,
+            zero_seq_mag: 0.0,
+            zero_seq_ang: 0.0,
+            harmonic_numbers: vec![],
+            harmonic_mags: vec![],
+            harmonic_angs: vec![],
+            harmonic_reference: HarmonicReference::Fundamental,
+            noise_max: 0.0,
+            phase_mag_scale: [1.0; 3],
+            notch_angles: vec![],
+            notch_depth: 0.0,
+            notch_width: 0,
+            adc: None,
+            modulation_mag: 0.0,
+            modulation_frequency: 0.0,
+
+            fault_phase_a_mag: 0.0,
+            fault_pos_seq_mag: 0.0,
+            fault_pos_seq_mag_ramp_rate: 0.0,
+            fault_remaining_samples: 0,
+
+            pos_seq_mag_new: 0.0,
+            pos_seq_mag_ramp_rate: 0.0,
+
+            p_angle: 0.0,
+            notch_remaining: [0; 3],
+            modulation_angle: 0.0,
+
+            a: 0.0,
+            b: 0.0,
+            c: 0.0,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct TemperatureEmulation {
     pub mean_temperature: f64,
@@ -72,12 +228,45 @@ pub struct TemperatureEmulation {
     pub trend_anomaly_duration: usize, // duration in seconds
     pub trend_anomaly_index: usize,
     pub trend_anomaly_magnitude: f64,
+    pub trend_shape: TrendShape,
 
     pub is_rising_trend_anomaly: bool,
 
     pub t: f64,
 }
 
+/// Emulates a DC quantity, such as a battery bank or DC auxiliary supply voltage.
+#[derive(Default)]
+pub struct DcEmulation {
+    pub nominal: f64,
+    pub drift_mag: f64, // pu, relative to nominal
+    pub drift_frequency: f64,
+    pub ripple_mag: f64, // pu, relative to nominal
+    pub ripple_frequency: f64,
+    pub noise_max: f64,
+
+    // internal state
+    pub elapsed: f64, // seconds
+
+    // outputs
+    pub value: f64,
+}
+
+/// Emulates the frequency reported by a device, which follows the true frequency through a
+/// first-order lag, as from the dynamics of a PLL, with measurement noise. To encode it, scale
+/// the value to an integer, such as in mHz.
+#[derive(Default)]
+pub struct FrequencyEmulation {
+    pub time_constant: f64, // seconds, or zero to track the true frequency immediately
+    pub noise_max: f64,     // Hz
+
+    // internal state, starting at the true frequency
+    pub filtered: Option<f64>,
+
+    // outputs
+    pub value: f64, // Hz
+}
+
 #[derive(Default)]
 pub struct SagEmulation {
     pub mean_strain: f64,
@@ -90,6 +279,27 @@ pub struct SagEmulation {
     pub calculated_temperature: f64,
 }
 
+/// Derived power quantities, computed when both voltage and current are emulated.
+#[derive(Default)]
+pub struct PowerEmulation {
+    // outputs
+    pub a: f64, // instantaneous power per phase, v * i
+    pub b: f64,
+    pub c: f64,
+    pub total: f64, // total instantaneous three-phase power
+
+    // updated at the end of each nominal cycle
+    pub real_power: f64,
+    pub apparent_power: f64,
+    pub power_factor: f64,
+
+    // internal state
+    sum_p: f64,
+    sum_v2: [f64; 3],
+    sum_i2: [f64; 3],
+    samples: usize,
+}
+
 /// Encapsulates the waveform emulation of three-phase voltage, three-phase current, or temperature.
 pub struct Emulator {
     // common inputs
@@ -103,10 +313,17 @@ pub struct Emulator {
 
     pub t: Option<TemperatureEmulation>,
     pub sag: Option<SagEmulation>,
+    pub dc: Option<DcEmulation>,
+    pub freq: Option<FrequencyEmulation>,
+    pub power: Option<PowerEmulation>,
 
     // common state
     pub smp_cnt: usize,
     deviation_remaining_samples: usize,
+    // the emulations have been validated, on the first step
+    validated: bool,
+    // the source of noise and random anomalies
+    rng: StdRng,
 }
 
 fn wrap_angle(a: f64) -> f64 {
@@ -117,43 +334,64 @@ fn wrap_angle(a: f64) -> f64 {
     }
 }
 
+// Wraps an angle into the range [0, 2 pi).
+fn wrap_angle_positive(a: f64) -> f64 {
+    a.rem_euclid(2.0 * PI)
+}
+
 impl Emulator {
-    /// Initiates an emulated event.
+    /// Initiates an emulated event, replacing any event in progress on the same quantities.
     pub fn start_event(&mut self, event_type: EventType) {
-        // println!("StartEvent(): {}", event_type);
+        debug!("starting emulated {}", event_type);
 
         match event_type {
             EventType::SinglePhaseFault => {
                 let i = self.i.as_mut().unwrap();
                 let v = self.v.as_mut().unwrap();
-                // TODO
-                // i.fault_pos_seq_mag = EMULATED_FAULT_CURRENT_MAGNITUDE
-                // i.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES
-                i.fault_phase_a_mag = i.pos_seq_mag * 1.2; //EMULATED_FAULT_CURRENT_MAGNITUDE
-                i.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
-                v.fault_phase_a_mag = v.pos_seq_mag * -0.2;
-                v.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
+
+                i.set_fault(
+                    i.pos_seq_mag * 1.2,
+                    0.0,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
+                v.set_fault(
+                    v.pos_seq_mag * -0.2,
+                    0.0,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
             }
             EventType::ThreePhaseFault => {
                 let i = self.i.as_mut().unwrap();
                 let v = self.v.as_mut().unwrap();
 
-                i.fault_pos_seq_mag = i.pos_seq_mag * 1.2; //EMULATED_FAULT_CURRENT_MAGNITUDE
-                i.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
-                v.fault_pos_seq_mag = v.pos_seq_mag * -0.2;
-                v.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
+                i.set_fault(
+                    0.0,
+                    i.pos_seq_mag * 1.2,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
+                v.set_fault(
+                    0.0,
+                    v.pos_seq_mag * -0.2,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
             }
             EventType::OverVoltage => {
                 let v = self.v.as_mut().unwrap();
 
-                v.fault_pos_seq_mag = v.pos_seq_mag * 0.2;
-                v.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
+                v.set_fault(
+                    0.0,
+                    v.pos_seq_mag * 0.2,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
             }
             EventType::UnderVoltage => {
                 let v = self.v.as_mut().unwrap();
 
-                v.fault_pos_seq_mag = v.pos_seq_mag * -0.2;
-                v.fault_remaining_samples = MAX_EMULATED_FAULT_DURATION_SAMPLES;
+                v.set_fault(
+                    0.0,
+                    v.pos_seq_mag * -0.2,
+                    MAX_EMULATED_FAULT_DURATION_SAMPLES,
+                );
             }
             EventType::OverFrequency => {
                 self.deviation = 0.1;
@@ -164,15 +402,35 @@ impl Emulator {
                 self.deviation_remaining_samples = MAX_EMULATED_FREQUENCY_DURATION_SAMPLES;
             }
             EventType::CapacitorOverCurrent => {
-                // todo
+                // the inrush current decays linearly to nothing by the end of the event
                 let i = self.i.as_mut().unwrap();
-                i.fault_pos_seq_mag = i.pos_seq_mag * 0.01;
-                i.fault_remaining_samples = MAX_EMULATED_CAPACITOR_OVER_CURRENT_SAMPLES;
+                let mag = i.pos_seq_mag * EMULATED_CAPACITOR_OVER_CURRENT_MAGNITUDE;
+                i.set_fault(0.0, mag, MAX_EMULATED_CAPACITOR_OVER_CURRENT_SAMPLES);
+                i.fault_pos_seq_mag_ramp_rate =
+                    -mag / MAX_EMULATED_CAPACITOR_OVER_CURRENT_SAMPLES as f64;
+            }
+            EventType::PhaseJump(degrees) => {
+                self.v.as_mut().unwrap().shift_phase(degrees.to_radians());
+                if let Some(i) = self.i.as_mut() {
+                    i.shift_phase(degrees.to_radians());
+                }
             }
         }
     }
 
+    /// Creates an emulator with a randomly seeded source of noise and anomalies, so each
+    /// emulator produces different waveforms.
     pub fn new(sampling_rate: usize, frequency: f64) -> Self {
+        Self::with_rng(sampling_rate, frequency, StdRng::from_entropy())
+    }
+
+    /// Creates an emulator whose noise and anomalies are generated from `seed`, so emulators
+    /// with the same seed and settings produce identical waveforms.
+    pub fn new_seeded(sampling_rate: usize, frequency: f64, seed: u64) -> Self {
+        Self::with_rng(sampling_rate, frequency, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(sampling_rate: usize, frequency: f64, rng: StdRng) -> Self {
         Emulator {
             sampling_rate,
             nom: frequency,
@@ -183,13 +441,66 @@ impl Emulator {
             i: None,
             t: None,
             sag: None,
+            dc: None,
+            freq: None,
+            power: None,
             smp_cnt: 0,
             deviation_remaining_samples: 0,
+            validated: false,
+            rng,
+        }
+    }
+
+    /// Returns the current currents and voltages as an IEC 61850-9-2 LE dataset: phase A, B, C
+    /// and neutral currents, followed by phase A, B, C and neutral voltages. The neutral is the
+    /// sum of the phase values. Currents are scaled by 1000 (mA) and voltages by 100 (10 mV),
+    /// following the 9-2 LE conventions. Quality is good and the timestamp is left as zero.
+    ///
+    /// Panics if either the current or voltage emulation is not configured.
+    pub fn dataset_9_2_le(&self) -> DatasetWithQuality {
+        let i = self.i.as_ref().unwrap();
+        let v = self.v.as_ref().unwrap();
+
+        let mut d = DatasetWithQuality::new(Channel::COUNT);
+        d.i32s[Channel::PhaseAI.index()] = (i.a * 1000.0) as i32;
+        d.i32s[Channel::PhaseBI.index()] = (i.b * 1000.0) as i32;
+        d.i32s[Channel::PhaseCI.index()] = (i.c * 1000.0) as i32;
+        d.i32s[Channel::NeutralI.index()] = ((i.a + i.b + i.c) * 1000.0) as i32;
+        d.i32s[Channel::PhaseAV.index()] = (v.a * 100.0) as i32;
+        d.i32s[Channel::PhaseBV.index()] = (v.b * 100.0) as i32;
+        d.i32s[Channel::PhaseCV.index()] = (v.c * 100.0) as i32;
+        d.i32s[Channel::NeutralV.index()] = ((v.a + v.b + v.c) * 100.0) as i32;
+        d
+    }
+
+    /// Steps the emulator `samples` times without recording the output, allowing ramps and
+    /// other start-up transients to settle before data is collected.
+    pub fn warm_up(&mut self, samples: usize) {
+        for _ in 0..samples {
+            self.step();
         }
     }
 
+    /// Checks that the settings of each three-phase emulation are consistent.
+    pub fn validate(&self) -> Result<(), JetstreamError> {
+        for emulation in [&self.v, &self.i].into_iter().flatten() {
+            emulation.validate()?;
+        }
+        Ok(())
+    }
+
     /// Performs one iteration of the waveform generation.
+    ///
+    /// The first step logs a warning if `validate` fails, as inconsistent settings are
+    /// otherwise ignored.
     pub fn step(&mut self) {
+        if !self.validated {
+            if let Err(err) = self.validate() {
+                warn!(err:err = err; "invalid emulation settings");
+            }
+            self.validated = true;
+        }
+
         let f = self.nom + self.deviation;
 
         if self.deviation_remaining_samples > 0 {
@@ -200,16 +511,28 @@ impl Emulator {
         }
 
         if let Some(v) = self.v.as_mut() {
-            v.step_three_phase(/*&mut self.r,*/ f, self.ts, self.smp_cnt);
+            v.step_three_phase(&mut self.rng, f, self.ts, self.smp_cnt);
         }
         if let Some(i) = self.i.as_mut() {
-            i.step_three_phase(/*&mut self.r,*/ f, self.ts, self.smp_cnt);
+            i.step_three_phase(&mut self.rng, f, self.ts, self.smp_cnt);
+        }
+        if let (Some(power), Some(v), Some(i)) =
+            (self.power.as_mut(), self.v.as_ref(), self.i.as_ref())
+        {
+            let samples_per_cycle = f64::round(self.sampling_rate as f64 / self.nom) as usize;
+            power.step_power(v, i, samples_per_cycle);
         }
         if let Some(t) = self.t.as_mut() {
-            t.step_temperature(/*&mut self.r,*/ self.ts);
+            t.step_temperature(&mut self.rng, self.ts);
         }
         if let Some(sag) = self.sag.as_mut() {
-            sag.step_sag(/*&mut self.r*/);
+            sag.step_sag(&mut self.rng);
+        }
+        if let Some(dc) = self.dc.as_mut() {
+            dc.step_dc(&mut self.rng, self.ts);
+        }
+        if let Some(freq) = self.freq.as_mut() {
+            freq.step_frequency(&mut self.rng, f, self.ts);
         }
 
         self.smp_cnt += 1;
@@ -220,19 +543,20 @@ impl Emulator {
 }
 
 impl TemperatureEmulation {
-    fn step_temperature(&mut self, ts: f64) {
+    fn step_temperature(&mut self, rng: &mut impl Rng, ts: f64) {
         let varying_t = self.mean_temperature * (1.0 + self.modulation_mag * f64::cos(1000.0 * ts));
 
         let mut trend_anomaly_delta = 0.0;
-        let trend_anomaly_step =
-            (self.trend_anomaly_magnitude / (self.trend_anomaly_duration as f64)) * ts;
+        let trend_anomaly_progress =
+            (self.trend_anomaly_index as f64) * ts / (self.trend_anomaly_duration as f64);
+        let trend_anomaly_magnitude =
+            self.trend_anomaly_magnitude * self.trend_shape.fraction(trend_anomaly_progress);
 
         if self.is_trend_anomaly == true {
             if self.is_rising_trend_anomaly == true {
-                trend_anomaly_delta = (self.trend_anomaly_index as f64) * trend_anomaly_step;
+                trend_anomaly_delta = trend_anomaly_magnitude;
             } else {
-                trend_anomaly_delta =
-                    (self.trend_anomaly_index as f64) * trend_anomaly_step * (-1.0)
+                trend_anomaly_delta = trend_anomaly_magnitude * (-1.0)
             }
 
             if self.trend_anomaly_index == ((self.trend_anomaly_duration as f64) / ts) as usize - 1
@@ -244,7 +568,7 @@ impl TemperatureEmulation {
         }
 
         let instantaneous_anomaly_delta =
-            if self.instantaneous_anomaly_probability > thread_rng().gen::<f64>() {
+            if self.instantaneous_anomaly_probability > rng.gen::<f64>() {
                 self.is_instantaneous_anomaly = true;
                 self.instantaneous_anomaly_magnitude
             } else {
@@ -255,7 +579,7 @@ impl TemperatureEmulation {
         let total_anomaly_delta = trend_anomaly_delta + instantaneous_anomaly_delta;
 
         self.t = varying_t
-            + thread_rng().sample::<f64, StandardNormal>(StandardNormal)
+            + rng.sample::<f64, StandardNormal>(StandardNormal)
                 * self.noise_max
                 * self.mean_temperature
             + total_anomaly_delta;
@@ -263,7 +587,59 @@ impl TemperatureEmulation {
 }
 
 impl ThreePhaseEmulation {
-    fn step_three_phase(&mut self, f: f64, ts: f64, _smp_cnt: usize) {
+    /// Checks that there is an order, magnitude and angle for each harmonic, as otherwise every
+    /// harmonic is ignored, and that the modulation does not invert the fundamental.
+    pub fn validate(&self) -> Result<(), JetstreamError> {
+        if !(0.0..=1.0).contains(&self.modulation_mag) || self.modulation_frequency < 0.0 {
+            return Err(JetstreamError::InvalidInput(format!(
+                "modulation of {} pu at {} Hz is not between 0 and 1 pu at a positive frequency",
+                self.modulation_mag, self.modulation_frequency
+            )));
+        }
+
+        let count = self.harmonic_numbers.len();
+        if self.harmonic_mags.len() != count || self.harmonic_angs.len() != count {
+            return Err(JetstreamError::InvalidInput(format!(
+                "{} harmonic orders do not match {} magnitudes and {} angles",
+                count,
+                self.harmonic_mags.len(),
+                self.harmonic_angs.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sets the harmonics from a spectrum of `(order, magnitude, angle)`, replacing any
+    /// existing harmonics. The magnitude is per unit, relative to `pos_seq_mag`, and the angle
+    /// is in degrees, as usually quoted for a harmonic spectrum. Angles are stored in radians.
+    ///
+    /// Order 1 adds to the fundamental in each phase. Order 0 is a DC offset of the given
+    /// magnitude, equal in every phase, and its angle is ignored.
+    pub fn set_harmonics(&mut self, spectrum: &[(f64, f64, f64)]) {
+        self.harmonic_numbers = spectrum.iter().map(|&(n, _, _)| n).collect();
+        self.harmonic_mags = spectrum.iter().map(|&(_, mag, _)| mag).collect();
+        self.harmonic_angs = spectrum
+            .iter()
+            .map(|&(_, _, ang)| ang.to_radians())
+            .collect();
+    }
+
+    // Shifts the angle of every phase by `shift` radians, from the next sample.
+    fn shift_phase(&mut self, shift: f64) {
+        self.p_angle = (self.p_angle + shift + PI).rem_euclid(2.0 * PI) - PI;
+    }
+
+    // Starts an event adding `phase_a_mag` to phase A and `pos_seq_mag` to every phase for
+    // `samples` samples, replacing any event in progress.
+    fn set_fault(&mut self, phase_a_mag: f64, pos_seq_mag: f64, samples: usize) {
+        self.fault_phase_a_mag = phase_a_mag;
+        self.fault_pos_seq_mag = pos_seq_mag;
+        self.fault_pos_seq_mag_ramp_rate = 0.0;
+        self.fault_remaining_samples = samples;
+    }
+
+    fn step_three_phase(&mut self, rng: &mut impl Rng, f: f64, ts: f64, _smp_cnt: usize) {
+        let prev_pos_seq_phase = self.phase_offset + self.p_angle;
         let angle = f * 2.0 * PI * ts + self.p_angle;
         let angle = wrap_angle(angle);
         self.p_angle = angle;
@@ -275,17 +651,25 @@ impl ThreePhaseEmulation {
             self.pos_seq_mag = self.pos_seq_mag + self.pos_seq_mag_ramp_rate
         }
 
-        let mut pos_seq_mag = self.pos_seq_mag;
-        // phaseAMag := self.pos_seq_mag
+        // any sub-synchronous modulation of the fundamental
+        self.modulation_angle =
+            wrap_angle_positive(self.modulation_angle + 2.0 * PI * self.modulation_frequency * ts);
+        let modulated_mag =
+            self.pos_seq_mag * (1.0 + self.modulation_mag * f64::sin(self.modulation_angle));
+
+        let mut pos_seq_mag = modulated_mag;
+        let mut phase_a_mag = modulated_mag;
         if
         /*smpCnt > EMULATED_FAULT_START_SAMPLES && */
         self.fault_remaining_samples > 0 {
             pos_seq_mag = pos_seq_mag + self.fault_pos_seq_mag;
+            phase_a_mag = pos_seq_mag + self.fault_phase_a_mag;
+            self.fault_pos_seq_mag += self.fault_pos_seq_mag_ramp_rate;
             self.fault_remaining_samples -= 1;
         }
 
-        // positive sequence
-        let a1 = f64::sin(pos_seq_phase) * pos_seq_mag;
+        // positive sequence, with any fault of phase A alone
+        let a1 = f64::sin(pos_seq_phase) * phase_a_mag;
         let b1 = f64::sin(pos_seq_phase - TWO_PI_OVER_THREE) * pos_seq_mag;
         let c1 = f64::sin(pos_seq_phase + TWO_PI_OVER_THREE) * pos_seq_mag;
 
@@ -305,6 +689,10 @@ impl ThreePhaseEmulation {
         let mut ah = 0.0;
         let mut bh = 0.0;
         let mut ch = 0.0;
+        let harmonic_phase = match self.harmonic_reference {
+            HarmonicReference::Fundamental => pos_seq_phase,
+            HarmonicReference::Absolute => self.p_angle,
+        };
         if self.harmonic_numbers.len() > 0 {
             // ensure consistent array sizes have been specified
             if self.harmonic_numbers.len() == self.harmonic_mags.len()
@@ -312,38 +700,139 @@ impl ThreePhaseEmulation {
             {
                 self.harmonic_numbers.iter().enumerate().for_each(|(i, n)| {
                     let mag = self.harmonic_mags[i] * self.pos_seq_mag;
-                    let ang = self.harmonic_angs[i]; // / 180.0 * PI
-
-                    ah = ah + f64::sin(n * (pos_seq_phase) + ang) * mag;
-                    bh = bh + f64::sin(n * (pos_seq_phase - TWO_PI_OVER_THREE) + ang) * mag;
-                    ch = ch + f64::sin(n * (pos_seq_phase + TWO_PI_OVER_THREE) + ang) * mag;
+                    let ang = self.harmonic_angs[i];
+
+                    // a DC offset has no angle, rather than a constant of sin(ang)
+                    if *n == 0.0 {
+                        ah += mag;
+                        bh += mag;
+                        ch += mag;
+                        return;
+                    }
+
+                    ah = ah + f64::sin(n * (harmonic_phase) + ang) * mag;
+                    bh = bh + f64::sin(n * (harmonic_phase - TWO_PI_OVER_THREE) + ang) * mag;
+                    ch = ch + f64::sin(n * (harmonic_phase + TWO_PI_OVER_THREE) + ang) * mag;
                 });
             }
         }
 
-        let mut r = thread_rng();
-
         // add noise, ensure worst case where noise is uncorrelated across phases
         let ra: f64 =
-            r.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
+            rng.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
         let rb: f64 =
-            r.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
+            rng.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
         let rc: f64 =
-            r.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
+            rng.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max * self.pos_seq_mag;
+
+        // start a notch in each phase whose angle has passed a notch angle since the last sample
+        let step = wrap_angle_positive(pos_seq_phase - prev_pos_seq_phase);
+        for (phase, offset) in [0.0, -TWO_PI_OVER_THREE, TWO_PI_OVER_THREE]
+            .iter()
+            .enumerate()
+        {
+            let crossed = self.notch_angles.iter().any(|&notch| {
+                let to_notch = wrap_angle_positive(notch - (prev_pos_seq_phase + offset));
+                to_notch > 0.0 && to_notch <= step
+            });
+            if crossed {
+                self.notch_remaining[phase] = self.notch_width;
+            }
+        }
+        let mut notch = [1.0; 3];
+        for (phase, remaining) in self.notch_remaining.iter_mut().enumerate() {
+            if *remaining > 0 {
+                notch[phase] = 1.0 - self.notch_depth;
+                *remaining -= 1;
+            }
+        }
 
         // combine the output for each phase
-        self.a = a1 + a2 + abc0 + ah + ra;
-        self.b = b1 + b2 + abc0 + bh + rb;
-        self.c = c1 + c2 + abc0 + ch + rc;
+        self.a = (a1 + a2 + abc0 + ah) * self.phase_mag_scale[0] * notch[0] + ra;
+        self.b = (b1 + b2 + abc0 + bh) * self.phase_mag_scale[1] * notch[1] + rb;
+        self.c = (c1 + c2 + abc0 + ch) * self.phase_mag_scale[2] * notch[2] + rc;
+
+        if let Some(adc) = &self.adc {
+            self.a = adc.quantize(self.a);
+            self.b = adc.quantize(self.b);
+            self.c = adc.quantize(self.c);
+        }
+    }
+}
+
+impl DcEmulation {
+    fn step_dc(&mut self, rng: &mut impl Rng, ts: f64) {
+        let drift = self.drift_mag * f64::sin(2.0 * PI * self.drift_frequency * self.elapsed);
+        let ripple = self.ripple_mag * f64::sin(2.0 * PI * self.ripple_frequency * self.elapsed);
+        let noise = rng.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max;
+
+        self.value = self.nominal * (1.0 + drift + ripple + noise);
+        self.elapsed += ts;
+    }
+}
+
+impl FrequencyEmulation {
+    fn step_frequency(&mut self, rng: &mut impl Rng, f: f64, ts: f64) {
+        let filtered = match self.filtered {
+            Some(filtered) if self.time_constant > 0.0 => {
+                filtered + (f - filtered) * (1.0 - f64::exp(-ts / self.time_constant))
+            }
+            _ => f,
+        };
+        self.filtered = Some(filtered);
+
+        let noise = rng.sample::<f64, StandardNormal>(StandardNormal) * self.noise_max;
+        self.value = filtered + noise;
+    }
+}
+
+impl PowerEmulation {
+    fn step_power(
+        &mut self,
+        v: &ThreePhaseEmulation,
+        i: &ThreePhaseEmulation,
+        samples_per_cycle: usize,
+    ) {
+        self.a = v.a * i.a;
+        self.b = v.b * i.b;
+        self.c = v.c * i.c;
+        self.total = self.a + self.b + self.c;
+
+        self.sum_p += self.total;
+        [(v.a, i.a), (v.b, i.b), (v.c, i.c)]
+            .iter()
+            .enumerate()
+            .for_each(|(phase, (v, i))| {
+                self.sum_v2[phase] += v * v;
+                self.sum_i2[phase] += i * i;
+            });
+        self.samples += 1;
+
+        // calculate average quantities over each complete cycle
+        if self.samples >= samples_per_cycle {
+            let n = self.samples as f64;
+            self.real_power = self.sum_p / n;
+            self.apparent_power = (0..3)
+                .map(|phase| f64::sqrt(self.sum_v2[phase] / n) * f64::sqrt(self.sum_i2[phase] / n))
+                .sum();
+            self.power_factor = if self.apparent_power > 0.0 {
+                self.real_power / self.apparent_power
+            } else {
+                0.0
+            };
+
+            self.sum_p = 0.0;
+            self.sum_v2 = [0.0; 3];
+            self.sum_i2 = [0.0; 3];
+            self.samples = 0;
+        }
     }
 }
 
 impl SagEmulation {
-    fn step_sag(&mut self) {
-        let mut r = thread_rng();
-        // r.Seed(time.Now().UnixNano());
-        self.total_strain = self.mean_strain * r.gen::<f64>();
-        self.sag = self.mean_sag * r.gen::<f64>();
-        self.calculated_temperature = self.mean_calculated_temperature * r.gen::<f64>();
+    fn step_sag(&mut self, rng: &mut impl Rng) {
+        self.total_strain = self.mean_strain * rng.gen::<f64>();
+        self.sag = self.mean_sag * rng.gen::<f64>();
+        self.calculated_temperature = self.mean_calculated_temperature * rng.gen::<f64>();
     }
 }
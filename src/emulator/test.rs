@@ -1,28 +1,38 @@
-use crate::emulator::SagEmulation;
-use crate::emulator::{Emulator, TemperatureEmulation, ThreePhaseEmulation};
+use crate::emulator::{AdcQuantization, EventType, TrendShape};
+use crate::emulator::{DcEmulation, FrequencyEmulation, PowerEmulation, SagEmulation};
+use crate::emulator::{Emulator, HarmonicReference, TemperatureEmulation, ThreePhaseEmulation};
+use crate::error::JetstreamError;
 use std::collections::HashMap;
 use std::f64::consts::PI;
 
 fn create_emulator(sampling_rate: usize, phase_offset_deg: f64) -> Emulator {
-    let mut emu = Emulator::new(sampling_rate, 50.0);
+    configure_emulator(Emulator::new(sampling_rate, 50.0), phase_offset_deg)
+}
 
+fn configure_emulator(mut emu: Emulator, phase_offset_deg: f64) -> Emulator {
     emu.v = Some(ThreePhaseEmulation {
         pos_seq_mag: 400000.0 / f64::sqrt(3.0) * f64::sqrt(2.0),
         noise_max: 0.000001,
         phase_offset: phase_offset_deg * PI / 180.0,
         ..Default::default()
     });
-    emu.i = Some(ThreePhaseEmulation {
+    let mut i = ThreePhaseEmulation {
         pos_seq_mag: 500.0,
         phase_offset: phase_offset_deg * PI / 180.0,
-        harmonic_numbers: vec![5.0, 7.0, 11.0, 13.0, 17.0, 19.0, 23.0, 25.0],
-        harmonic_mags: vec![
-            0.2164, 0.1242, 0.0892, 0.0693, 0.0541, 0.0458, 0.0370, 0.0332,
-        ],
-        harmonic_angs: vec![171.5, 100.4, -52.4, 128.3, 80.0, 2.9, -146.8, 133.9],
         noise_max: 0.000001,
         ..Default::default()
-    });
+    };
+    i.set_harmonics(&[
+        (5.0, 0.2164, 171.5),
+        (7.0, 0.1242, 100.4),
+        (11.0, 0.0892, -52.4),
+        (13.0, 0.0693, 128.3),
+        (17.0, 0.0541, 80.0),
+        (19.0, 0.0458, 2.9),
+        (23.0, 0.0370, -146.8),
+        (25.0, 0.0332, 133.9),
+    ]);
+    emu.i = Some(i);
     emu.t = Some(TemperatureEmulation {
         mean_temperature: 30.0,
         noise_max: 0.01,
@@ -139,6 +149,65 @@ fn test_temperature_emulation_decreasing_trend() {
     assert!(mean(&results) < emulator.t.as_ref().unwrap().mean_temperature);
 }
 
+// Returns the trend anomaly at each second of a 10 second anomaly of the given shape, with no
+// noise, modulation or instantaneous anomalies.
+fn trend_anomaly_progression(shape: TrendShape) -> Vec<f64> {
+    let mut emulator = create_emulator(1, 0.0);
+    let t = emulator.t.as_mut().unwrap();
+    t.noise_max = 0.0;
+    t.instantaneous_anomaly_probability = 0.0;
+    t.is_trend_anomaly = true;
+    t.trend_anomaly_magnitude = 30.0;
+    t.trend_anomaly_duration = 10;
+    t.is_rising_trend_anomaly = true;
+    t.trend_shape = shape;
+
+    (0..10)
+        .map(|_| {
+            emulator.step();
+            let t = emulator.t.as_ref().unwrap();
+            t.t - t.mean_temperature
+        })
+        .collect()
+}
+
+#[test]
+fn test_temperature_emulation_linear_trend() {
+    let results = trend_anomaly_progression(TrendShape::Linear);
+    for (k, &delta) in results.iter().enumerate() {
+        assert!(
+            floating_point_equal(3.0 * k as f64, delta, 1e-9),
+            "{}",
+            delta
+        );
+    }
+}
+
+#[test]
+fn test_temperature_emulation_exponential_trend() {
+    let results = trend_anomaly_progression(TrendShape::Exponential);
+    assert!(floating_point_equal(0.0, results[0], 1e-9));
+
+    // each step is larger than the last, and the anomaly stays below the linear trend
+    for k in 1..results.len() {
+        assert!(results[k] > results[k - 1]);
+        assert!(results[k] < 3.0 * k as f64, "{} at {}", results[k], k);
+        if k > 1 {
+            assert!(results[k] - results[k - 1] > results[k - 1] - results[k - 2]);
+        }
+    }
+    let expected = 30.0 * f64::exp_m1(4.5) / f64::exp_m1(5.0);
+    assert!(floating_point_equal(expected, results[9], 1e-9));
+}
+
+#[test]
+fn test_temperature_emulation_step_trend() {
+    let results = trend_anomaly_progression(TrendShape::Step);
+    for &delta in &results {
+        assert!(floating_point_equal(30.0, delta, 1e-9), "{}", delta);
+    }
+}
+
 #[test]
 fn test_sag_emulation() {
     let mut emulator = create_emulator(14400, 0.0);
@@ -174,3 +243,793 @@ fn test_sag_emulation() {
     // 	assert.IsType(t, []float64{}, results[field])
     // }
 }
+
+fn peak(values: &[f64]) -> f64 {
+    values.iter().fold(0.0, |max, v| f64::max(max, v.abs()))
+}
+
+#[test]
+fn test_phase_mag_scale() {
+    let mut balanced = create_emulator(4000, 0.0);
+    let mut unbalanced = create_emulator(4000, 0.0);
+    unbalanced.v.as_mut().unwrap().phase_mag_scale = [1.0, 0.5, 1.0];
+
+    let mut balanced_abc: Vec<Vec<f64>> = vec![vec![]; 3];
+    let mut unbalanced_abc: Vec<Vec<f64>> = vec![vec![]; 3];
+
+    // two cycles at 50 Hz
+    for _ in 0..160 {
+        balanced.step();
+        unbalanced.step();
+
+        let v = balanced.v.as_ref().unwrap();
+        balanced_abc[0].push(v.a);
+        balanced_abc[1].push(v.b);
+        balanced_abc[2].push(v.c);
+
+        let v = unbalanced.v.as_ref().unwrap();
+        unbalanced_abc[0].push(v.a);
+        unbalanced_abc[1].push(v.b);
+        unbalanced_abc[2].push(v.c);
+    }
+
+    let pos_seq_mag = balanced.v.as_ref().unwrap().pos_seq_mag;
+    let threshold = 0.001 * pos_seq_mag;
+
+    assert!(floating_point_equal(
+        peak(&balanced_abc[0]),
+        peak(&unbalanced_abc[0]),
+        threshold
+    ));
+    assert!(floating_point_equal(
+        0.5 * peak(&balanced_abc[1]),
+        peak(&unbalanced_abc[1]),
+        threshold
+    ));
+    assert!(floating_point_equal(
+        peak(&balanced_abc[2]),
+        peak(&unbalanced_abc[2]),
+        threshold
+    ));
+}
+
+#[test]
+fn test_power_factor() {
+    let mut emulator = Emulator::new(4000, 50.0);
+    emulator.v = Some(ThreePhaseEmulation {
+        pos_seq_mag: 1000.0,
+        ..Default::default()
+    });
+    // current lags voltage by 30 degrees
+    emulator.i = Some(ThreePhaseEmulation {
+        pos_seq_mag: 10.0,
+        phase_offset: -30.0 * PI / 180.0,
+        ..Default::default()
+    });
+    emulator.power = Some(PowerEmulation::default());
+
+    // two cycles at 50 Hz
+    for _ in 0..160 {
+        emulator.step();
+
+        let v = emulator.v.as_ref().unwrap();
+        let i = emulator.i.as_ref().unwrap();
+        let power = emulator.power.as_ref().unwrap();
+        assert!(floating_point_equal(v.a * i.a, power.a, 1e-9));
+        assert!(floating_point_equal(
+            power.a + power.b + power.c,
+            power.total,
+            1e-9
+        ));
+    }
+
+    let power = emulator.power.as_ref().unwrap();
+    let expected_pf = f64::cos(30.0 * PI / 180.0);
+    assert!(floating_point_equal(expected_pf, power.power_factor, 1e-6));
+    // three phases, each with an apparent power of Vrms * Irms
+    assert!(floating_point_equal(
+        3.0 * 1000.0 * 10.0 / 2.0,
+        power.apparent_power,
+        1e-6
+    ));
+    assert!(floating_point_equal(
+        expected_pf * power.apparent_power,
+        power.real_power,
+        1e-6
+    ));
+}
+
+#[test]
+fn test_dc_emulation() {
+    let mut emulator = Emulator::new(4000, 50.0);
+    emulator.dc = Some(DcEmulation {
+        nominal: 110.0,
+        ripple_mag: 0.01,
+        ripple_frequency: 100.0,
+        ..Default::default()
+    });
+
+    // one second of data
+    let mut results: Vec<f64> = vec![];
+    for _ in 0..emulator.sampling_rate {
+        emulator.step();
+        results.push(emulator.dc.as_ref().unwrap().value);
+    }
+
+    let mean_value = mean(&results);
+    assert!(floating_point_equal(110.0, mean_value, 1e-6));
+
+    // count rising zero crossings of the ripple to find its frequency
+    let crossings = results
+        .windows(2)
+        .filter(|w| w[0] - mean_value <= 0.0 && w[1] - mean_value > 0.0)
+        .count();
+    assert!(floating_point_equal(100.0, crossings as f64, 1.5));
+}
+
+#[test]
+fn test_dataset_9_2_le() {
+    let mut emu = create_emulator(4000, 0.0);
+
+    for _ in 0..100 {
+        emu.step();
+        let d = emu.dataset_9_2_le();
+
+        let i = emu.i.as_ref().unwrap();
+        let v = emu.v.as_ref().unwrap();
+        let expected = [
+            (i.a * 1000.0) as i32,
+            (i.b * 1000.0) as i32,
+            (i.c * 1000.0) as i32,
+            ((i.a + i.b + i.c) * 1000.0) as i32,
+            (v.a * 100.0) as i32,
+            (v.b * 100.0) as i32,
+            (v.c * 100.0) as i32,
+            ((v.a + v.b + v.c) * 100.0) as i32,
+        ];
+        assert_eq!(d.i32s, expected);
+        assert_eq!(d.q, [0; 8]);
+    }
+}
+
+#[test]
+fn test_warm_up() {
+    // the ramped emulator starts from zero, settling after 100 samples
+    let mut ramped = Emulator::new(4000, 50.0);
+    ramped.v = Some(ThreePhaseEmulation {
+        pos_seq_mag_new: 500.0,
+        pos_seq_mag_ramp_rate: 5.0,
+        ..Default::default()
+    });
+    let mut steady = Emulator::new(4000, 50.0);
+    steady.v = Some(ThreePhaseEmulation {
+        pos_seq_mag: 500.0,
+        ..Default::default()
+    });
+
+    // before warm-up, the first sample is part of the transient
+    let mut cold = Emulator::new(4000, 50.0);
+    cold.v = Some(ThreePhaseEmulation {
+        pos_seq_mag_new: 500.0,
+        pos_seq_mag_ramp_rate: 5.0,
+        ..Default::default()
+    });
+    cold.step();
+    steady.step();
+    assert!((cold.v.as_ref().unwrap().a - steady.v.as_ref().unwrap().a).abs() > 1.0);
+
+    // after warm-up, the first sample matches the steady state at the same phase
+    ramped.warm_up(400);
+    steady.warm_up(399);
+    assert_eq!(ramped.smp_cnt, steady.smp_cnt);
+    for _ in 0..80 {
+        ramped.step();
+        steady.step();
+        let (r, s) = (ramped.v.as_ref().unwrap(), steady.v.as_ref().unwrap());
+        assert!((r.a - s.a).abs() < 1e-9, "{} != {}", r.a, s.a);
+        assert!((r.b - s.b).abs() < 1e-9, "{} != {}", r.b, s.b);
+        assert!((r.c - s.c).abs() < 1e-9, "{} != {}", r.c, s.c);
+    }
+}
+
+#[test]
+fn test_set_harmonics() {
+    let mut from_spectrum = ThreePhaseEmulation::default();
+    from_spectrum.set_harmonics(&[(5.0, 0.2, 90.0), (7.0, 0.1, -45.0)]);
+
+    let manual = ThreePhaseEmulation {
+        harmonic_numbers: vec![5.0, 7.0],
+        harmonic_mags: vec![0.2, 0.1],
+        harmonic_angs: vec![PI / 2.0, -PI / 4.0],
+        ..Default::default()
+    };
+    assert_eq!(from_spectrum.harmonic_numbers, manual.harmonic_numbers);
+    assert_eq!(from_spectrum.harmonic_mags, manual.harmonic_mags);
+    for (actual, expected) in from_spectrum
+        .harmonic_angs
+        .iter()
+        .zip(&manual.harmonic_angs)
+    {
+        assert!(floating_point_equal(*expected, *actual, 1e-12));
+    }
+
+    // an equal "harmonic" at the fundamental frequency, 180 degrees out of phase, cancels it
+    let mut emu = Emulator::new(4000, 50.0);
+    let mut i = ThreePhaseEmulation {
+        pos_seq_mag: 500.0,
+        ..Default::default()
+    };
+    i.set_harmonics(&[(1.0, 1.0, 180.0)]);
+    emu.i = Some(i);
+
+    for _ in 0..80 {
+        emu.step();
+        let i = emu.i.as_ref().unwrap();
+        assert!(peak(&[i.a, i.b, i.c]) < 1e-9);
+    }
+}
+
+#[test]
+fn test_notches() {
+    let sampling_rate = 4000;
+    let new_emulator = |notch_depth| {
+        let mut emu = Emulator::new(sampling_rate, 50.0);
+        emu.v = Some(ThreePhaseEmulation {
+            pos_seq_mag: 1000.0,
+            notch_angles: vec![PI / 2.0, -PI / 2.0],
+            notch_depth,
+            notch_width: 3,
+            ..Default::default()
+        });
+        emu
+    };
+    let mut plain = new_emulator(0.0);
+    let mut notched = new_emulator(0.5);
+
+    // 80 samples per cycle, with each phase angle recorded after the step
+    let step = 2.0 * PI * 50.0 / sampling_rate as f64;
+    let mut notched_angles: Vec<Vec<f64>> = vec![vec![]; 3];
+    for _ in 0..160 {
+        plain.step();
+        notched.step();
+        let (p, n) = (plain.v.as_ref().unwrap(), notched.v.as_ref().unwrap());
+        for (phase, (expected, actual)) in [(p.a, n.a), (p.b, n.b), (p.c, n.c)].iter().enumerate() {
+            if floating_point_equal(0.5 * expected, *actual, 1e-9) && expected.abs() > 1e-6 {
+                let offset = [0.0, -2.0 * PI / 3.0, 2.0 * PI / 3.0][phase];
+                notched_angles[phase].push((n.p_angle + offset).rem_euclid(2.0 * PI));
+            } else {
+                assert!(floating_point_equal(*expected, *actual, 1e-9));
+            }
+        }
+    }
+
+    // each phase has two notches of three samples per cycle, starting at the positive and
+    // negative peaks of that phase
+    for angles in &notched_angles {
+        assert_eq!(angles.len(), 2 * 2 * 3);
+        for notch in angles.chunks(3) {
+            let start = [PI / 2.0, 3.0 * PI / 2.0]
+                .iter()
+                .map(|&peak| (notch[0] - peak).rem_euclid(2.0 * PI))
+                .fold(f64::MAX, f64::min);
+            assert!(start < step, "notch starts {} after the peak", start);
+        }
+    }
+}
+
+#[test]
+fn test_frequency_emulation() {
+    let mut emulator = Emulator::new(4000, 50.0);
+    emulator.freq = Some(FrequencyEmulation {
+        time_constant: 0.1,
+        ..Default::default()
+    });
+    let measured = |emulator: &Emulator| emulator.freq.as_ref().unwrap().value;
+
+    // the measurement starts at the true frequency, without a transient
+    emulator.warm_up(100);
+    assert!(floating_point_equal(50.0, measured(&emulator), 1e-9));
+
+    // after a step change, the error decays by a factor of e for each time constant
+    emulator.deviation = 0.5;
+    emulator.warm_up(400);
+    let expected = 50.0 + 0.5 * (1.0 - f64::exp(-1.0));
+    assert!(floating_point_equal(expected, measured(&emulator), 1e-3));
+    emulator.warm_up(1600);
+    assert!(floating_point_equal(
+        50.5,
+        measured(&emulator),
+        0.5 * f64::exp(-5.0) + 1e-3
+    ));
+
+    // noise is added to the output, but does not disturb the tracked frequency
+    emulator.freq.as_mut().unwrap().noise_max = 0.01;
+    let mut results: Vec<f64> = vec![];
+    for _ in 0..emulator.sampling_rate {
+        emulator.step();
+        results.push(measured(&emulator));
+    }
+    assert!(floating_point_equal(50.5, mean(&results), 1e-3));
+    assert!(results
+        .iter()
+        .any(|&f| !floating_point_equal(50.5, f, 1e-3)));
+}
+
+// Returns the phase of the harmonic of order `n` of a 50 Hz waveform sampled at 4 kHz, over
+// a whole number of cycles, relative to the time of the first sample.
+fn harmonic_phase(values: &[f64], n: f64) -> f64 {
+    let w = 2.0 * PI * 50.0 * n / 4000.0;
+    let (sin_sum, cos_sum) = values
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(s, c), (k, v)| {
+            let t = (k + 1) as f64; // the emulator advances the angle before each sample
+            (s + v * f64::sin(w * t), c + v * f64::cos(w * t))
+        });
+    f64::atan2(cos_sum, sin_sum)
+}
+
+#[test]
+fn test_harmonic_reference() {
+    let phase_a = |harmonic_reference| {
+        let mut emu = Emulator::new(4000, 50.0);
+        let mut v = ThreePhaseEmulation {
+            pos_seq_mag: 1000.0,
+            phase_offset: PI / 6.0,
+            harmonic_reference,
+            ..Default::default()
+        };
+        v.set_harmonics(&[(5.0, 0.1, 20.0)]);
+        emu.v = Some(v);
+
+        // ten cycles
+        (0..800)
+            .map(|_| {
+                emu.step();
+                emu.v.as_ref().unwrap().a
+            })
+            .collect::<Vec<f64>>()
+    };
+    let angle_equal = |expected: f64, actual: f64| {
+        let diff = (expected - actual).rem_euclid(2.0 * PI);
+        floating_point_equal(0.0, f64::min(diff, 2.0 * PI - diff), 1e-6)
+    };
+
+    // relative to the fundamental, the harmonic follows five times the 30 degree offset
+    let relative = phase_a(HarmonicReference::Fundamental);
+    assert!(angle_equal(PI / 6.0, harmonic_phase(&relative, 1.0)));
+    assert!(angle_equal(
+        5.0 * PI / 6.0 + 20f64.to_radians(),
+        harmonic_phase(&relative, 5.0)
+    ));
+
+    // with an absolute reference, the harmonic keeps its own angle
+    let absolute = phase_a(HarmonicReference::Absolute);
+    assert!(angle_equal(PI / 6.0, harmonic_phase(&absolute, 1.0)));
+    assert!(angle_equal(
+        20f64.to_radians(),
+        harmonic_phase(&absolute, 5.0)
+    ));
+}
+
+#[test]
+fn test_harmonic_spectrum_angles() {
+    // the spectrum of `create_emulator`, with angles in degrees
+    let mut emu = create_emulator(4000, 0.0);
+    let i = emu.i.as_mut().unwrap();
+    i.noise_max = 0.0;
+    let spectrum: Vec<(f64, f64)> = i
+        .harmonic_numbers
+        .iter()
+        .zip(&i.harmonic_angs)
+        .map(|(&n, &ang)| (n, ang.to_degrees()))
+        .collect();
+    assert_eq!(spectrum[0].0, 5.0);
+    assert!(floating_point_equal(171.5, spectrum[0].1, 1e-9));
+
+    // ten cycles of phase A, from which a DFT recovers each angle
+    let values: Vec<f64> = (0..800)
+        .map(|_| {
+            emu.step();
+            emu.i.as_ref().unwrap().a
+        })
+        .collect();
+    for (n, degrees) in spectrum {
+        let diff = (harmonic_phase(&values, n) - degrees.to_radians()).rem_euclid(2.0 * PI);
+        assert!(
+            floating_point_equal(0.0, f64::min(diff, 2.0 * PI - diff), 1e-6),
+            "harmonic {}",
+            n
+        );
+    }
+}
+
+#[test]
+fn test_dc_offset_harmonic() {
+    let new_emulator = |spectrum: &[(f64, f64, f64)]| {
+        let mut emu = Emulator::new(4000, 50.0);
+        let mut v = ThreePhaseEmulation {
+            pos_seq_mag: 1000.0,
+            ..Default::default()
+        };
+        v.set_harmonics(spectrum);
+        emu.v = Some(v);
+        emu
+    };
+    // order 0 offsets every phase by its magnitude, whatever its angle
+    for angle in [0.0, 90.0, -45.0] {
+        let mut offset = new_emulator(&[(0.0, 0.1, angle), (5.0, 0.05, 0.0)]);
+        let mut harmonic = new_emulator(&[(5.0, 0.05, 0.0)]);
+        for _ in 0..80 {
+            offset.step();
+            harmonic.step();
+            let (h, o) = (harmonic.v.as_ref().unwrap(), offset.v.as_ref().unwrap());
+            for (expected, actual) in [(h.a, o.a), (h.b, o.b), (h.c, o.c)] {
+                assert!(floating_point_equal(expected + 100.0, actual, 1e-9));
+            }
+        }
+    }
+
+    // the offset averages to the magnitude over a cycle
+    let mut offset = new_emulator(&[(0.0, -0.2, 0.0)]);
+    let mut values: Vec<f64> = vec![];
+    for _ in 0..80 {
+        offset.step();
+        values.push(offset.v.as_ref().unwrap().a);
+    }
+    assert!(floating_point_equal(-200.0, mean(&values), 1e-6));
+}
+
+fn rms(values: &[f64]) -> f64 {
+    f64::sqrt(values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64)
+}
+
+// The RMS of each phase of the current and voltage over `cycles` cycles at 50 Hz and 4 kHz,
+// after stepping the emulator.
+fn phase_rms(emulator: &mut Emulator, cycles: usize) -> ([f64; 3], [f64; 3]) {
+    let mut i_abc: Vec<Vec<f64>> = vec![vec![]; 3];
+    let mut v_abc: Vec<Vec<f64>> = vec![vec![]; 3];
+    for _ in 0..cycles * 80 {
+        emulator.step();
+
+        let i = emulator.i.as_ref().unwrap();
+        i_abc[0].push(i.a);
+        i_abc[1].push(i.b);
+        i_abc[2].push(i.c);
+
+        let v = emulator.v.as_ref().unwrap();
+        v_abc[0].push(v.a);
+        v_abc[1].push(v.b);
+        v_abc[2].push(v.c);
+    }
+    (
+        [rms(&i_abc[0]), rms(&i_abc[1]), rms(&i_abc[2])],
+        [rms(&v_abc[0]), rms(&v_abc[1]), rms(&v_abc[2])],
+    )
+}
+
+#[test]
+fn test_event_type_display() {
+    assert_eq!(
+        EventType::SinglePhaseFault.to_string(),
+        "single-phase fault"
+    );
+    assert_eq!(
+        EventType::CapacitorOverCurrent.to_string(),
+        "capacitor over-current"
+    );
+    assert_eq!(format!("{:?}", EventType::OverVoltage), "OverVoltage");
+}
+
+#[test]
+fn test_single_phase_fault() {
+    let mut nominal = create_emulator(4000, 0.0);
+    let mut faulted = create_emulator(4000, 0.0);
+    faulted.start_event(EventType::SinglePhaseFault);
+
+    let (nominal_i, nominal_v) = phase_rms(&mut nominal, 2);
+    let (faulted_i, faulted_v) = phase_rms(&mut faulted, 2);
+
+    // only phase A is perturbed, with the voltage falling to 0.8 pu
+    assert!(faulted_i[0] > 1.5 * nominal_i[0], "{:?}", faulted_i);
+    assert!(floating_point_equal(
+        0.8 * nominal_v[0],
+        faulted_v[0],
+        0.001 * nominal_v[0]
+    ));
+    for phase in 1..3 {
+        assert!(floating_point_equal(
+            nominal_i[phase],
+            faulted_i[phase],
+            0.001 * nominal_i[phase]
+        ));
+        assert!(floating_point_equal(
+            nominal_v[phase],
+            faulted_v[phase],
+            0.001 * nominal_v[phase]
+        ));
+    }
+}
+
+#[test]
+fn test_single_phase_fault_expires() {
+    let mut emulator = create_emulator(4000, 0.0);
+    emulator.start_event(EventType::SinglePhaseFault);
+
+    // phase A diverges from phases B and C during the fault
+    let (i, v) = phase_rms(&mut emulator, 2);
+    for phase in 1..3 {
+        assert!(i[0] > 1.5 * i[phase], "{:?}", i);
+        assert!(floating_point_equal(0.8 * v[phase], v[0], 0.001 * v[phase]));
+    }
+
+    // and the phases are balanced again once it ends, after 6000 samples
+    phase_rms(&mut emulator, 73);
+    let (i, v) = phase_rms(&mut emulator, 2);
+    for phase in 1..3 {
+        assert!(
+            floating_point_equal(i[0], i[phase], 0.001 * i[0]),
+            "{:?}",
+            i
+        );
+        assert!(
+            floating_point_equal(v[0], v[phase], 0.001 * v[0]),
+            "{:?}",
+            v
+        );
+    }
+}
+
+#[test]
+fn test_capacitor_over_current() {
+    let mut nominal = create_emulator(4000, 0.0);
+    let mut event = create_emulator(4000, 0.0);
+    event.start_event(EventType::CapacitorOverCurrent);
+
+    // the over-current decays over two seconds
+    let (nominal_i, nominal_v) = phase_rms(&mut nominal, 2);
+    let (start_i, start_v) = phase_rms(&mut event, 2);
+    phase_rms(&mut nominal, 46);
+    phase_rms(&mut event, 46);
+    let (later_i, _) = phase_rms(&mut event, 2);
+    phase_rms(&mut event, 50);
+    let (end_i, _) = phase_rms(&mut event, 2);
+
+    for phase in 0..3 {
+        assert!(start_i[phase] > 1.2 * nominal_i[phase], "{:?}", start_i);
+        assert!(later_i[phase] > 1.05 * nominal_i[phase], "{:?}", later_i);
+        assert!(later_i[phase] < start_i[phase] * 0.9, "{:?}", later_i);
+        assert!(floating_point_equal(
+            nominal_i[phase],
+            end_i[phase],
+            0.001 * nominal_i[phase]
+        ));
+
+        // the voltage is not affected
+        assert!(floating_point_equal(
+            nominal_v[phase],
+            start_v[phase],
+            0.001 * nominal_v[phase]
+        ));
+    }
+}
+
+#[test]
+fn test_adc_quantization() {
+    let mut emulator = create_emulator(4000, 0.0);
+    let v = emulator.v.as_mut().unwrap();
+    let adc = AdcQuantization {
+        bits: 12,
+        full_scale: 1.2 * v.pos_seq_mag,
+    };
+    v.adc = Some(adc);
+
+    // the output only takes values on the quantization grid, within the range of the ADC
+    let mut codes: Vec<i64> = vec![];
+    for _ in 0..800 {
+        emulator.step();
+        let v = emulator.v.as_ref().unwrap();
+        for value in [v.a, v.b, v.c] {
+            let code = value / adc.lsb();
+            assert!(floating_point_equal(code.round(), code, 1e-6), "{}", value);
+            assert!((-2048.0..2048.0).contains(&code), "{}", code);
+            codes.push(code.round() as i64);
+        }
+    }
+
+    // the peaks of a sine wave at 1/1.2 of full scale are near code 2048 / 1.2
+    let max = codes.iter().max().unwrap();
+    let min = codes.iter().min().unwrap();
+    assert!((1705..=1708).contains(max), "{}", max);
+    assert!((-1708..=-1705).contains(min), "{}", min);
+
+    // the output saturates at the limits of the range
+    let adc = AdcQuantization {
+        bits: 8,
+        full_scale: 1.0,
+    };
+    assert_eq!(adc.quantize(2.0), 127.0 * adc.lsb());
+    assert_eq!(adc.quantize(-2.0), -1.0);
+    assert_eq!(adc.quantize(0.4 * adc.lsb()), 0.0);
+    assert_eq!(adc.quantize(0.6 * adc.lsb()), adc.lsb());
+}
+
+#[test]
+fn test_seeded_emulator() {
+    let mut first = configure_emulator(Emulator::new_seeded(4000, 50.0, 42), 0.0);
+    let mut second = configure_emulator(Emulator::new_seeded(4000, 50.0, 42), 0.0);
+    let mut other = configure_emulator(Emulator::new_seeded(4000, 50.0, 43), 0.0);
+
+    // the noise and temperature anomalies are identical with the same seed
+    let mut differs = false;
+    for _ in 0..1000 {
+        first.step();
+        second.step();
+        other.step();
+
+        for (f, s, o) in [
+            (&first.v, &second.v, &other.v),
+            (&first.i, &second.i, &other.i),
+        ] {
+            let (f, s, o) = (
+                f.as_ref().unwrap(),
+                s.as_ref().unwrap(),
+                o.as_ref().unwrap(),
+            );
+            assert_eq!([f.a, f.b, f.c], [s.a, s.b, s.c]);
+            differs |= [f.a, f.b, f.c] != [o.a, o.b, o.c];
+        }
+        assert_eq!(first.t.as_ref().unwrap().t, second.t.as_ref().unwrap().t);
+    }
+    assert!(differs);
+}
+
+#[test]
+fn test_validate_harmonics() {
+    let mut emulator = create_emulator(4000, 0.0);
+    assert!(emulator.validate().is_ok());
+
+    // a missing angle is reported, rather than every harmonic being dropped silently
+    let i = emulator.i.as_mut().unwrap();
+    i.harmonic_angs.pop();
+    assert!(matches!(i.validate(), Err(JetstreamError::InvalidInput(_))));
+    assert!(matches!(
+        emulator.validate(),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+
+    let i = emulator.i.as_mut().unwrap();
+    i.harmonic_numbers.pop();
+    i.harmonic_mags.pop();
+    assert!(i.validate().is_ok());
+    i.harmonic_numbers.push(3.0);
+    assert!(i.validate().is_err());
+}
+
+#[test]
+fn test_phase_jump() {
+    let mut emu = Emulator::new(4000, 50.0);
+    emu.v = Some(ThreePhaseEmulation {
+        pos_seq_mag: 1000.0,
+        ..Default::default()
+    });
+    emu.i = Some(ThreePhaseEmulation {
+        pos_seq_mag: 500.0,
+        phase_offset: -PI / 6.0,
+        ..Default::default()
+    });
+    let cycle = |emu: &mut Emulator| {
+        (0..80)
+            .map(|_| {
+                emu.step();
+                (emu.v.as_ref().unwrap().a, emu.i.as_ref().unwrap().a)
+            })
+            .unzip::<f64, f64, Vec<f64>, Vec<f64>>()
+    };
+    let angle_equal = |expected: f64, actual: f64| {
+        let diff = (expected - actual).rem_euclid(2.0 * PI);
+        floating_point_equal(0.0, f64::min(diff, 2.0 * PI - diff), 1e-6)
+    };
+
+    // whole cycles start at the same angle, until the jump
+    let (v_before, i_before) = cycle(&mut emu);
+    let last_v = *v_before.last().unwrap();
+    emu.start_event(EventType::PhaseJump(-40.0));
+    assert_eq!(
+        EventType::PhaseJump(-40.0).to_string(),
+        "phase jump of -40 degrees"
+    );
+    let (v_after, i_after) = cycle(&mut emu);
+
+    let jump = -40f64.to_radians();
+    assert!(angle_equal(
+        harmonic_phase(&v_before, 1.0) + jump,
+        harmonic_phase(&v_after, 1.0)
+    ));
+    assert!(angle_equal(
+        harmonic_phase(&i_before, 1.0) + jump,
+        harmonic_phase(&i_after, 1.0)
+    ));
+
+    // the first sample after the jump is at the shifted angle, which is discontinuous
+    let w = 2.0 * PI * 50.0 / 4000.0;
+    assert!(floating_point_equal(
+        1000.0 * f64::sin(81.0 * w + jump),
+        v_after[0],
+        1e-6
+    ));
+    // unlike the largest change between samples without the jump
+    assert!((v_after[0] - last_v).abs() > 5.0 * 1000.0 * w);
+}
+
+#[test]
+fn test_sub_synchronous_modulation() {
+    // the amplitude of phase A in each cycle at 50 Hz and 4 kHz, over four seconds
+    let envelope = |modulation_mag: f64| {
+        let mut emu = Emulator::new(4000, 50.0);
+        emu.v = Some(ThreePhaseEmulation {
+            pos_seq_mag: 1000.0,
+            modulation_mag,
+            modulation_frequency: 2.0,
+            ..Default::default()
+        });
+        assert!(emu.validate().is_ok());
+        (0..200)
+            .map(|_| {
+                let cycle: Vec<f64> = (0..80)
+                    .map(|_| {
+                        emu.step();
+                        emu.v.as_ref().unwrap().a
+                    })
+                    .collect();
+                rms(&cycle) * f64::sqrt(2.0)
+            })
+            .collect::<Vec<f64>>()
+    };
+    // the magnitude of the component of the envelope at `f` Hz, sampled once per cycle
+    let component = |envelope: &[f64], f: f64| {
+        let w = 2.0 * PI * f / 50.0;
+        let (s, c) = envelope
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(s, c), (k, v)| {
+                (
+                    s + v * f64::sin(w * k as f64),
+                    c + v * f64::cos(w * k as f64),
+                )
+            });
+        2.0 * f64::hypot(s, c) / envelope.len() as f64
+    };
+
+    // the envelope oscillates at the modulation frequency, with the given depth
+    let modulated = envelope(0.1);
+    let frequencies: Vec<f64> = (2..=20).map(|k| k as f64 * 0.25).collect();
+    let magnitudes: Vec<f64> = frequencies
+        .iter()
+        .map(|&f| component(&modulated, f))
+        .collect();
+    let (peak, &largest) = magnitudes
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap();
+    assert_eq!(frequencies[peak], 2.0);
+    assert!(floating_point_equal(100.0, largest, 1.0), "{}", largest);
+
+    // without modulation, the envelope is flat
+    let flat = envelope(0.0);
+    assert!(floating_point_equal(0.0, component(&flat, 2.0), 1e-6));
+    assert!(floating_point_equal(1000.0, mean(&flat), 1e-6));
+
+    // a modulation deeper than the fundamental is invalid
+    let mut emu = Emulator::new(4000, 50.0);
+    emu.v = Some(ThreePhaseEmulation {
+        pos_seq_mag: 1000.0,
+        modulation_mag: 1.5,
+        ..Default::default()
+    });
+    assert!(matches!(
+        emu.validate(),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+}
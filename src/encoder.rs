@@ -1,33 +1,95 @@
-use crate::encoding::{bitops, simple8b};
+use crate::encoding::simple8b;
+use crate::error::JetstreamError;
 use crate::jetstream::*;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+#[cfg(feature = "std")]
 use flate2::write::GzEncoder;
-use flate2::Compression;
-use log::{as_error, error};
+#[cfg(feature = "std")]
+use log::error;
+#[cfg(feature = "std")]
 use std::io::Write;
 use uuid::Uuid;
 
 /// Encoder defines a stream protocol instance
+///
+/// Dropping an encoder discards any samples buffered towards the next message, without
+/// writing them anywhere. Call `finish` at the end of a stream to emit them as a final,
+/// shorter message.
 pub struct Encoder {
     pub id: Uuid,
     pub sampling_rate: usize,
     pub samples_per_message: usize,
     pub i32_count: usize,
+    nominal_sampling_rate: usize,
     buf_a: Vec<u8>,
     buf_b: Vec<u8>,
     use_buf_a: bool,
     len: usize,
+    start_timestamp: u64,
+    epoch: Option<u64>,
+    little_endian: bool,
+    sample_timestamps: Option<Vec<u64>>,
+    reset_on_quality_change: bool,
+    // the sample which most recently restarted delta encoding, for each variable
+    segment_start: Vec<usize>,
+    /// Store the minimum and maximum of each variable over the message in the header, so
+    /// that the range can be read without decoding the samples.
+    pub store_channel_ranges: bool,
+    channel_ranges: Vec<(i32, i32)>,
+    // the statistics of each variable over the stream, if enabled
+    channel_stats: Option<Vec<ChannelStats>>,
+    // the variables included in each message
+    active_channels: Vec<bool>,
+    /// Pad the header so that the simple-8b words start at a multiple of 8 bytes from the start
+    /// of the message, allowing them to be loaded directly as aligned words. This has no effect
+    /// for messages which use varints or compression, or which place the quality section first.
+    pub align_words: bool,
+    /// Place the quality section and the boolean channels before the samples, so that
+    /// `Decoder::decode_quality` can read them without decoding the samples.
+    pub quality_first: bool,
+    /// Write the length of the final quality run of each variable, rather than the run length
+    /// of `0` which marks a run continuing to the end of the message. Both decode identically;
+    /// the marker is never longer, but a counted run states the length of the message.
+    pub count_final_quality_run: bool,
+    quality_width: QualityWidth,
+    compression: Compression,
+    compression_threshold: usize,
+    /// The longest a sample may be buffered before `encode_at` or `flush_if_due` emits a
+    /// partial message, bounding the latency of slow or sparse streams.
+    pub flush_timeout: Option<Duration>,
+    // the caller-supplied time at which the first buffered sample arrived, if the samples of
+    // the message are given by `encode_at`
+    first_sample_at: Option<Duration>,
     encoded_samples: usize,
     using_simple8b: bool,
     delta_encoding_layers: usize,
+    // the number of delta layers was chosen with `with_delta_layers`, rather than following
+    // from the sampling rate
+    fixed_delta_layers: bool,
     simple8b_values: Vec<u64>,
     prev_data: Vec<Dataset>,
     delta_n: Vec<i32>,
 
     quality_history: Vec<Vec<QualityHistory>>,
-    diffs: Vec<Vec<u64>>,
+    // the runs of each boolean channel, with the state stored as 0 or 1
+    bool_history: Vec<Vec<QualityHistory>>,
+    // the scale factor of each float channel, and the quantized value of every variable
+    float_scales: Vec<f64>,
+    quantized: Vec<i32>,
+    // the factor converting each variable to engineering units, which is not encoded
+    scale_factors: Vec<f64>,
+    diffs: Vec<Vec<i64>>,
     values: Vec<Vec<i32>>,
-    /// Use XOR delta instead of arithmetic delta.
+    /// Use XOR delta instead of arithmetic delta. The choice is recorded in each message header.
     pub use_xor: bool,
+    /// Write only the first 8 bytes of the ID in each message, rather than all 16. Such a
+    /// compact ID can be created from a `u64` with `Uuid::from_u64_pair(id, 0)`. The choice
+    /// is recorded in each message header, and the decoder compares only those bytes.
+    pub compact_id: bool,
     spatial_ref: Vec<Option<usize>>,
 }
 
@@ -39,28 +101,46 @@ impl Encoder {
         sampling_rate: usize,
         samples_per_message: usize,
     ) -> Self {
-        // estimate maximum buffer space required
-        let buf_size = MAX_HEADER_SIZE + samples_per_message * i32_count * 8 + i32_count * 4;
-
         let delta_encoding_layers = get_delta_encoding(sampling_rate);
 
         let using_simple8b = samples_per_message > SIMPLE8B_THRESHOLD_SAMPLES;
 
-        Self {
+        let mut enc = Self {
             id,
             sampling_rate,
             samples_per_message,
             i32_count,
+            nominal_sampling_rate: sampling_rate,
 
-            buf_a: vec![0; buf_size],
-            buf_b: vec![0; buf_size],
+            // allocated below, once the size can be estimated
+            buf_a: vec![],
+            buf_b: vec![],
 
             // initialise ping-pong buffer
             use_buf_a: true,
             len: 0,
+            start_timestamp: 0,
+            epoch: None,
+            little_endian: false,
+            sample_timestamps: None,
+            reset_on_quality_change: false,
+            segment_start: vec![0; i32_count],
+            store_channel_ranges: false,
+            channel_ranges: vec![(0, 0); i32_count],
+            channel_stats: None,
+            active_channels: vec![true; i32_count],
+            align_words: false,
+            quality_first: false,
+            count_final_quality_run: false,
+            quality_width: QualityWidth::U32,
+            compression: Compression::default(),
+            compression_threshold: COMPRESSION_THRESHOLD_SAMPLES,
+            flush_timeout: None,
+            first_sample_at: None,
             encoded_samples: 0,
             using_simple8b,
             delta_encoding_layers,
+            fixed_delta_layers: false,
 
             simple8b_values: vec![0; samples_per_message],
             // storage for delta-delta encoding
@@ -68,6 +148,10 @@ impl Encoder {
             delta_n: vec![0; delta_encoding_layers],
 
             quality_history: vec![vec![QualityHistory::default()]; i32_count],
+            bool_history: vec![],
+            float_scales: vec![],
+            quantized: vec![],
+            scale_factors: vec![1.0; i32_count],
             diffs: if using_simple8b {
                 vec![vec![0; samples_per_message]; i32_count]
             } else {
@@ -79,7 +163,120 @@ impl Encoder {
                 vec![]
             },
             use_xor: false,
+            compact_id: false,
             spatial_ref: vec![None; i32_count],
+        };
+        enc.grow_buffers(enc.estimate_size());
+        enc
+    }
+
+    /// Creates an encoder which applies `layers` of delta encoding, from 1 to 4, instead of
+    /// the number chosen for the sampling rate. Fewer layers can produce smaller messages for
+    /// slowly varying signals. As always, the number is recorded in each message header, so
+    /// any decoder follows it.
+    pub fn with_delta_layers(
+        id: Uuid,
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
+        layers: usize,
+    ) -> Result<Self, JetstreamError> {
+        if !(1..=MAX_DELTA_ENCODING_LAYERS).contains(&layers) {
+            return Err(JetstreamError::InvalidInput(format!(
+                "{} delta encoding layers is not between 1 and {}",
+                layers, MAX_DELTA_ENCODING_LAYERS
+            )));
+        }
+
+        let mut enc = Self::new(id, i32_count, sampling_rate, samples_per_message);
+        enc.delta_encoding_layers = layers;
+        enc.fixed_delta_layers = true;
+        enc.prev_data = vec![Dataset::new(i32_count); layers];
+        enc.delta_n = vec![0; layers];
+        Ok(enc)
+    }
+
+    /// Creates a stream protocol encoder instance from a raw 16 byte ID.
+    pub fn from_id_bytes(
+        id: [u8; 16],
+        i32_count: usize,
+        sampling_rate: usize,
+        samples_per_message: usize,
+    ) -> Self {
+        Self::new(
+            Uuid::from_bytes(id),
+            i32_count,
+            sampling_rate,
+            samples_per_message,
+        )
+    }
+
+    /// The size of each message buffer needed for a full message, with one quality value for
+    /// each variable and one run for each boolean channel. The buffers are allocated with this
+    /// size up front, and only grow if a message holds many changes of quality or state.
+    pub fn estimate_size(&self) -> usize {
+        let timestamps_size = if self.sample_timestamps.is_some() {
+            self.samples_per_message * 10 // the maximum size of each timestamp offset
+        } else {
+            0
+        };
+        MAX_HEADER_SIZE
+            + self.i32_count * MAX_CHANNEL_RANGE_SIZE
+            + self.i32_count.div_ceil(8) // bitmap of active variables
+            + 7 // padding to align the simple-8b words
+            + self.samples_per_message * self.i32_count * 8
+            + self.i32_count * 10 // a quality value and run length for each variable
+            + self.bool_history.len() * 6 // a state and run length for each boolean channel
+            + timestamps_size
+    }
+
+    /// Reserves at least `bytes` for each message buffer up front, for streams whose messages
+    /// are expected to be larger than `estimate_size`, such as those with frequent changes of
+    /// quality. This avoids growing the buffers while encoding. The buffers never shrink.
+    pub fn set_reserve_hint(&mut self, bytes: usize) {
+        self.grow_buffers(bytes);
+    }
+
+    /// Writes zeros to every buffer used to encode a message, so that the memory backing them is
+    /// mapped now rather than while the first message is encoded, avoiding a latency spike for
+    /// real-time streams. Call it after any settings which grow the buffers, such as
+    /// `set_reserve_hint`, and before the first sample. Compressed messages still allocate
+    /// the compressed output as they are encoded.
+    pub fn prewarm(&mut self) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "buffers can only be prewarmed between messages".to_string(),
+            ));
+        }
+
+        self.buf_a.fill(0);
+        self.buf_b.fill(0);
+        self.simple8b_values.fill(0);
+        for diffs in &mut self.diffs {
+            diffs.fill(0);
+        }
+        for values in &mut self.values {
+            values.fill(0);
+        }
+        Ok(())
+    }
+
+    // Grows both message buffers to at least `size` bytes.
+    fn grow_buffers(&mut self, size: usize) {
+        for buf in [&mut self.buf_a, &mut self.buf_b] {
+            if buf.len() < size {
+                buf.resize(size, 0);
+            }
+        }
+    }
+
+    #[cfg(any(test, feature = "internal-testing"))]
+    pub(crate) fn buffers(&self) -> crate::internal::EncoderBuffers<'_> {
+        crate::internal::EncoderBuffers {
+            buf_a: &self.buf_a,
+            buf_b: &self.buf_b,
+            use_buf_a: self.use_buf_a,
+            len: self.len,
         }
     }
 
@@ -91,6 +288,35 @@ impl Encoder {
         }
     }
 
+    // The format of the samples and the compression of the payload, which are recorded in
+    // each message header.
+    fn format_descriptor(&self) -> FormatDescriptor {
+        FormatDescriptor {
+            delta_layers: self.delta_encoding_layers,
+            simple8b: self.using_simple8b,
+            xor: self.use_xor,
+            compression: self.compression_for(self.encoded_samples),
+        }
+    }
+
+    // The compression of a message holding `samples`, which is none at or below the threshold.
+    pub(crate) fn compression_for(&self, samples: usize) -> Compression {
+        if samples > self.compression_threshold {
+            self.compression
+        } else {
+            Compression::None
+        }
+    }
+
+    // The number of bytes of the ID written in each message.
+    fn id_len(&self) -> usize {
+        if self.compact_id {
+            8
+        } else {
+            16
+        }
+    }
+
     fn buf_mut(&mut self) -> &mut Vec<u8> {
         if self.use_buf_a {
             &mut self.buf_a
@@ -99,52 +325,398 @@ impl Encoder {
         }
     }
 
-    // /// Use XOR delta instead of arithmetic delta.
-    // pub fn set_xor(&mut self, xor: bool) {
-    //     self.use_xor = xor;
-    // }
+    /// Use XOR delta instead of arithmetic delta. The choice can only be changed at a message
+    /// boundary, and is recorded in each message header.
+    pub fn set_xor(&mut self, xor: bool) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "XOR delta can only be changed between messages".to_string(),
+            ));
+        }
+        self.use_xor = xor;
+        Ok(())
+    }
+
+    /// Restart delta encoding for a variable when its quality changes, storing the next value
+    /// directly. This avoids large deltas where the value jumps at a quality transition. The
+    /// choice can only be changed at a message boundary, and is recorded in each message
+    /// header.
+    pub fn set_reset_on_quality_change(&mut self, reset: bool) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "reset on quality change can only be changed between messages".to_string(),
+            ));
+        }
+        self.reset_on_quality_change = reset;
+        Ok(())
+    }
+
+    /// Whether delta encoding restarts when the quality of a variable changes.
+    pub fn reset_on_quality_change(&self) -> bool {
+        self.reset_on_quality_change
+    }
 
     /// Automatically maps adjacent sets of three-phase currents for spatial compression.
+    /// The mapping can only be changed at a message boundary. Returns an error if the
+    /// configuration does not map any variables, as spatial compression would have no effect.
     pub fn set_spatial_refs(
         &mut self,
         count: usize,
         count_v: usize,
         count_i: usize,
         include_neutral: bool,
-    ) {
-        self.spatial_ref = create_spatial_refs(count, count_v, count_i, include_neutral)
+    ) -> Result<(), JetstreamError> {
+        let neutral = if include_neutral {
+            NeutralRefs::Included
+        } else {
+            NeutralRefs::Excluded
+        };
+        self.set_spatial_refs_with(count, count_v, count_i, neutral)
+    }
+
+    /// Like `set_spatial_refs`, with control over how neutral variables are referenced.
+    pub fn set_spatial_refs_with(
+        &mut self,
+        count: usize,
+        count_v: usize,
+        count_i: usize,
+        neutral: NeutralRefs,
+    ) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "spatial references can only be changed between messages".to_string(),
+            ));
+        }
+        let spatial_ref = create_spatial_refs_with(count, count_v, count_i, neutral);
+        if spatial_ref.iter().all(Option::is_none) {
+            return Err(JetstreamError::InvalidInput(format!(
+                "spatial references have no effect for {} variables with {} voltage and {} current sets",
+                count, count_v, count_i
+            )));
+        }
+        self.spatial_ref = spatial_ref;
+        Ok(())
+    }
+
+    /// Disables spatial compression for subsequent messages. The mapping can only be changed
+    /// at a message boundary.
+    pub fn clear_spatial_refs(&mut self) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "spatial references can only be changed between messages".to_string(),
+            ));
+        }
+        self.spatial_ref = vec![None; self.i32_count];
+        Ok(())
+    }
+
+    /// Changes the sampling rate for subsequent messages. The rate can only be changed at a
+    /// message boundary and, when it differs from the rate the encoder was created with, it is
+    /// recorded in the header of each message so the decoder can follow the change.
+    pub fn set_sampling_rate(&mut self, sampling_rate: usize) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "sampling rate can only be changed between messages".to_string(),
+            ));
+        }
+
+        let delta_encoding_layers = get_delta_encoding(sampling_rate);
+        if !self.fixed_delta_layers && delta_encoding_layers != self.delta_encoding_layers {
+            self.delta_encoding_layers = delta_encoding_layers;
+            self.prev_data = vec![Dataset::new(self.i32_count); delta_encoding_layers];
+            self.delta_n = vec![0; delta_encoding_layers];
+        }
+        self.sampling_rate = sampling_rate;
+
+        Ok(())
+    }
+
+    /// Sets the stream epoch. Each message timestamp at or after the epoch is then encoded as a
+    /// variable length offset from the epoch, rather than in full. The decoder must be given
+    /// the same epoch.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = Some(epoch);
+    }
+
+    /// Writes the timestamp and simple-8b words in little-endian byte order, instead of the
+    /// default big-endian. The choice is recorded in each message header.
+    pub fn set_little_endian(&mut self, little_endian: bool) {
+        self.little_endian = little_endian;
+    }
+
+    /// Encodes the timestamp of every sample, rather than only the first, so that gaps in the
+    /// input are preserved. Each timestamp is stored as a variable length offset from the
+    /// previous sample.
+    pub fn set_sample_timestamps(&mut self, enabled: bool) {
+        if enabled == self.sample_timestamps.is_some() {
+            return;
+        }
+        if enabled {
+            self.sample_timestamps = Some(vec![0; self.samples_per_message]);
+            self.grow_buffers(self.estimate_size());
+        } else {
+            self.sample_timestamps = None;
+        }
+    }
+
+    /// Sets the width used to store quality values for subsequent messages. A narrower width
+    /// is smaller only for values which need more bytes as a uvarint, such as values with the
+    /// top bit of a `u8` or `u16` set. The width can only be changed at a message boundary.
+    pub fn set_quality_width(&mut self, width: QualityWidth) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "quality width can only be changed between messages".to_string(),
+            ));
+        }
+        self.quality_width = width;
+        Ok(())
+    }
+
+    /// Sets the algorithm used to compress messages with more samples than the compression
+    /// threshold, which is recorded in each message header. Returns an error if the algorithm
+    /// needs a feature which is not enabled.
+    pub fn set_compression(&mut self, compression: Compression) -> Result<(), JetstreamError> {
+        if !compression.is_supported() {
+            return Err(JetstreamError::UnsupportedCompression(compression));
+        }
+        self.compression = compression;
+        Ok(())
+    }
+
+    /// The algorithm used to compress long messages.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Sets the number of samples which a message must exceed to have its payload compressed,
+    /// if compression is enabled, which is 4096 by default. Messages flushed before they are
+    /// full are compressed only if the samples they actually hold exceed the threshold. The
+    /// threshold may be changed part way through a message, because it is only applied when
+    /// the message is complete, and the decision is recorded in the message header.
+    pub fn set_compression_threshold(&mut self, threshold: usize) {
+        self.compression_threshold = threshold;
+    }
+
+    /// The number of samples which a message must exceed to be compressed.
+    pub fn compression_threshold(&self) -> usize {
+        self.compression_threshold
+    }
+
+    /// Sets which variables are included in subsequent messages, for example to omit a channel
+    /// which is out of service. The values and quality of inactive variables are ignored, and
+    /// the decoder reports them as absent. The set can only be changed at a message boundary.
+    pub fn set_active_channels(&mut self, active: &[bool]) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "active channels can only be changed between messages".to_string(),
+            ));
+        }
+        if active.len() != self.i32_count {
+            return Err(JetstreamError::InvalidInput(format!(
+                "expected {} active channel flags, got {}",
+                self.i32_count,
+                active.len()
+            )));
+        }
+        self.active_channels.copy_from_slice(active);
+        Ok(())
+    }
+
+    /// Includes or omits variable `i` in subsequent messages, like `set_active_channels`.
+    pub fn set_channel_active(&mut self, i: usize, active: bool) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "active channels can only be changed between messages".to_string(),
+            ));
+        }
+        if i >= self.i32_count {
+            return Err(JetstreamError::InvalidInput(format!(
+                "channel {} is out of range for {} channels",
+                i, self.i32_count
+            )));
+        }
+        self.active_channels[i] = active;
+        Ok(())
+    }
+
+    /// Sets the number of boolean channels in each dataset, such as breaker positions. These are
+    /// encoded as runs of each state, so a channel which rarely changes takes only a few bytes
+    /// per message. The count can only be changed at a message boundary, and the decoder must
+    /// be given the same count.
+    pub fn set_bool_count(&mut self, count: usize) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "boolean channels can only be changed between messages".to_string(),
+            ));
+        }
+        self.bool_history = vec![vec![QualityHistory::default()]; count];
+        self.grow_buffers(self.estimate_size());
+        Ok(())
+    }
+
+    /// Encodes the `f32s` of each sample as the last `scales.len()` variables of the stream,
+    /// following the `i32s`, so the number of variables given to `new` includes them. Each
+    /// float is divided by its scale factor and rounded, such as with a factor of `0.01` for a
+    /// temperature in hundredths of a degree, so the decoded value is within half of the scale
+    /// factor of the original, plus the rounding of the result to `f32`. A value too large for
+    /// the quotient to fit in an `i32` saturates, and NaN is encoded as zero. The decoder must
+    /// be given the same factors. The channels can only be changed at a message boundary.
+    pub fn set_float_channels(&mut self, scales: &[f64]) -> Result<(), JetstreamError> {
+        if self.encoded_samples != 0 {
+            return Err(JetstreamError::InvalidInput(
+                "float channels can only be changed between messages".to_string(),
+            ));
+        }
+        if scales.len() > self.i32_count {
+            return Err(JetstreamError::InvalidInput(format!(
+                "{} float channels do not fit in {} variables",
+                scales.len(),
+                self.i32_count
+            )));
+        }
+        if let Some(scale) = scales.iter().find(|&&s| !(s.is_finite() && s > 0.0)) {
+            return Err(JetstreamError::InvalidInput(format!(
+                "float scale factor {} is not positive",
+                scale
+            )));
+        }
+        self.float_scales = scales.to_vec();
+        Ok(())
+    }
+
+    /// Records the factor converting each variable to engineering units, such as `0.001` for a
+    /// current encoded in mA, for the decoder of the stream to be given with
+    /// `Decoder::set_scale_factors`. The factors are not encoded, and the encoded integers are
+    /// unchanged. The factors default to one.
+    pub fn set_scale_factors(&mut self, scale_factors: &[f64]) -> Result<(), JetstreamError> {
+        if scale_factors.len() != self.i32_count {
+            return Err(JetstreamError::InvalidInput(format!(
+                "{} scale factors given for {} variables",
+                scale_factors.len(),
+                self.i32_count
+            )));
+        }
+        self.scale_factors.copy_from_slice(scale_factors);
+        Ok(())
+    }
+
+    /// The factor converting each variable to engineering units, set by `set_scale_factors`.
+    pub fn scale_factors(&self) -> &[f64] {
+        &self.scale_factors
+    }
+
+    /// Accumulates the minimum, maximum and mean of each variable over the samples encoded
+    /// from now on, across messages, for monitoring the health of the signals. Disabling the
+    /// statistics discards them, and enabling them again starts afresh.
+    pub fn set_channel_stats(&mut self, enabled: bool) {
+        self.channel_stats = enabled.then(|| vec![ChannelStats::default(); self.i32_count]);
+    }
+
+    /// The statistics of variable `i`, or `None` if they are not enabled or no samples of the
+    /// variable have been encoded since. Samples of a variable are not included while it is
+    /// inactive.
+    pub fn channel_stats(&self, i: usize) -> Option<ChannelStats> {
+        self.channel_stats
+            .as_ref()
+            .map(|stats| stats[i])
+            .filter(|stats| stats.samples > 0)
     }
 
     fn encode_single_sample(&mut self, index: usize, value: i32) {
         if self.using_simple8b {
-            self.diffs[index][self.encoded_samples] = bitops::zig_zag_encode64(value as i64)
+            self.diffs[index][self.encoded_samples] = value as i64
         } else {
             self.values[self.encoded_samples][index] = value;
         }
     }
 
     /// Encodes the next set of samples. It is called iteratively until the pre-defined number
-    /// of samples are provided.
-    pub fn encode(&mut self, data: &DatasetWithQuality) -> Result<(Vec<u8>, usize), String> {
-        // encode header and prepare quality values
+    /// of samples are provided, at which point the complete message is returned. `None` is
+    /// returned while samples are still being buffered. Returns an error if the message was
+    /// started by `encode_at`, whose deadline would not account for this sample.
+    pub fn encode(
+        &mut self,
+        data: &DatasetWithQuality,
+    ) -> Result<Option<EncodedMessage>, JetstreamError> {
         if self.encoded_samples == 0 {
-            let id_bytes = self.id.as_bytes().clone();
-            self.buf_mut()[0..16].copy_from_slice(&id_bytes);
-            self.len = 16;
+            self.first_sample_at = None;
+        } else if self.first_sample_at.is_some() {
+            return Err(JetstreamError::InvalidInput(
+                "encode cannot follow encode_at within a message".to_string(),
+            ));
+        }
+        self.encode_sample(data)
+    }
 
-            // encode timestamp
-            let len = self.len;
-            self.buf_mut()[len..len + 8].copy_from_slice(&data.t.to_be_bytes());
-            self.len += 8;
+    // Encodes the next set of samples for `encode` and `encode_at`.
+    fn encode_sample(
+        &mut self,
+        data: &DatasetWithQuality,
+    ) -> Result<Option<EncodedMessage>, JetstreamError> {
+        if let Some(&q) = data.q.iter().find(|&&q| q > self.quality_width.max()) {
+            return Err(JetstreamError::InvalidInput(format!(
+                "quality value {:#x} does not fit in {:?}",
+                q, self.quality_width
+            )));
+        }
+        if data.bools.len() != self.bool_history.len() {
+            return Err(JetstreamError::InvalidInput(format!(
+                "expected {} boolean channels, got {}",
+                self.bool_history.len(),
+                data.bools.len()
+            )));
+        }
+        if data.f32s.len() != self.float_scales.len()
+            || (!self.float_scales.is_empty()
+                && data.i32s.len() + data.f32s.len() != self.i32_count)
+        {
+            return Err(JetstreamError::InvalidInput(format!(
+                "expected {} float channels following {} integer variables, got {} and {}",
+                self.float_scales.len(),
+                self.i32_count - self.float_scales.len(),
+                data.f32s.len(),
+                data.i32s.len()
+            )));
+        }
+
+        // the value of each variable, with any float channels quantized after the integers
+        let mut quantized = core::mem::take(&mut self.quantized);
+        let values: &[i32] = if self.float_scales.is_empty() {
+            &data.i32s
+        } else {
+            quantized.clear();
+            quantized.extend_from_slice(&data.i32s);
+            let floats = data.f32s.iter().zip(&self.float_scales);
+            quantized.extend(floats.map(|(&value, &scale)| quantize(value, scale)));
+            &quantized
+        };
+
+        // record the header timestamp and prepare quality values
+        if let Some(sample_timestamps) = self.sample_timestamps.as_mut() {
+            sample_timestamps[self.encoded_samples] = data.t;
+        }
+        if self.encoded_samples == 0 {
+            self.start_timestamp = data.t;
 
             // record first set of quality
             data.q.iter().enumerate().for_each(|(i, &q)| {
                 self.quality_history[i][0].value = q;
                 self.quality_history[i][0].samples = 1;
             });
+            for (history, &state) in self.bool_history.iter_mut().zip(&data.bools) {
+                history[0].value = state as u32;
+                history[0].samples = 1;
+            }
+            self.segment_start.fill(0);
+            for (range, &value) in self.channel_ranges.iter_mut().zip(values) {
+                *range = (value, value);
+            }
         } else {
             // write the next quality value
             for i in 0..data.q.len() {
+                if !self.active_channels[i] {
+                    continue;
+                }
                 if self.quality_history[i].last().unwrap().value == data.q[i] {
                     self.quality_history[i].last_mut().unwrap().samples += 1;
                 } else {
@@ -152,17 +724,44 @@ impl Encoder {
                         value: data.q[i],
                         samples: 1,
                     });
+                    if self.reset_on_quality_change {
+                        self.segment_start[i] = self.encoded_samples;
+                    }
+                }
+            }
+
+            // extend the current run of each boolean channel, or start the next
+            for (history, &state) in self.bool_history.iter_mut().zip(&data.bools) {
+                let run = history.last_mut().unwrap();
+                if run.value == state as u32 {
+                    run.samples += 1;
+                } else {
+                    history.push(QualityHistory {
+                        value: state as u32,
+                        samples: 1,
+                    });
                 }
             }
         }
 
-        for i in 0..data.i32s.len() {
-            let j = self.encoded_samples; // copy for conciseness
-            let mut val = data.i32s[i];
+        for i in 0..values.len() {
+            if !self.active_channels[i] {
+                continue;
+            }
+            let j = self.encoded_samples - self.segment_start[i]; // samples since delta start
+            let mut val = values[i];
+
+            let range = &mut self.channel_ranges[i];
+            *range = (i32::min(range.0, val), i32::max(range.1, val));
+            if let Some(stats) = self.channel_stats.as_mut() {
+                stats[i].add(val);
+            }
 
-            // check if another data stream is to be used the spatial reference
-            if let Some(spatial_ref_i) = self.spatial_ref[i] {
-                val -= data.i32s[spatial_ref_i];
+            // check if another data stream is to be used the spatial reference; an inactive
+            // variable cannot be a reference, as the decoder does not have its values
+            let active_channels = &self.active_channels;
+            if let Some(spatial_ref_i) = self.spatial_ref[i].filter(|&r| active_channels[r]) {
+                val = val.wrapping_sub(values[spatial_ref_i]);
             }
 
             // prepare data for delta encoding
@@ -170,14 +769,14 @@ impl Encoder {
                 if self.use_xor {
                     self.delta_n[0] = val ^ self.prev_data[0].i32s[i];
                 } else {
-                    self.delta_n[0] = val - self.prev_data[0].i32s[i];
+                    self.delta_n[0] = val.wrapping_sub(self.prev_data[0].i32s[i]);
                 }
             }
             for k in 1..usize::min(j, self.delta_encoding_layers) {
                 if self.use_xor {
                     self.delta_n[k] = self.delta_n[k - 1] ^ self.prev_data[k].i32s[i];
                 } else {
-                    self.delta_n[k] = self.delta_n[k - 1] - self.prev_data[k].i32s[i];
+                    self.delta_n[k] = self.delta_n[k - 1].wrapping_sub(self.prev_data[k].i32s[i]);
                 }
             }
 
@@ -191,25 +790,114 @@ impl Encoder {
                 );
             }
 
-            // save samples and deltas for next iteration
+            // save samples and deltas for next iteration. Each layer k is written at sample k,
+            // before it is first read at sample k + 1, so the state left by a previous message
+            // never needs to be reset.
             self.prev_data[0].i32s[i] = val;
             for k in 1..=usize::min(j, self.delta_encoding_layers - 1) {
                 self.prev_data[k].i32s[i] = self.delta_n[k - 1];
             }
         }
+        self.quantized = quantized;
 
         self.encoded_samples += 1;
         if self.encoded_samples >= self.samples_per_message {
-            self.end_encode()
+            self.end_encode().map(Some)
         } else {
-            Ok((vec![], 0))
+            Ok(None)
+        }
+    }
+
+    /// Like `encode`, but also emits a partial message if the first buffered sample arrived
+    /// at least `flush_timeout` before `now`. The time is supplied by the caller, such as
+    /// from a `Clock`, and only needs to be consistent between calls. Returns an error if the
+    /// message was started by `encode`, as the arrival of its first sample is unknown.
+    pub fn encode_at(
+        &mut self,
+        data: &DatasetWithQuality,
+        now: Duration,
+    ) -> Result<Option<EncodedMessage>, JetstreamError> {
+        if self.encoded_samples == 0 {
+            self.first_sample_at = Some(now);
+        } else if self.first_sample_at.is_none() {
+            return Err(JetstreamError::InvalidInput(
+                "encode_at cannot follow encode within a message".to_string(),
+            ));
+        }
+        match self.encode_sample(data)? {
+            Some(msg) => Ok(Some(msg)),
+            None => self.flush_if_due(now),
         }
     }
 
+    /// Emits a partial message if samples have been buffered by `encode_at` for at least
+    /// `flush_timeout`. This should be called periodically when samples may stop arriving.
+    pub fn flush_if_due(
+        &mut self,
+        now: Duration,
+    ) -> Result<Option<EncodedMessage>, JetstreamError> {
+        match (self.flush_timeout, self.first_sample_at) {
+            (Some(timeout), Some(first_sample_at))
+                if self.encoded_samples > 0 && now.saturating_sub(first_sample_at) >= timeout =>
+            {
+                self.end_encode().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Encodes the next set of samples, returning an empty buffer and a length of zero
+    /// while samples are still being buffered. The length is always equal to the length of
+    /// the buffer, on every encoding path.
+    #[deprecated(note = "use `encode`, which returns `None` while buffering")]
+    pub fn encode_with_length(
+        &mut self,
+        data: &DatasetWithQuality,
+    ) -> Result<(Vec<u8>, usize), JetstreamError> {
+        let (buf, len) = match self.encode(data)? {
+            Some(msg) => {
+                let len = msg.len();
+                (msg.into_bytes(), len)
+            }
+            None => (vec![], 0),
+        };
+        Ok((buf, len))
+    }
+
+    /// The number of samples buffered towards the next message.
+    pub fn buffered_samples(&self) -> usize {
+        self.encoded_samples
+    }
+
+    /// Returns a heartbeat message, which holds only the stream ID and no samples, so that a
+    /// consumer can tell the producer is alive while there is no data to send. Any buffered
+    /// samples are unaffected.
+    pub fn heartbeat(&self) -> EncodedMessage {
+        let mut flags = FLAG_HEARTBEAT;
+        if self.compact_id {
+            flags |= FLAG_COMPACT_ID;
+        }
+        let mut buf = vec![0; 5 + 16];
+        let len = put_uvarint32(&mut buf, flags);
+        buf.truncate(len);
+        buf.extend_from_slice(&self.id.as_bytes()[..self.id_len()]);
+        EncodedMessage::new(buf, 0, self.i32_count)
+    }
+
+    /// Consumes the encoder at the end of a stream, returning any buffered samples as a final,
+    /// shorter message, or `None` if no samples are buffered.
+    pub fn finish(mut self) -> Result<Option<EncodedMessage>, JetstreamError> {
+        if self.encoded_samples == 0 {
+            return Ok(None);
+        }
+        self.end_encode().map(Some)
+    }
+
     /// Ends the encoding early, but does not write to the file.
     pub fn cancel_encode(&mut self) {
         // reset quality history
         self.quality_history = vec![vec![QualityHistory::default()]; self.i32_count];
+        self.bool_history = vec![vec![QualityHistory::default()]; self.bool_history.len()];
 
         // reset previous values
         self.encoded_samples = 0;
@@ -221,24 +909,22 @@ impl Encoder {
         }
     }
 
-    /// Ends the encoding early, and completes the buffer so far.
-    pub fn end_encode(&mut self) -> Result<(Vec<u8>, usize), String> {
-        // write encoded samples
-        let len = self.len;
-        let encoded_samples = self.encoded_samples as i32;
-        self.len += put_varint32(&mut self.buf_mut()[len..], encoded_samples as i32);
-        let actual_header_len = self.len;
-
+    // Writes the first sample of each active variable and the encoded deltas.
+    fn write_samples(&mut self) -> Result<(), JetstreamError> {
         if self.using_simple8b {
             for i in 0..self.diffs.len() {
+                if !self.active_channels[i] {
+                    continue;
+                }
+
                 // ensure slice only contains up to self.encoded_samples
                 let actual_samples = usize::min(self.encoded_samples, self.samples_per_message);
 
-                let number_of_simple8b = simple8b::encode_all_ref(
+                let number_of_simple8b = simple8b::encode_signed(
                     &mut self.simple8b_values,
                     &self.diffs[i][..actual_samples],
                 )
-                .unwrap_or(0);
+                .map_err(|_| JetstreamError::BufferTooSmall)?;
 
                 // calculate efficiency of simple8b
                 // multiply number of simple8b units by 2 because input is 32-bit, output is 64-bit
@@ -247,7 +933,11 @@ impl Encoder {
 
                 for j in 0..number_of_simple8b {
                     let len = self.len;
-                    let simple8b_values = self.simple8b_values[j].to_be_bytes();
+                    let simple8b_values = if self.little_endian {
+                        self.simple8b_values[j].to_le_bytes()
+                    } else {
+                        self.simple8b_values[j].to_be_bytes()
+                    };
                     self.buf_mut()[len..len + 8].copy_from_slice(&simple8b_values);
                     self.len += 8;
                 }
@@ -255,22 +945,36 @@ impl Encoder {
         } else {
             for i in 0..self.encoded_samples {
                 for j in 0..self.i32_count {
+                    if !self.active_channels[j] {
+                        continue;
+                    }
                     let len = self.len;
                     let value = self.values[i][j];
                     self.len += put_varint32(&mut self.buf_mut()[len..], value);
                 }
             }
         }
+        Ok(())
+    }
 
+    // Writes the quality values and the boolean channels, and resets their history.
+    fn write_quality(&mut self) {
         // encode final quality values using RLE
         for i in 0..self.quality_history.len() {
-            // override final number of samples to zero
-            self.quality_history[i].last_mut().unwrap().samples = 0;
+            if !self.active_channels[i] {
+                continue;
+            }
+
+            // override final number of samples to zero, unless the final run is counted
+            if !self.count_final_quality_run {
+                self.quality_history[i].last_mut().unwrap().samples = 0;
+            }
 
             // otherwise, encode each value
             for j in 0..self.quality_history[i].len() {
                 let (len, value) = (self.len, self.quality_history[i][j].value);
-                self.len += put_uvarint32(&mut self.buf_mut()[len..], value);
+                let quality_width = self.quality_width;
+                self.len += quality_width.put(&mut self.buf_mut()[len..], value);
 
                 let (len, samples) = (self.len, self.quality_history[i][j].samples);
                 self.len += put_uvarint32(&mut self.buf_mut()[len..], samples);
@@ -280,38 +984,185 @@ impl Encoder {
         // reset quality history
         self.quality_history = vec![vec![QualityHistory::default()]; self.i32_count];
 
-        // experiment with gzip
-        let out_buf = if self.encoded_samples > USE_GZIP_THRESHOLD_SAMPLES {
-            // do not compress header
-            let out_buf = self.buf()[..actual_header_len].to_vec();
+        // encode each boolean channel as its first state followed by the length of each run,
+        // which alternate in state, with the final run length of zero
+        for i in 0..self.bool_history.len() {
+            let (len, state) = (self.len, self.bool_history[i][0].value as u8);
+            self.buf_mut()[len] = state;
+            self.len += 1;
 
-            let mut gz = GzEncoder::new(out_buf, Compression::best());
-            if let Err(err) = gz.write_all(&self.buf()[actual_header_len..self.len]) {
-                error!(err = as_error!(err); "could not write gz");
+            self.bool_history[i].last_mut().unwrap().samples = 0;
+            for j in 0..self.bool_history[i].len() {
+                let (len, samples) = (self.len, self.bool_history[i][j].samples);
+                self.len += put_uvarint32(&mut self.buf_mut()[len..], samples);
             }
+        }
+        self.bool_history = vec![vec![QualityHistory::default()]; self.bool_history.len()];
+    }
+
+    /// Ends the encoding early, and completes the buffer so far. Returns an error if no
+    /// samples are buffered: a message without samples carries no data, and is not a
+    /// heartbeat, so the end of a stream is left to `finish`, which returns `None` instead.
+    pub fn end_encode(&mut self) -> Result<EncodedMessage, JetstreamError> {
+        if self.encoded_samples == 0 {
+            return Err(JetstreamError::InvalidInput(
+                "no samples to end the message".to_string(),
+            ));
+        }
+
+        // encode header flags
+        let mut flags = 0;
+        if self.sampling_rate != self.nominal_sampling_rate {
+            flags |= FLAG_SAMPLING_RATE;
+        }
+        let epoch = self.epoch.filter(|&epoch| self.start_timestamp >= epoch);
+        if epoch.is_some() {
+            flags |= FLAG_EPOCH_TIMESTAMP;
+        }
+        if self.little_endian {
+            flags |= FLAG_LITTLE_ENDIAN;
+        }
+        if self.sample_timestamps.is_some() {
+            flags |= FLAG_SAMPLE_TIMESTAMPS;
+        }
+        if self.reset_on_quality_change {
+            flags |= FLAG_QUALITY_RESET;
+        }
+        if self.store_channel_ranges {
+            flags |= FLAG_CHANNEL_RANGES;
+        }
+        flags |= self.quality_width.flags();
+        if self.active_channels.contains(&false) {
+            flags |= FLAG_ACTIVE_CHANNELS;
+        }
+        if !self.bool_history.is_empty() {
+            flags |= FLAG_BOOLS;
+        }
+        if self.compact_id {
+            flags |= FLAG_COMPACT_ID;
+        }
+        if self.quality_first {
+            flags |= FLAG_QUALITY_FIRST;
+        } else if self.align_words
+            && self.using_simple8b
+            && self.compression_for(self.encoded_samples) == Compression::None
+        {
+            flags |= FLAG_ALIGNED_WORDS;
+        }
+        self.len = put_uvarint32(&mut self.buf_mut()[..], flags);
+
+        // encode ID
+        let (len, id_len, id) = (self.len, self.id_len(), self.id);
+        self.buf_mut()[len..len + id_len].copy_from_slice(&id.as_bytes()[..id_len]);
+        self.len += id_len;
+
+        // encode the format of the samples
+        let (len, descriptor) = (self.len, self.format_descriptor());
+        self.buf_mut()[len] = descriptor.to_byte();
+        self.len += 1;
+
+        // encode timestamp
+        let (len, start_timestamp) = (self.len, self.start_timestamp);
+        if let Some(epoch) = epoch {
+            self.len += put_uvarint64(&mut self.buf_mut()[len..], start_timestamp - epoch);
+        } else {
+            let start_timestamp = if self.little_endian {
+                start_timestamp.to_le_bytes()
+            } else {
+                start_timestamp.to_be_bytes()
+            };
+            self.buf_mut()[len..len + 8].copy_from_slice(&start_timestamp);
+            self.len += 8;
+        }
+
+        // write encoded samples
+        let len = self.len;
+        let encoded_samples = self.encoded_samples as i32;
+        self.len += put_varint32(&mut self.buf_mut()[len..], encoded_samples);
 
-            match gz.finish() {
-                Err(err) => {
-                    error!(err = as_error!(err); "could not close gz");
-                    vec![]
+        // write the sampling rate, if it differs from the stream rate
+        if flags & FLAG_SAMPLING_RATE != 0 {
+            let (len, sampling_rate) = (self.len, self.sampling_rate as u32);
+            self.len += put_uvarint32(&mut self.buf_mut()[len..], sampling_rate);
+        }
+
+        // write a bitmap of the active variables, if any are inactive
+        if flags & FLAG_ACTIVE_CHANNELS != 0 {
+            let (len, bitmap_len) = (self.len, self.i32_count.div_ceil(8));
+            self.buf_mut()[len..len + bitmap_len].fill(0);
+            for i in 0..self.i32_count {
+                if self.active_channels[i] {
+                    self.buf_mut()[len + i / 8] |= 1 << (i % 8);
                 }
-                Ok(out_buf) => {
-                    // ensure that gzip size is never greater that input for all input sizes
-                    if out_buf.len() > self.len && self.encoded_samples == self.samples_per_message
-                    {
-                        error!(
-                            gz = out_buf.len(),
-                            original = self.len,
-                            samples_per_message = self.samples_per_message;
-                            "gzip encoding length greater"
-                        );
-                    }
-                    out_buf
+            }
+            self.len += bitmap_len;
+        }
+
+        // write the range of each active variable
+        if flags & FLAG_CHANNEL_RANGES != 0 {
+            for i in 0..self.i32_count {
+                if !self.active_channels[i] {
+                    continue;
                 }
+                let (len, (min, max)) = (self.len, self.channel_ranges[i]);
+                self.len += put_varint32(&mut self.buf_mut()[len..], min);
+                let len = self.len;
+                self.len += put_varint32(&mut self.buf_mut()[len..], max);
             }
+        }
+
+        // pad the header to align the simple-8b words; the buffer may hold a previous message
+        if flags & FLAG_ALIGNED_WORDS != 0 {
+            let (len, padding) = (self.len, self.len.next_multiple_of(8) - self.len);
+            self.buf_mut()[len..len + padding].fill(0);
+            self.len += padding;
+        }
+        let actual_header_len = self.len;
+
+        // the quality section is usually tiny, but grows with every change of quality, so make
+        // room for the worst case of a uvarint value and run length for each change, ahead of
+        // the samples if the quality section comes first
+        let quality_size: usize = self.quality_history.iter().map(|h| h.len() * 10).sum();
+        let bools_size: usize = self.bool_history.iter().map(|h| 1 + h.len() * 5).sum();
+        let timestamps_size = if self.sample_timestamps.is_some() {
+            self.encoded_samples * 10
+        } else {
+            0
+        };
+        let samples_size = self.encoded_samples * self.i32_count * 8;
+        let needed = self.len + samples_size + quality_size + bools_size + timestamps_size;
+        if self.buf().len() < needed {
+            // grow geometrically, so that a stream with many changes soon stops reallocating
+            let size = usize::max(needed, self.buf().len() * 2);
+            self.buf_mut().resize(size, 0);
+        }
+
+        // the quality section comes first if requested, so that it can be read alone
+        if flags & FLAG_QUALITY_FIRST != 0 {
+            self.write_quality();
+            self.write_samples()?;
         } else {
-            self.buf()[..self.len].to_vec()
+            self.write_samples()?;
+            self.write_quality();
+        }
+
+        // encode the offset of each sample timestamp from the previous sample
+        if let Some(sample_timestamps) = self.sample_timestamps.take() {
+            for k in 1..self.encoded_samples {
+                let len = self.len;
+                let offset = sample_timestamps[k].wrapping_sub(sample_timestamps[k - 1]);
+                self.len += put_uvarint64(&mut self.buf_mut()[len..], offset);
+            }
+            self.sample_timestamps = Some(sample_timestamps);
+        }
+
+        // compress the payload, if the message is long enough
+        let out_buf = match descriptor.compression {
+            Compression::None => Ok(self.buf()[..self.len].to_vec()),
+            compression => self.compress(compression, actual_header_len),
         };
+        // a message which could not be compressed is dropped, and the next message starts afresh
+        let msg = out_buf.map(|b| EncodedMessage::new(b, self.encoded_samples, self.i32_count));
 
         // reset previous values
         self.encoded_samples = 0;
@@ -323,7 +1174,94 @@ impl Encoder {
         } else {
             self.use_buf_a = true;
         }
-        let len = out_buf.len();
-        Ok((out_buf, len))
+        msg
+    }
+
+    // Compresses the message following the header of `header_len` bytes, which is left
+    // uncompressed so that it can be read without decompressing the payload.
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn compress(
+        &self,
+        compression: Compression,
+        header_len: usize,
+    ) -> Result<Vec<u8>, JetstreamError> {
+        match compression {
+            #[cfg(feature = "std")]
+            Compression::Gzip => self.compress_gzip(header_len),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => self.compress_zstd(header_len),
+            // only supported algorithms can be set
+            #[allow(unreachable_patterns)]
+            _ => Err(JetstreamError::UnsupportedCompression(compression)),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn compress_gzip(&self, header_len: usize) -> Result<Vec<u8>, JetstreamError> {
+        let out_buf = self.buf()[..header_len].to_vec();
+
+        let mut gz = GzEncoder::new(out_buf, flate2::Compression::best());
+        gz.write_all(&self.buf()[header_len..self.len])
+            .map_err(JetstreamError::Gzip)?;
+        let out_buf = gz.finish().map_err(JetstreamError::Gzip)?;
+
+        // ensure that gzip size is never greater that input for all input sizes
+        if out_buf.len() > self.len && self.encoded_samples == self.samples_per_message {
+            error!(
+                gz = out_buf.len(),
+                original = self.len,
+                samples_per_message = self.samples_per_message;
+                "gzip encoding length greater"
+            );
+        }
+        Ok(out_buf)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compress_zstd(&self, header_len: usize) -> Result<Vec<u8>, JetstreamError> {
+        let payload = &self.buf()[header_len..self.len];
+        let compressed = zstd::bulk::compress(payload, zstd::DEFAULT_COMPRESSION_LEVEL)
+            .map_err(JetstreamError::Zstd)?;
+
+        let mut out_buf = Vec::with_capacity(header_len + compressed.len());
+        out_buf.extend_from_slice(&self.buf()[..header_len]);
+        out_buf.extend_from_slice(&compressed);
+        Ok(out_buf)
+    }
+}
+
+/// Encodes every sample in `data` as a single message, with a stream-agreed sampling rate of
+/// `sampling_rate`. This is a shortcut for encoding a captured record in one step.
+pub fn encode_message(
+    id: Uuid,
+    i32_count: usize,
+    sampling_rate: usize,
+    data: &[DatasetWithQuality],
+) -> Result<EncodedMessage, JetstreamError> {
+    if data.is_empty() {
+        return Err(JetstreamError::InvalidInput(
+            "no samples to encode".to_string(),
+        ));
+    }
+    let mut enc = Encoder::new(id, i32_count, sampling_rate, data.len());
+    let mut messages = encode_all_messages(&mut enc, data)?;
+    Ok(messages.remove(0))
+}
+
+/// Encodes every sample in `data`, returning each completed message in order. Any trailing
+/// samples which do not fill a message are flushed as a final, shorter message.
+pub fn encode_all_messages(
+    enc: &mut Encoder,
+    data: &[DatasetWithQuality],
+) -> Result<Vec<EncodedMessage>, JetstreamError> {
+    let mut messages = Vec::with_capacity(data.len() / enc.samples_per_message + 1);
+    for d in data {
+        if let Some(msg) = enc.encode(d)? {
+            messages.push(msg);
+        }
+    }
+    if enc.encoded_samples > 0 {
+        messages.push(enc.end_encode()?);
     }
+    Ok(messages)
 }
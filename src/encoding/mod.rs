@@ -16,3 +16,5 @@
 // If not, see <https://www.gnu.org/licenses/>.
 pub mod bitops;
 pub mod simple8b;
+#[cfg(test)]
+mod test;
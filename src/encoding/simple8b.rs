@@ -1,4 +1,8 @@
-use rand_distr::num_traits::ToPrimitive;
+use crate::encoding::bitops;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 
 /// Implements the 64bit integer encoding algorithm as published by Ann and Moffat in
 /// "Index compression using 64-bit words", Softw. Pract. Exper. 2010; 40:131–147
@@ -49,107 +53,420 @@ const SELECTOR: [Packing; 16] = [
     Packing { n: 1, bit: 60 },
 ];
 
-pub fn for_each<F>(mut b: &[u8], mut f: F) -> Result<usize, String>
+/// Calls `f` with each value packed in the big-endian words of `b`, stopping early if `f`
+/// returns false. Returns the number of words read.
+///
+/// The count is always of whole words: if `f` stops partway through the values of a word,
+/// that word is still counted, so `8 * count` is the offset of the first byte not belonging
+/// to a word that was read. Callers rely on this to locate data following the words.
+pub fn for_each<F>(b: &[u8], f: F) -> Result<usize, String>
+where
+    F: FnMut(u64) -> bool,
+{
+    for_each_word(b, u64::from_be_bytes, f)
+}
+
+/// Like `for_each`, but for words stored in little-endian byte order.
+pub fn for_each_le<F>(b: &[u8], f: F) -> Result<usize, String>
 where
     F: FnMut(u64) -> bool,
 {
+    for_each_word(b, u64::from_le_bytes, f)
+}
+
+fn for_each_word<F>(mut b: &[u8], from_bytes: fn([u8; 8]) -> u64, mut f: F) -> Result<usize, String>
+where
+    F: FnMut(u64) -> bool,
+{
+    let mut values = [0; 240];
     let mut count = 0;
     while b.len() >= 8 {
-        let mut v = u64::from_be_bytes(b[..8].try_into().unwrap());
+        let v = from_bytes(b[..8].try_into().unwrap());
         b = &b[8..];
         count += 1;
 
-        let sel = (v >> 60).to_usize().unwrap();
-        if sel >= 16 {
-            return Err(format!("invalid selector value: {}", sel));
+        let n = unpack(v, &mut values);
+        for &val in &values[..n] {
+            if !f(val) {
+                return Ok(count);
+            }
         }
+    }
+    Ok(count)
+}
 
-        let n = SELECTOR[sel].n;
-        let bits = SELECTOR[sel].bit; // as usize;
+// Unpacks the values of a single word into dst, returning the number of values.
+fn unpack(mut v: u64, dst: &mut [u64; 240]) -> usize {
+    let sel = (v >> 60) as usize;
+    let n = SELECTOR[sel].n;
+    let bits = SELECTOR[sel].bit;
 
-        let mask = (!((!0 as i64) << bits)) as u64;
+    // selectors 0 and 1 store runs of the value 1 using no bits
+    if bits == 0 {
+        dst[..n].fill(1);
+        return n;
+    }
 
-        for _ in 0..n {
-            let val = v & mask;
-            if !f(val) {
-                return Ok(count);
+    let mask = (1u64 << bits) - 1;
+    for d in &mut dst[..n] {
+        *d = v & mask;
+        v >>= bits;
+    }
+    n
+}
+
+/// Packs values into big-endian words as they are written, for producers which do not hold
+/// all of the values at once. Values are buffered until enough have accumulated to fill a word
+/// with the largest packing. A run of ones fills a word as soon as 240 have been buffered, so
+/// the words may differ from `encode_all`, but decode to the same values.
+pub struct Encoder {
+    // the values waiting to be packed, from head to tail
+    buf: [u64; 240],
+    head: usize,
+    tail: usize,
+    bytes: Vec<u8>,
+    err: Option<String>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; 240],
+            head: 0,
+            tail: 0,
+            bytes: vec![],
+            err: None,
+        }
+    }
+
+    /// Buffers a value, packing a word first if the buffer is full. A value over 1 << 60 is
+    /// reported as an error by `bytes`.
+    pub fn write(&mut self, v: u64) {
+        if self.tail == self.buf.len() {
+            self.flush();
+            self.buf.copy_within(self.head..self.tail, 0);
+            self.tail -= self.head;
+            self.head = 0;
+        }
+        self.buf[self.tail] = v;
+        self.tail += 1;
+    }
+
+    /// Packs any buffered values and returns the words written so far.
+    pub fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        while self.head < self.tail {
+            self.flush();
+        }
+        match &self.err {
+            Some(err) => Err(err.clone()),
+            None => Ok(self.bytes.clone()),
+        }
+    }
+
+    // Packs a single word from the buffered values. The values are dropped if they cannot be
+    // packed, and the error kept for `bytes`.
+    fn flush(&mut self) {
+        match pack_next(&self.buf[self.head..self.tail]) {
+            Ok((packed, n)) => {
+                self.bytes.extend(packed.to_be_bytes());
+                self.head += n;
+            }
+            Err(err) => {
+                self.err.get_or_insert(err);
+                self.head = self.tail;
+            }
+        }
+    }
+}
+
+/// Reads the values packed in big-endian words one at a time, in the same way as `for_each`.
+/// Call `next` to move to each value, then `read` to get it. Trailing bytes which do not form
+/// a whole word are ignored.
+pub struct Decoder {
+    bytes: Vec<u8>,
+    // the offset of the next word
+    pos: usize,
+    // the values of the current word, and the index of the next to be read
+    values: [u64; 240],
+    i: usize,
+    n: usize,
+    current: u64,
+}
+
+impl Decoder {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            values: [0; 240],
+            i: 0,
+            n: 0,
+            current: 0,
+        }
+    }
+
+    /// Moves to the next value, returning false once every word has been read.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        if self.i == self.n {
+            if self.bytes.len() - self.pos < 8 {
+                return false;
+            }
+            let word = self.bytes[self.pos..self.pos + 8].try_into().unwrap();
+            self.n = unpack(u64::from_be_bytes(word), &mut self.values);
+            self.i = 0;
+            self.pos += 8;
+        }
+        self.current = self.values[self.i];
+        self.i += 1;
+        true
+    }
+
+    /// The value moved to by the last call to `next`, or zero before the first call.
+    pub fn read(&self) -> u64 {
+        self.current
+    }
+}
+
+/// Returns the number of values packed in the big-endian words of `b`. Trailing bytes which do
+/// not form a whole word are ignored, as in `for_each`.
+pub fn count_bytes(b: &[u8]) -> Result<usize, String> {
+    Ok(b.chunks_exact(8)
+        .map(|word| SELECTOR[(word[0] >> 4) as usize].n)
+        .sum())
+}
+
+/// Returns the number of values packed in the big-endian words of `b` which are at least
+/// `min` and less than `max`. The lower bound is inclusive and the upper bound exclusive, so
+/// a single value `v` is matched by `v, v + 1`, and no values are matched if `max <= min`.
+pub fn count_bytes_between(b: &[u8], min: u64, max: u64) -> Result<usize, String> {
+    let mut count = 0;
+    for_each(b, |v| {
+        if v >= min && v < max {
+            count += 1;
+        }
+        true
+    })?;
+    Ok(count)
+}
+
+/// Decodes the big-endian words of `src` into `dst` until it is full. Returns the number of
+/// words read, counted in the same way as `for_each`, or an error if there are too few values
+/// to fill `dst`.
+pub fn decode_all(dst: &mut [u64], src: &[u8]) -> Result<usize, String> {
+    decode_words(dst, src, u64::from_be_bytes, |v| v)
+}
+
+/// Decodes the big-endian words of `src` into `dst` until it is full, zig-zag decoding each
+/// value. Returns the number of words read, counted in the same way as `for_each`. The output
+/// matches `zig_zag_decode64(v) as i32` for each value `v` from `for_each`, without the
+/// per-value callback.
+pub fn decode_all_i32(dst: &mut [i32], src: &[u8]) -> Result<usize, String> {
+    decode_words(dst, src, u64::from_be_bytes, |v| {
+        bitops::zig_zag_decode64(v) as i32
+    })
+}
+
+/// Like `decode_all_i32`, but for words stored in little-endian byte order.
+pub fn decode_all_i32_le(dst: &mut [i32], src: &[u8]) -> Result<usize, String> {
+    decode_words(dst, src, u64::from_le_bytes, |v| {
+        bitops::zig_zag_decode64(v) as i32
+    })
+}
+
+fn decode_words<T, F>(
+    dst: &mut [T],
+    src: &[u8],
+    from_bytes: fn([u8; 8]) -> u64,
+    decode: F,
+) -> Result<usize, String>
+where
+    F: Fn(u64) -> T,
+    T: Copy,
+{
+    let mut i = 0;
+    let mut count = 0;
+    for word in src.chunks_exact(8) {
+        if i >= dst.len() {
+            break;
+        }
+        let v = from_bytes(word.try_into().unwrap());
+        count += 1;
+
+        let sel = (v >> 60) as usize;
+        let n = usize::min(SELECTOR[sel].n, dst.len() - i);
+        let bits = SELECTOR[sel].bit;
+        let out = &mut dst[i..i + n];
+        if bits == 0 {
+            // selectors 0 and 1 store runs of the value 1
+            out.fill(decode(1));
+        } else {
+            let mask = (1u64 << bits) - 1;
+            for (k, d) in out.iter_mut().enumerate() {
+                *d = decode((v >> (k * bits)) & mask);
             }
-            v = v >> bits
         }
+        i += n;
+    }
+
+    if i < dst.len() {
+        return Err(format!("expected {} values, found {}", dst.len(), i));
     }
     Ok(count)
 }
 
+/// Packs the values from src into big-endian words, the layout read by `for_each` and
+/// `decode_all`. If a value is over 1 << 60, an error is returned.
+pub fn encode_all(src: &[u64]) -> Result<Vec<u8>, String> {
+    let mut words = vec![0; src.len()];
+    let n = encode_all_ref(&mut words, src)?;
+    Ok(words[..n].iter().flat_map(|w| w.to_be_bytes()).collect())
+}
+
 /// Returns a packed slice of the values from src.  If a value is over
 /// 1 << 60, an error is returned.
 pub fn encode_all_ref(dst: &mut [u64], src: &[u64]) -> Result<usize, String> {
     let mut i = 0;
     let mut j = 0;
 
-    loop {
-        if i >= src.len() {
-            break;
+    while i < src.len() {
+        let (packed, n) = pack_next(&src[i..])?;
+        dst[j] = packed;
+        i += n;
+        j += 1;
+    }
+    Ok(j)
+}
+
+/// Zig-zag encodes the signed values from src and returns them packed into dst, which must be
+/// at least as long as src because it is also used to hold the zig-zag encoded values. If an
+/// encoded value is over 1 << 60, an error is returned.
+pub fn encode_all_signed(dst: &mut [u64], src: &[i64]) -> Result<usize, String> {
+    if dst.len() < src.len() {
+        return Err("destination too small".to_string());
+    }
+    for (d, &v) in dst.iter_mut().zip(src) {
+        *d = bitops::zig_zag_encode64(v);
+    }
+
+    // pack in place, each word is only written after the values it consumes have been read
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < src.len() {
+        let (packed, n) = pack_next(&dst[i..src.len()])?;
+        dst[j] = packed;
+        i += n;
+        j += 1;
+    }
+    Ok(j)
+}
+
+/// Zig-zag encodes and packs the signed values from src into dst in a single pass, without
+/// storing the zig-zag encoded values. The output is identical to `encode_all_signed`, but
+/// dst only needs to be large enough to hold the packed words.
+pub fn encode_signed(dst: &mut [u64], src: &[i64]) -> Result<usize, String> {
+    // a window of zig-zag encoded values, holding at least as many as a single word can pack
+    let mut window = [0; 120];
+    let mut start = 0;
+    let mut end = 0;
+
+    let mut i = 0;
+    let mut j = 0;
+    while i < src.len() {
+        if j >= dst.len() {
+            return Err("destination too small".to_string());
         }
-        let remaining = &src[i..];
-
-        if can_pack(remaining, 240, 0) {
-            dst[j] = 0;
-            i += 240;
-        } else if can_pack(remaining, 120, 0) {
-            dst[j] = 1 << 60;
-            i += 120;
-        } else if can_pack(remaining, 60, 1) {
-            dst[j] = pack60(&src[i..i + 60]);
-            i += 60;
-        } else if can_pack(remaining, 30, 2) {
-            dst[j] = pack30(&src[i..i + 30]);
-            i += 30;
-        } else if can_pack(remaining, 20, 3) {
-            dst[j] = pack20(&src[i..i + 20]);
-            i += 20;
-        } else if can_pack(remaining, 15, 4) {
-            dst[j] = pack15(&src[i..i + 15]);
-            i += 15;
-        } else if can_pack(remaining, 12, 5) {
-            dst[j] = pack12(&src[i..i + 12]);
-            i += 12;
-        } else if can_pack(remaining, 10, 6) {
-            dst[j] = pack10(&src[i..i + 10]);
-            i += 10;
-        } else if can_pack(remaining, 8, 7) {
-            dst[j] = pack8(&src[i..i + 8]);
-            i += 8;
-        } else if can_pack(remaining, 7, 8) {
-            dst[j] = pack7(&src[i..i + 7]);
-            i += 7;
-        } else if can_pack(remaining, 6, 10) {
-            dst[j] = pack6(&src[i..i + 6]);
-            i += 6;
-        } else if can_pack(remaining, 5, 12) {
-            dst[j] = pack5(&src[i..i + 5]);
-            i += 5;
-        } else if can_pack(remaining, 4, 15) {
-            dst[j] = pack4(&src[i..i + 4]);
-            i += 4;
-        } else if can_pack(remaining, 3, 20) {
-            dst[j] = pack3(&src[i..i + 3]);
-            i += 3;
-        } else if can_pack(remaining, 2, 30) {
-            dst[j] = pack2(&src[i..i + 2]);
-            i += 2;
-        } else if can_pack(remaining, 1, 60) {
-            dst[j] = pack1(&src[i..i + 1]);
-            i += 1;
-        } else {
-            return Err("value out of bounds".to_string());
+
+        // runs of 1 are only used if every remaining value is 1, matching `can_pack`
+        let remaining = src.len() - i;
+        if remaining >= 120 && src[i..].iter().all(|&v| bitops::zig_zag_encode64(v) == 1) {
+            let n = if remaining >= 240 { 240 } else { 120 };
+            dst[j] = if n == 240 { 0 } else { 1 << 60 };
+            i += n;
+            j += 1;
+            start = 0;
+            end = 0;
+            continue;
+        }
+
+        // top up the window
+        if end - start < 60 {
+            window.copy_within(start..end, 0);
+            end -= start;
+            start = 0;
+            let fill = usize::min(window.len() - end, src.len() - (i + end));
+            for (w, &v) in window[end..end + fill].iter_mut().zip(&src[i + end..]) {
+                *w = bitops::zig_zag_encode64(v);
+            }
+            end += fill;
         }
+
+        let (packed, n) = pack_bits(&window[start..end])?;
+        dst[j] = packed;
+        start += n;
+        i += n;
         j += 1;
     }
     Ok(j)
 }
 
+// Packs as many values from the start of src as possible into a single word, returning the
+// word and the number of values consumed.
+fn pack_next(src: &[u64]) -> Result<(u64, usize), String> {
+    if can_pack(src, 240, 0) {
+        Ok((0, 240))
+    } else if can_pack(src, 120, 0) {
+        Ok((1 << 60, 120))
+    } else {
+        pack_bits(src)
+    }
+}
+
+// Packs values from the start of src using the selectors which store each value in one or
+// more bits, returning the word and the number of values consumed.
+fn pack_bits(src: &[u64]) -> Result<(u64, usize), String> {
+    Ok(if can_pack(src, 60, 1) {
+        (pack60(&src[..60]), 60)
+    } else if can_pack(src, 30, 2) {
+        (pack30(&src[..30]), 30)
+    } else if can_pack(src, 20, 3) {
+        (pack20(&src[..20]), 20)
+    } else if can_pack(src, 15, 4) {
+        (pack15(&src[..15]), 15)
+    } else if can_pack(src, 12, 5) {
+        (pack12(&src[..12]), 12)
+    } else if can_pack(src, 10, 6) {
+        (pack10(&src[..10]), 10)
+    } else if can_pack(src, 8, 7) {
+        (pack8(&src[..8]), 8)
+    } else if can_pack(src, 7, 8) {
+        (pack7(&src[..7]), 7)
+    } else if can_pack(src, 6, 10) {
+        (pack6(&src[..6]), 6)
+    } else if can_pack(src, 5, 12) {
+        (pack5(&src[..5]), 5)
+    } else if can_pack(src, 4, 15) {
+        (pack4(&src[..4]), 4)
+    } else if can_pack(src, 3, 20) {
+        (pack3(&src[..3]), 3)
+    } else if can_pack(src, 2, 30) {
+        (pack2(&src[..2]), 2)
+    } else if can_pack(src, 1, 60) {
+        (pack1(&src[..1]), 1)
+    } else {
+        return Err("value out of bounds".to_string());
+    })
+}
+
 // Returns true if n elements from in can be stored using bits per element.
-fn can_pack(src: &[u64], n: usize, bits: usize) -> bool {
+pub(crate) fn can_pack(src: &[u64], n: usize, bits: usize) -> bool {
     if src.len() < n {
         return false;
     }
@@ -169,7 +486,8 @@ fn can_pack(src: &[u64], n: usize, bits: usize) -> bool {
         return true;
     }
 
-    let max = ((1_u64.wrapping_shl(bits as u32)) - 1) as u64;
+    // the largest value with the low `bits` set, for 1 to 64 bits, without overflowing
+    let max = u64::MAX >> (64 - bits);
 
     for i in 0..end {
         if src[i] > max {
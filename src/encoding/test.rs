@@ -0,0 +1,340 @@
+use crate::encoding::{bitops, simple8b};
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[test]
+fn test_encode_all_signed() {
+    let src: Vec<i64> = (0..500).map(|i| (i % 7) - 6 - (i % 40) * 1000).collect();
+    let zig_zagged: Vec<u64> = src.iter().map(|&v| bitops::zig_zag_encode64(v)).collect();
+
+    let mut expected = vec![0; src.len()];
+    let n_expected = simple8b::encode_all_ref(&mut expected, &zig_zagged).unwrap();
+
+    let mut actual = vec![0; src.len()];
+    let n_actual = simple8b::encode_all_signed(&mut actual, &src).unwrap();
+
+    assert_eq!(n_expected, n_actual);
+    assert_eq!(expected[..n_expected], actual[..n_actual]);
+}
+
+#[test]
+fn test_encode_all_signed_dst_too_small() {
+    let src: Vec<i64> = vec![-1; 10];
+    let mut dst = vec![0; 9];
+    assert!(simple8b::encode_all_signed(&mut dst, &src).is_err());
+}
+
+#[test]
+fn test_for_each_counts_whole_words() {
+    // 30 values of 2 bits pack into a single word, and 60-bit values one per word
+    let mut src = vec![1; 30];
+    src.extend([1 << 59, 1 << 58]);
+
+    let mut words = vec![0; src.len()];
+    let n = simple8b::encode_all_ref(&mut words, &src).unwrap();
+    assert_eq!(n, 3);
+
+    // follow the words with unrelated data, such as the quality section
+    let mut buf: Vec<u8> = words[..n].iter().flat_map(|w| w.to_be_bytes()).collect();
+    buf.extend([0xaa, 0xbb, 0xcc]);
+
+    // stopping partway through the first word still counts the whole word
+    let mut seen = 0;
+    let count = simple8b::for_each(&buf, |_| {
+        seen += 1;
+        seen < 10
+    })
+    .unwrap();
+    assert_eq!((seen, count), (10, 1));
+
+    // stopping at the end of the final word does not consume the trailing data
+    let mut values = vec![];
+    let count = simple8b::for_each(&buf, |v| {
+        values.push(v);
+        values.len() < src.len()
+    })
+    .unwrap();
+    assert_eq!(values, src);
+    assert_eq!(count * 8, buf.len() - 3);
+    assert_eq!(buf[count * 8..], [0xaa, 0xbb, 0xcc]);
+}
+
+#[test]
+fn test_encode_signed() {
+    let inputs: Vec<Vec<i64>> = vec![
+        (0..500).map(|i| (i % 7) - 6 - (i % 40) * 1000).collect(),
+        vec![-1; 300],
+        vec![-1; 150],
+        (0..100)
+            .map(|i| if i % 17 == 0 { 1 << 40 } else { i % 3 })
+            .collect(),
+        vec![(1 << 59) - 1, -(1 << 59), 0, 5],
+        vec![],
+    ];
+
+    for src in inputs {
+        let mut expected = vec![0; src.len()];
+        let n_expected = simple8b::encode_all_signed(&mut expected, &src).unwrap();
+
+        let mut actual = vec![0; src.len()];
+        let n_actual = simple8b::encode_signed(&mut actual, &src).unwrap();
+
+        assert_eq!(n_expected, n_actual);
+        assert_eq!(expected[..n_expected], actual[..n_actual]);
+
+        // only space for the packed words is needed
+        let mut exact = vec![0; n_expected];
+        assert_eq!(simple8b::encode_signed(&mut exact, &src), Ok(n_expected));
+        if n_expected > 0 {
+            let mut short = vec![0; n_expected - 1];
+            assert!(simple8b::encode_signed(&mut short, &src).is_err());
+        }
+    }
+
+    let mut dst = vec![0; 2];
+    assert!(simple8b::encode_signed(&mut dst, &[0, 1 << 60]).is_err());
+}
+
+#[test]
+fn test_decode_all_i32() {
+    // include runs of -1, which use the selectors storing runs of 1
+    let mut src: Vec<i64> = (0..500).map(|i| (i % 7) - 3 - (i % 40) * 1000).collect();
+    src.extend([-1; 300]);
+    src.extend([i32::MIN as i64, i32::MAX as i64]);
+    src.extend(0..10);
+
+    let mut words = vec![0; src.len()];
+    let n = simple8b::encode_all_signed(&mut words, &src).unwrap();
+    let be: Vec<u8> = words[..n].iter().flat_map(|w| w.to_be_bytes()).collect();
+    let le: Vec<u8> = words[..n].iter().flat_map(|w| w.to_le_bytes()).collect();
+
+    let mut expected = vec![];
+    simple8b::for_each(&be, |v| {
+        expected.push(bitops::zig_zag_decode64(v) as i32);
+        true
+    })
+    .unwrap();
+
+    for (buf, decode_all) in [
+        (&be, simple8b::decode_all_i32 as fn(&mut [i32], &[u8]) -> _),
+        (&le, simple8b::decode_all_i32_le),
+    ] {
+        let mut actual = vec![0; src.len()];
+        assert_eq!(decode_all(&mut actual, buf).unwrap(), n);
+        assert_eq!(actual, expected);
+
+        // stopping partway through the final word still counts it
+        assert_eq!(decode_all(&mut actual[..src.len() - 1], buf).unwrap(), n);
+
+        // there are not enough words to fill a larger output
+        let mut too_many = vec![0; src.len() + 1];
+        assert!(decode_all(&mut too_many, buf).is_err());
+    }
+}
+
+#[test]
+fn test_count_bytes() {
+    let mut src: Vec<u64> = (0..100).collect();
+    src.extend([1; 240]);
+    let buf = simple8b::encode_all(&src).unwrap();
+    assert_eq!(simple8b::count_bytes(&buf).unwrap(), src.len());
+
+    // a trailing partial word is ignored
+    let mut partial = buf.clone();
+    partial.extend([0xff; 7]);
+    assert_eq!(simple8b::count_bytes(&partial).unwrap(), src.len());
+    assert_eq!(simple8b::count_bytes(&[]).unwrap(), 0);
+}
+
+#[test]
+fn test_count_bytes_between() {
+    let mut src: Vec<u64> = (0..100).collect();
+    src.extend([1; 240]);
+    let buf = simple8b::encode_all(&src).unwrap();
+
+    // the lower bound is inclusive and the upper bound exclusive
+    assert_eq!(simple8b::count_bytes_between(&buf, 10, 20).unwrap(), 10);
+    assert_eq!(simple8b::count_bytes_between(&buf, 1, 2).unwrap(), 241);
+
+    // a range matching nothing, and an empty range
+    assert_eq!(simple8b::count_bytes_between(&buf, 100, 1000).unwrap(), 0);
+    assert_eq!(simple8b::count_bytes_between(&buf, 50, 50).unwrap(), 0);
+    assert_eq!(simple8b::count_bytes_between(&buf, 60, 50).unwrap(), 0);
+
+    // a range covering every value
+    assert_eq!(
+        simple8b::count_bytes_between(&buf, 0, u64::MAX).unwrap(),
+        src.len()
+    );
+}
+
+#[test]
+fn test_count_bytes_between_skip_min() {
+    let mut src: Vec<u64> = (0..8).collect();
+    src.push(100000);
+    let buf = simple8b::encode_all(&src).unwrap();
+
+    assert_eq!(
+        simple8b::count_bytes_between(&buf, 100000, 100001).unwrap(),
+        1
+    );
+    assert_eq!(simple8b::count_bytes_between(&buf, 1, 100000).unwrap(), 7);
+}
+
+#[test]
+fn test_can_pack_boundaries() {
+    // the number of values and bits per value of each selector which packs values other than 1
+    let selectors = [
+        (60, 1),
+        (30, 2),
+        (20, 3),
+        (15, 4),
+        (12, 5),
+        (10, 6),
+        (8, 7),
+        (7, 8),
+        (6, 10),
+        (5, 12),
+        (4, 15),
+        (3, 20),
+        (2, 30),
+        (1, 60),
+    ];
+    for (n, bits) in selectors {
+        let max = (1_u64 << bits) - 1;
+        let mut src = vec![max; n];
+        assert!(simple8b::can_pack(&src, n, bits), "{} bits", bits);
+
+        // a single value one above the maximum, in any position, is rejected
+        for i in 0..n {
+            src[i] = max + 1;
+            assert!(!simple8b::can_pack(&src, n, bits), "{} bits at {}", bits, i);
+            src[i] = max;
+        }
+
+        // values beyond the first n are not checked, and too few values cannot be packed
+        src.push(max + 1);
+        assert!(simple8b::can_pack(&src, n, bits), "{} bits", bits);
+        assert!(!simple8b::can_pack(&src[..n - 1], n, bits), "{} bits", bits);
+    }
+
+    // runs of 1 require every value to be 1
+    assert!(simple8b::can_pack(&[1; 240], 240, 0));
+    assert!(!simple8b::can_pack(&[1; 239], 240, 0));
+    let mut ones = vec![1; 240];
+    ones[239] = 0;
+    assert!(!simple8b::can_pack(&ones, 240, 0));
+    ones[239] = 2;
+    assert!(!simple8b::can_pack(&ones, 120, 0));
+}
+
+#[test]
+fn test_encode_all_each_selector() {
+    // the number of values and bits of each selector, from the table in `simple8b`
+    let selectors = [
+        (240, 0),
+        (120, 0),
+        (60, 1),
+        (30, 2),
+        (20, 3),
+        (15, 4),
+        (12, 5),
+        (10, 6),
+        (8, 7),
+        (7, 8),
+        (6, 10),
+        (5, 12),
+        (4, 15),
+        (3, 20),
+        (2, 30),
+        (1, 60),
+    ];
+    for (sel, &(n, bits)) in selectors.iter().enumerate() {
+        // alternate the largest value for the width with zero, or a run of ones for no bits
+        let src: Vec<u64> = if bits == 0 {
+            vec![1; n]
+        } else {
+            (0..n)
+                .map(|k| if k % 2 == 0 { (1 << bits) - 1 } else { 0 })
+                .collect()
+        };
+
+        let buf = simple8b::encode_all(&src).unwrap();
+        assert_eq!(buf.len(), 8, "selector {}", sel);
+        assert_eq!((buf[0] >> 4) as usize, sel, "selector {}", sel);
+        assert_eq!(simple8b::count_bytes(&buf).unwrap(), n, "selector {}", sel);
+        assert_eq!(
+            simple8b::count_bytes_between(&buf, 1, u64::MAX).unwrap(),
+            src.iter().filter(|&&v| v >= 1).count(),
+            "selector {}",
+            sel
+        );
+
+        let mut dst = vec![0; n];
+        assert_eq!(simple8b::decode_all(&mut dst, &buf).unwrap(), 1);
+        assert_eq!(dst, src, "selector {}", sel);
+
+        // the words match those read by `for_each`
+        let mut values = vec![];
+        simple8b::for_each(&buf, |v| {
+            values.push(v);
+            true
+        })
+        .unwrap();
+        assert_eq!(values, src, "selector {}", sel);
+    }
+
+    // values must fit in 60 bits, and too few values is an error
+    assert!(simple8b::encode_all(&[1 << 60]).is_err());
+    let buf = simple8b::encode_all(&[1, 2, 3]).unwrap();
+    assert!(simple8b::decode_all(&mut [0; 4], &buf).is_err());
+}
+
+#[test]
+fn test_streaming_encoder_decoder() {
+    // runs of ones, values of every width, and enough values to pack many words
+    let mut src: Vec<u64> = (0..1000).map(|i| (i * 7919) % (1 << (i % 61))).collect();
+    src.extend([1; 300]);
+    src.extend([0, 1 << 59, 3]);
+
+    let mut enc = simple8b::Encoder::new();
+    for &v in &src {
+        enc.write(v);
+    }
+    let bytes = enc.bytes().unwrap();
+    assert_eq!(simple8b::count_bytes(&bytes).unwrap(), src.len());
+
+    let mut dec = simple8b::Decoder::new(bytes.clone());
+    let mut values = vec![];
+    while dec.next() {
+        values.push(dec.read());
+    }
+    assert_eq!(values, src);
+    assert!(!dec.next());
+
+    // without runs of ones, the words match those packed in one pass
+    let src: Vec<u64> = (0..1000).map(|i| i % 50).collect();
+    let mut enc = simple8b::Encoder::default();
+    src.iter().for_each(|&v| enc.write(v));
+    assert_eq!(enc.bytes().unwrap(), simple8b::encode_all(&src).unwrap());
+
+    // an empty encoder has no words, and trailing bytes are ignored by the decoder
+    assert!(simple8b::Encoder::new().bytes().unwrap().is_empty());
+    let mut dec = simple8b::Decoder::new(vec![0xaa; 7]);
+    assert!(!dec.next());
+}
+
+#[test]
+fn test_streaming_encoder_value_too_large() {
+    let mut enc = simple8b::Encoder::new();
+    enc.write(1);
+    enc.write(1 << 60);
+    enc.write(2);
+    assert!(enc.bytes().is_err());
+
+    // the error is also reported once the buffer fills
+    let mut enc = simple8b::Encoder::new();
+    enc.write(u64::MAX);
+    (0..1000).for_each(|v| enc.write(v));
+    assert!(enc.bytes().is_err());
+}
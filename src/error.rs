@@ -0,0 +1,99 @@
+use crate::jetstream::Compression;
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+use uuid::Uuid;
+
+/// Errors returned when encoding or decoding a message.
+#[derive(Debug)]
+pub enum JetstreamError {
+    /// The message ID did not match the decoder ID.
+    IdMismatch { expected: Uuid, got: Uuid },
+    /// A container holds a message from a stream which none of the decoders are for.
+    UnknownStream(Uuid),
+    /// The message ended early, for example if it was cut short in transit. Receiving the
+    /// message again may succeed. `needed` is the length required to read the field which was
+    /// cut short, which is a lower bound for a variable length field, and `got` is the length
+    /// available. Within a compressed payload, these are lengths of the decompressed payload.
+    Truncated { needed: usize, got: usize },
+    /// The encoded samples do not fit in the encoder's buffer.
+    BufferTooSmall,
+    /// The simple-8b words of the message could not be decoded.
+    Simple8bDecode(String),
+    /// The compressed payload is invalid and the message should be dropped.
+    #[cfg(feature = "std")]
+    Gzip(io::Error),
+    /// The compressed payload is invalid and the message should be dropped.
+    #[cfg(feature = "zstd")]
+    Zstd(io::Error),
+    /// The compression of a message, or the compression given to an encoder, needs a feature
+    /// which is not enabled: `std` for gzip, or `zstd` for zstd.
+    UnsupportedCompression(Compression),
+    /// The message does not match the decoder's configuration and should be dropped.
+    Corrupt(String),
+    /// The message timestamp is relative to the stream epoch, but no epoch has been set.
+    MissingEpoch,
+    /// A quality value sets reserved bits, and `QualityCheck::Reject` is in use.
+    InvalidQuality(u32),
+    /// A decoded value is outside the plausible range configured for its variable, which
+    /// suggests that the message is corrupt.
+    Implausible {
+        variable: usize,
+        sample: usize,
+        value: i32,
+    },
+    /// The samples given to the encoder, a change to its settings, or the settings of an
+    /// emulator, are invalid.
+    InvalidInput(String),
+}
+
+/// The error returned when decoding a message, before encoder errors were added.
+#[deprecated(note = "use `JetstreamError`")]
+pub type DecodeError = JetstreamError;
+
+impl fmt::Display for JetstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JetstreamError::IdMismatch { .. } => write!(f, "IDs did not match"),
+            JetstreamError::UnknownStream(id) => write!(f, "no decoder for stream {}", id),
+            JetstreamError::Truncated { needed, got } => {
+                write!(f, "truncated message: needed {} bytes, got {}", needed, got)
+            }
+            JetstreamError::BufferTooSmall => write!(f, "encoder buffer too small"),
+            JetstreamError::Simple8bDecode(err) => write!(f, "simple-8b error: {}", err),
+            #[cfg(feature = "std")]
+            JetstreamError::Gzip(err) => write!(f, "gzip error: {}", err),
+            #[cfg(feature = "zstd")]
+            JetstreamError::Zstd(err) => write!(f, "zstd error: {}", err),
+            JetstreamError::UnsupportedCompression(compression) => {
+                write!(f, "{:?} compression is not enabled", compression)
+            }
+            JetstreamError::Corrupt(err) => write!(f, "corrupt message: {}", err),
+            JetstreamError::MissingEpoch => write!(f, "no epoch set for relative timestamp"),
+            JetstreamError::InvalidQuality(q) => write!(f, "quality {:#x} sets reserved bits", q),
+            JetstreamError::Implausible {
+                variable,
+                sample,
+                value,
+            } => write!(
+                f,
+                "value {} of variable {} at sample {} is outside the plausible range",
+                value, variable, sample
+            ),
+            JetstreamError::InvalidInput(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for JetstreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JetstreamError::Gzip(err) => Some(err),
+            #[cfg(feature = "zstd")]
+            JetstreamError::Zstd(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::jetstream::put_uvarint32;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// The default maximum length of a frame, which bounds the allocation for a length prefix
+/// read from an untrusted stream. This is far larger than any message the encoder produces.
+pub const MAX_FRAME_LENGTH: usize = 16 << 20;
+
+/// Writes an encoded message to `w`, prefixed with its length as a uvarint.
+pub fn write_frame<W: Write>(w: &mut W, msg: &[u8]) -> io::Result<()> {
+    let mut prefix = [0; 5];
+    let n = put_uvarint32(&mut prefix, msg.len() as u32);
+    w.write_all(&prefix[..n])?;
+    w.write_all(msg)
+}
+
+/// Writes `len` bytes of padding to `w`. Padding is a run of zero bytes, each of which reads
+/// as an empty frame, so it can be placed between any two frames.
+pub fn write_padding<W: Write>(w: &mut W, len: usize) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(len as u64), w)?;
+    Ok(())
+}
+
+/// Reads the next length-prefixed message from `r`, skipping any padding before it. Returns
+/// `None` at the end of the stream. A frame longer than `MAX_FRAME_LENGTH` is an error.
+pub fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    read_frame_with_limit(r, MAX_FRAME_LENGTH)
+}
+
+/// Reads the next length-prefixed message from `r`, like `read_frame`, but rejects a frame
+/// longer than `max_length` with `ErrorKind::InvalidData`, before allocating for it. The
+/// stream is left part way through the frame, so framing cannot be recovered.
+pub fn read_frame_with_limit<R: Read>(r: &mut R, max_length: usize) -> io::Result<Option<Vec<u8>>> {
+    let length = loop {
+        match read_uvarint32(r)? {
+            Some(0) => continue, // padding
+            Some(length) => break length as usize,
+            None => return Ok(None),
+        }
+    };
+    if length > max_length {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "frame length {} exceeds the maximum of {}",
+                length, max_length
+            ),
+        ));
+    }
+
+    let mut msg = vec![0; length];
+    r.read_exact(&mut msg)?;
+    Ok(Some(msg))
+}
+
+// Reads a uvarint one byte at a time, returning `None` if the stream ends before the first byte.
+fn read_uvarint32<R: Read>(r: &mut R) -> io::Result<Option<u32>> {
+    let mut x: u32 = 0;
+    let mut s: usize = 0;
+    let mut b = [0; 1];
+    for i in 0..5 {
+        if let Err(err) = r.read_exact(&mut b) {
+            return if i == 0 && err.kind() == ErrorKind::UnexpectedEof {
+                Ok(None)
+            } else {
+                Err(err)
+            };
+        }
+        if b[0] < 0x80 {
+            return Ok(Some(x | (b[0] as u32) << s));
+        }
+        x |= ((b[0] & 0x7f) as u32) << s;
+        s += 7;
+    }
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        "frame length overflow",
+    ))
+}
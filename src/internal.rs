@@ -0,0 +1,42 @@
+//! White-box access to private state, for tests which check the message format against the
+//! buffers the encoder writes. This is built for the crate's own tests, or for other crates
+//! with the `internal-testing` feature, and is not part of the stable API.
+use crate::encoder::Encoder;
+
+/// The ping-pong message buffers of an `Encoder`. The encoder writes each message into the
+/// active buffer, then switches to the other buffer for the next message, so the bytes of the
+/// previous message are left intact until the message after is complete.
+#[derive(Debug)]
+pub struct EncoderBuffers<'a> {
+    pub buf_a: &'a [u8],
+    pub buf_b: &'a [u8],
+    /// The next message is written to `buf_a`, rather than `buf_b`.
+    pub use_buf_a: bool,
+    /// The number of bytes of the next message written so far.
+    pub len: usize,
+}
+
+impl EncoderBuffers<'_> {
+    /// The buffer which the next message is written to.
+    pub fn active(&self) -> &[u8] {
+        if self.use_buf_a {
+            self.buf_a
+        } else {
+            self.buf_b
+        }
+    }
+
+    /// The buffer holding the previous message, unless it was compressed.
+    pub fn inactive(&self) -> &[u8] {
+        if self.use_buf_a {
+            self.buf_b
+        } else {
+            self.buf_a
+        }
+    }
+}
+
+/// Returns the message buffers of `enc`.
+pub fn encoder_buffers(enc: &Encoder) -> EncoderBuffers<'_> {
+    enc.buffers()
+}
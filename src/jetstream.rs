@@ -1,3 +1,10 @@
+use crate::error::JetstreamError;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use uuid::Uuid;
+
 // The number of samples per message required before using simple-8b encoding.
 pub(crate) const SIMPLE8B_THRESHOLD_SAMPLES: usize = 16;
 
@@ -8,23 +15,85 @@ pub(crate) const DEFAULT_DELTA_ENCODING_LAYERS: usize = 3;
 // The number of layers of delta encoding for high sampling rate scenarios.
 pub(crate) const HIGH_DELTA_ENCODING_LAYERS: usize = 3;
 
-// The size of the message header in bytes.
-pub(crate) const MAX_HEADER_SIZE: usize = 36;
+// The maximum number of layers of delta encoding which can be chosen for a stream.
+pub(crate) const MAX_DELTA_ENCODING_LAYERS: usize = 4;
+
+// The maximum size of the message header in bytes.
+pub(crate) const MAX_HEADER_SIZE: usize = 42;
+
+// Header flag indicating that the message records its own sampling rate, which differs from
+// the rate agreed for the stream.
+pub(crate) const FLAG_SAMPLING_RATE: u32 = 1 << 0;
+
+// Header flag indicating that the timestamp is encoded as a uvarint relative to the stream epoch.
+pub(crate) const FLAG_EPOCH_TIMESTAMP: u32 = 1 << 1;
+
+// Header flag indicating that the timestamp and simple-8b words are little-endian.
+pub(crate) const FLAG_LITTLE_ENDIAN: u32 = 1 << 2;
+
+// Header flag indicating that every sample timestamp is encoded, after the quality section.
+pub(crate) const FLAG_SAMPLE_TIMESTAMPS: u32 = 1 << 3;
+
+// Header flag indicating that a quality change restarts delta encoding for that variable.
+pub(crate) const FLAG_QUALITY_RESET: u32 = 1 << 4;
+
+// Header flag indicating that the header holds the minimum and maximum of each variable.
+pub(crate) const FLAG_CHANNEL_RANGES: u32 = 1 << 5;
+
+// Header flags indicating that quality values are stored with a fixed width of two or one
+// bytes, rather than as uvarints. The flags no longer fit in one byte once the second is set.
+pub(crate) const FLAG_QUALITY_U16: u32 = 1 << 6;
+pub(crate) const FLAG_QUALITY_U8: u32 = 1 << 7;
+
+// Header flag indicating a heartbeat, which has no fields other than the ID and no samples.
+pub(crate) const FLAG_HEARTBEAT: u32 = 1 << 8;
+
+// Header flag indicating that the header is padded with zeros so that the simple-8b words
+// start at a multiple of 8 bytes from the start of the message.
+pub(crate) const FLAG_ALIGNED_WORDS: u32 = 1 << 9;
 
-// The minimum number of samples per message to use gzip on the payload.
-pub(crate) const USE_GZIP_THRESHOLD_SAMPLES: usize = 4096;
+// Header flag indicating that the header holds a bitmap of the variables present in the
+// message. Variables which are not present have no samples, quality values or range.
+pub(crate) const FLAG_ACTIVE_CHANNELS: u32 = 1 << 10;
+
+// Header flag indicating that the payload holds the state of the boolean channels, after the
+// quality section.
+pub(crate) const FLAG_BOOLS: u32 = 1 << 11;
+
+// Header flag indicating that the quality section and the boolean channels come before the
+// samples in the payload, so that they can be read without decoding the samples.
+pub(crate) const FLAG_QUALITY_FIRST: u32 = 1 << 12;
+
+// Header flag indicating that the stream ID is compact: only the first 8 bytes of the UUID.
+pub(crate) const FLAG_COMPACT_ID: u32 = 1 << 13;
+
+// The bits of the format descriptor which hold the number of delta layers, which indicate
+// that the samples are packed into simple-8b words and XOR delta encoded, and which hold the
+// compression of the payload.
+const DESCRIPTOR_DELTA_LAYERS: u8 = 0b111;
+const DESCRIPTOR_SIMPLE8B: u8 = 1 << 3;
+const DESCRIPTOR_XOR: u8 = 1 << 4;
+const DESCRIPTOR_COMPRESSION: u8 = 0b11 << 5;
+const DESCRIPTOR_COMPRESSION_SHIFT: u32 = 5;
+
+// The maximum size of the minimum and maximum of a variable in the header.
+pub(crate) const MAX_CHANNEL_RANGE_SIZE: usize = 10;
+
+// By default, messages with more than this number of samples have their payload compressed.
+pub(crate) const COMPRESSION_THRESHOLD_SAMPLES: usize = 4096;
 
 /// Lists of variables to be encoded.
 #[derive(Clone)]
 pub struct Dataset {
     pub i32s: Vec<i32>,
-    // can extend with other data types
+    pub f32s: Vec<f32>,
 }
 
 impl Dataset {
     pub(crate) fn new(count: usize) -> Self {
         Self {
             i32s: vec![0; count],
+            f32s: vec![],
         }
     }
 }
@@ -34,7 +103,13 @@ impl Dataset {
 pub struct DatasetWithQuality {
     pub t: u64,
     pub i32s: Vec<i32>,
+    /// Floating point channels, such as temperatures, if configured for the stream. They are
+    /// encoded as the variables which follow `i32s`, and have the quality values which follow
+    /// those of `i32s`.
+    pub f32s: Vec<f32>,
     pub q: Vec<u32>,
+    /// Boolean status channels, such as breaker positions, if configured for the stream.
+    pub bools: Vec<bool>,
 }
 
 impl DatasetWithQuality {
@@ -42,28 +117,419 @@ impl DatasetWithQuality {
         Self {
             t: 0,
             i32s: vec![0; count],
+            f32s: vec![],
             q: vec![0; count],
+            bools: vec![],
+        }
+    }
+
+    /// Creates a zeroed dataset with `i32_count` integer and `f32_count` floating point
+    /// variables, and a quality value for each.
+    pub fn with_floats(i32_count: usize, f32_count: usize) -> Self {
+        Self {
+            t: 0,
+            i32s: vec![0; i32_count],
+            f32s: vec![0.0; f32_count],
+            q: vec![0; i32_count + f32_count],
+            bools: vec![],
+        }
+    }
+}
+
+/// The timestamps, variables and quality values of many samples, each held in a single
+/// allocation rather than in a `DatasetWithQuality` per sample, for building large inputs
+/// cheaply. Each sample is read and written through views of the shared storage.
+#[derive(Clone)]
+pub struct Datasets {
+    count: usize,
+    pub t: Vec<u64>,
+    i32s: Vec<i32>,
+    q: Vec<u32>,
+}
+
+impl Datasets {
+    /// Creates `samples` zeroed samples, each with `count` variables.
+    pub fn new(samples: usize, count: usize) -> Self {
+        Self {
+            count,
+            t: vec![0; samples],
+            i32s: vec![0; samples * count],
+            q: vec![0; samples * count],
+        }
+    }
+
+    /// The number of samples.
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// The variables of sample `k`.
+    pub fn i32s(&self, k: usize) -> &[i32] {
+        &self.i32s[k * self.count..(k + 1) * self.count]
+    }
+
+    pub fn i32s_mut(&mut self, k: usize) -> &mut [i32] {
+        &mut self.i32s[k * self.count..(k + 1) * self.count]
+    }
+
+    /// The quality values of sample `k`.
+    pub fn q(&self, k: usize) -> &[u32] {
+        &self.q[k * self.count..(k + 1) * self.count]
+    }
+
+    pub fn q_mut(&mut self, k: usize) -> &mut [u32] {
+        &mut self.q[k * self.count..(k + 1) * self.count]
+    }
+
+    /// Copies sample `k` into `out`, which must have the same number of variables, so that
+    /// it can be encoded.
+    pub fn copy_to(&self, k: usize, out: &mut DatasetWithQuality) {
+        out.t = self.t[k];
+        out.i32s.copy_from_slice(self.i32s(k));
+        out.q.copy_from_slice(self.q(k));
+    }
+}
+
+// How the samples of a message are encoded, stored in one byte after the ID so that the
+// decoder follows the encoder rather than deriving the format from its own settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FormatDescriptor {
+    pub(crate) delta_layers: usize,
+    pub(crate) simple8b: bool,
+    pub(crate) xor: bool,
+    pub(crate) compression: Compression,
+}
+
+impl FormatDescriptor {
+    pub(crate) fn to_byte(self) -> u8 {
+        let mut b = self.delta_layers as u8;
+        if self.simple8b {
+            b |= DESCRIPTOR_SIMPLE8B;
+        }
+        if self.xor {
+            b |= DESCRIPTOR_XOR;
+        }
+        b |= (self.compression as u8) << DESCRIPTOR_COMPRESSION_SHIFT;
+        b
+    }
+
+    // Reads the descriptor at `offset` in `buf`, rejecting one which sets reserved bits, an
+    // unsupported number of delta layers or an unknown compression.
+    pub(crate) fn read(buf: &[u8], offset: usize) -> Result<Self, JetstreamError> {
+        let b = *buf.get(offset).ok_or(JetstreamError::Truncated {
+            needed: offset + 1,
+            got: buf.len(),
+        })?;
+        let delta_layers = (b & DESCRIPTOR_DELTA_LAYERS) as usize;
+        let invalid = || JetstreamError::Corrupt(format!("invalid format descriptor {:#04x}", b));
+        if b & !(DESCRIPTOR_DELTA_LAYERS
+            | DESCRIPTOR_SIMPLE8B
+            | DESCRIPTOR_XOR
+            | DESCRIPTOR_COMPRESSION)
+            != 0
+            || !(1..=MAX_DELTA_ENCODING_LAYERS).contains(&delta_layers)
+        {
+            return Err(invalid());
+        }
+        let compression = match (b & DESCRIPTOR_COMPRESSION) >> DESCRIPTOR_COMPRESSION_SHIFT {
+            0 => Compression::None,
+            1 => Compression::Gzip,
+            2 => Compression::Zstd,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            delta_layers,
+            simple8b: b & DESCRIPTOR_SIMPLE8B != 0,
+            xor: b & DESCRIPTOR_XOR != 0,
+            compression,
+        })
+    }
+}
+
+/// The algorithm used to compress the payload of a message, which follows the header. Only
+/// messages with more samples than the encoder's compression threshold are compressed, and
+/// the algorithm used is recorded in each message header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// The payload is not compressed.
+    None = 0,
+    /// gzip, which needs the `std` feature.
+    Gzip = 1,
+    /// zstd, which needs the `zstd` feature.
+    Zstd = 2,
+}
+
+impl Default for Compression {
+    /// gzip if the `std` feature is enabled, and otherwise no compression.
+    fn default() -> Self {
+        if cfg!(feature = "std") {
+            Compression::Gzip
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl Compression {
+    // Whether the algorithm is available with the enabled features.
+    pub(crate) fn is_supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "std"),
+            Compression::Zstd => cfg!(feature = "zstd"),
         }
     }
 }
 
+/// The width used to store each quality value in a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QualityWidth {
+    /// One byte per value.
+    U8,
+    /// Two bytes per value, big-endian.
+    U16,
+    /// A uvarint of up to five bytes per value.
+    #[default]
+    U32,
+}
+
+impl QualityWidth {
+    // The largest value which can be stored.
+    pub(crate) fn max(self) -> u32 {
+        match self {
+            QualityWidth::U8 => u8::MAX as u32,
+            QualityWidth::U16 => u16::MAX as u32,
+            QualityWidth::U32 => u32::MAX,
+        }
+    }
+
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            QualityWidth::U8 => FLAG_QUALITY_U8,
+            QualityWidth::U16 => FLAG_QUALITY_U16,
+            QualityWidth::U32 => 0,
+        }
+    }
+
+    // Reads the width from the header flags, of which at most one width may be set.
+    pub(crate) fn from_flags(flags: u32) -> Result<Self, JetstreamError> {
+        match (flags & FLAG_QUALITY_U8 != 0, flags & FLAG_QUALITY_U16 != 0) {
+            (true, true) => Err(JetstreamError::Corrupt(
+                "both u8 and u16 quality widths are set".to_string(),
+            )),
+            (true, false) => Ok(QualityWidth::U8),
+            (false, true) => Ok(QualityWidth::U16),
+            (false, false) => Ok(QualityWidth::U32),
+        }
+    }
+
+    // Writes a quality value into `buf` and returns the number of bytes written.
+    pub(crate) fn put(self, buf: &mut [u8], value: u32) -> usize {
+        match self {
+            QualityWidth::U8 => {
+                buf[0] = value as u8;
+                1
+            }
+            QualityWidth::U16 => {
+                buf[..2].copy_from_slice(&(value as u16).to_be_bytes());
+                2
+            }
+            QualityWidth::U32 => put_uvarint32(buf, value),
+        }
+    }
+
+    // Reads a quality value from `buf`, returning the value and the number of bytes read.
+    pub(crate) fn read(self, buf: &[u8]) -> Result<(u32, usize), JetstreamError> {
+        match (self, buf) {
+            (QualityWidth::U8, [b, ..]) => Ok((*b as u32, 1)),
+            (QualityWidth::U16, [b0, b1, ..]) => Ok((u16::from_be_bytes([*b0, *b1]) as u32, 2)),
+            (QualityWidth::U32, _) => uvarint32(buf),
+            (QualityWidth::U8, _) => Err(JetstreamError::Truncated { needed: 1, got: 0 }),
+            (QualityWidth::U16, _) => Err(JetstreamError::Truncated {
+                needed: 2,
+                got: buf.len(),
+            }),
+        }
+    }
+}
+
+/// Variable indices for the IEC 61850-9-2 LE dataset: phase and neutral currents, followed by
+/// phase and neutral voltages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    PhaseAI = 0,
+    PhaseBI = 1,
+    PhaseCI = 2,
+    NeutralI = 3,
+    PhaseAV = 4,
+    PhaseBV = 5,
+    PhaseCV = 6,
+    NeutralV = 7,
+}
+
+impl Channel {
+    /// The number of variables in the 9-2 LE dataset.
+    pub const COUNT: usize = 8;
+
+    /// The index of the variable in `DatasetWithQuality::i32s`.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The factor converting the encoded value to engineering units, following the 9-2 LE
+    /// scaling of currents in mA and voltages in 10 mV.
+    pub const fn scale_factor(self) -> f64 {
+        match self {
+            Channel::PhaseAI | Channel::PhaseBI | Channel::PhaseCI | Channel::NeutralI => 0.001,
+            _ => 0.01,
+        }
+    }
+}
+
+/// Running statistics of a variable over every sample given to an `Encoder`, for monitoring
+/// the health of a signal. A stuck channel has the same minimum and maximum, and a channel at
+/// the limit of its measurement range has a minimum or maximum at that limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub min: i32,
+    pub max: i32,
+    /// The number of samples included.
+    pub samples: u64,
+    sum: i128,
+}
+
+impl Default for ChannelStats {
+    fn default() -> Self {
+        Self {
+            min: i32::MAX,
+            max: i32::MIN,
+            samples: 0,
+            sum: 0,
+        }
+    }
+}
+
+impl ChannelStats {
+    /// The mean of the samples included.
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.samples as f64
+    }
+
+    pub(crate) fn add(&mut self, value: i32) {
+        self.min = i32::min(self.min, value);
+        self.max = i32::max(self.max, value);
+        self.samples += 1;
+        self.sum += value as i128;
+    }
+}
+
+/// A complete message produced by an `Encoder`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedMessage {
+    buf: Vec<u8>,
+    samples: usize,
+    i32_count: usize,
+}
+
+impl EncodedMessage {
+    pub(crate) fn new(buf: Vec<u8>, samples: usize, i32_count: usize) -> Self {
+        Self {
+            buf,
+            samples,
+            i32_count,
+        }
+    }
+
+    /// The encoded bytes, ready to be sent over the network or stored.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Consumes the message, returning the encoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// The length of the encoded message in bytes.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The number of samples contained in the message.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The number of variables in each sample of the stream which produced the message.
+    pub fn i32_count(&self) -> usize {
+        self.i32_count
+    }
+}
+
+impl AsRef<[u8]> for EncodedMessage {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct QualityHistory {
     pub(crate) value: u32,
     pub(crate) samples: u32,
 }
 
+/// How the neutral variable of each three-phase set takes part in spatial references.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeutralRefs {
+    /// Each set only has the three phase variables.
+    Excluded,
+    /// Each set has a neutral variable, which references the neutral of the previous set.
+    Included,
+    /// Each set has a neutral variable, which is neither referenced by the next set nor
+    /// references the previous set. Neutrals are often noisy, making poor references.
+    Unreferenced,
+}
+
 pub(crate) fn create_spatial_refs(
     count: usize,
     count_v: usize,
     count_i: usize,
     include_neutral: bool,
+) -> Vec<Option<usize>> {
+    let neutral = if include_neutral {
+        NeutralRefs::Included
+    } else {
+        NeutralRefs::Excluded
+    };
+    create_spatial_refs_with(count, count_v, count_i, neutral)
+}
+
+pub(crate) fn create_spatial_refs_with(
+    count: usize,
+    count_v: usize,
+    count_i: usize,
+    neutral: NeutralRefs,
 ) -> Vec<Option<usize>> {
     let mut refs: Vec<Option<usize>> = vec![None; count as usize];
 
-    let inc = if include_neutral { 4 } else { 3 };
+    let inc = if neutral == NeutralRefs::Excluded {
+        3
+    } else {
+        4
+    };
 
     for i in 0..count {
+        if neutral == NeutralRefs::Unreferenced && i % inc == 3 {
+            continue;
+        }
         if i >= inc {
             if i < count_v * inc {
                 refs[i] = Some(i - inc);
@@ -76,6 +542,12 @@ pub(crate) fn create_spatial_refs(
     refs
 }
 
+// Converts a float to the nearest multiple of `scale`, returning the multiple. A quotient
+// which does not fit in an `i32` saturates, and NaN is zero.
+pub(crate) fn quantize(value: f32, scale: f64) -> i32 {
+    libm::round(value as f64 / scale) as i32
+}
+
 pub(crate) fn get_delta_encoding(sampling_rate: usize) -> usize {
     if sampling_rate > 100_000 {
         HIGH_DELTA_ENCODING_LAYERS
@@ -87,31 +559,97 @@ pub(crate) fn get_delta_encoding(sampling_rate: usize) -> usize {
 // TODO: Use "integer-encoding" crate
 
 /// Copied from encoding/binary/varint.go to provide 32-bit version to avoid casting.
-pub(crate) fn uvarint32(buf: &[u8]) -> (u32, usize) {
+///
+/// Returns the value and the number of bytes read. A buffer which ends before the last byte of
+/// the value is reported as truncated, and a value which does not fit in a `u32` as corrupt.
+pub(crate) fn uvarint32(buf: &[u8]) -> Result<(u32, usize), JetstreamError> {
     let mut x: u32 = 0;
     let mut s: usize = 0;
-    for i in 0..buf.len() {
-        let b = buf[i];
+    for (i, &b) in buf.iter().enumerate() {
+        // the final byte holds the remaining bits of the value, and cannot continue
+        if i == 4 && b > 0x0f {
+            return Err(JetstreamError::Corrupt("uvarint32 overflow".to_string()));
+        }
         if b < 0x80 {
-            if i > 9 || i == 9 && b > 1 {
-                panic!("uvarint32: overflow")
-                // return (0, -(i + 1)); // overflow  FIXME: Result
-            }
-            return (x | (b as u32) << s, i + 1);
+            return Ok((x | (b as u32) << s, i + 1));
         }
         x |= ((b & 0x7f) as u32) << s;
         s += 7
     }
-    (0, 0)
+    Err(JetstreamError::Truncated {
+        needed: buf.len() + 1,
+        got: buf.len(),
+    })
 }
 
-pub(crate) fn varint32(buf: &[u8]) -> (i32, usize) {
-    let (ux, n) = uvarint32(buf); // ok to continue in presence of error
+pub(crate) fn varint32(buf: &[u8]) -> Result<(i32, usize), JetstreamError> {
+    let (ux, n) = uvarint32(buf)?;
     let mut x = (ux >> 1) as i32;
     if ux & 1 != 0 {
         x = !x;
     }
-    (x, n)
+    Ok((x, n))
+}
+
+/// 64-bit version of `uvarint32`, for timestamps.
+pub(crate) fn uvarint64(buf: &[u8]) -> Result<(u64, usize), JetstreamError> {
+    let mut x: u64 = 0;
+    let mut s: usize = 0;
+    for (i, &b) in buf.iter().enumerate() {
+        // the final byte holds the remaining bits of the value, and cannot continue
+        if i == 9 && b > 1 {
+            return Err(JetstreamError::Corrupt("uvarint64 overflow".to_string()));
+        }
+        if b < 0x80 {
+            return Ok((x | (b as u64) << s, i + 1));
+        }
+        x |= ((b & 0x7f) as u64) << s;
+        s += 7
+    }
+    Err(JetstreamError::Truncated {
+        needed: buf.len() + 1,
+        got: buf.len(),
+    })
+}
+
+// Reads a field with `read` from `offset` in `buf`, returning the value and the number of
+// bytes read. A truncated field is reported with lengths from the start of `buf`, rather than
+// the start of the field.
+pub(crate) fn read_at<T>(
+    buf: &[u8],
+    offset: usize,
+    read: impl FnOnce(&[u8]) -> Result<(T, usize), JetstreamError>,
+) -> Result<(T, usize), JetstreamError> {
+    let field = buf.get(offset..).ok_or(JetstreamError::Truncated {
+        needed: offset,
+        got: buf.len(),
+    })?;
+    read(field).map_err(|err| match err {
+        JetstreamError::Truncated { needed, got } => JetstreamError::Truncated {
+            needed: offset + needed,
+            got: offset + got,
+        },
+        err => err,
+    })
+}
+
+// Reads the stream ID which follows the header flags at `offset` in `buf`. A compact ID is the
+// first 8 bytes of the stream's UUID.
+pub(crate) fn read_id(buf: &[u8], offset: usize, flags: u32) -> Result<&[u8], JetstreamError> {
+    let len = if flags & FLAG_COMPACT_ID != 0 { 8 } else { 16 };
+    buf.get(offset..offset + len)
+        .ok_or(JetstreamError::Truncated {
+            needed: offset + len,
+            got: buf.len(),
+        })
+}
+
+// Returns the UUID for an ID read by `read_id`, with the bytes missing from a compact ID set
+// to zero.
+pub(crate) fn id_from_bytes(bytes: &[u8]) -> Uuid {
+    let mut id = [0; 16];
+    id[..bytes.len()].copy_from_slice(bytes);
+    Uuid::from_bytes(id)
 }
 
 /// Encodes a `u32` into `buf` and returns the number of bytes written.
@@ -127,6 +665,19 @@ pub(crate) fn put_uvarint32(buf: &mut [u8], mut x: u32) -> usize {
     i + 1
 }
 
+/// Encodes a `u64` into `buf` and returns the number of bytes written.
+/// If the buffer is too small, `put_uvarint64` will panic.
+pub(crate) fn put_uvarint64(buf: &mut [u8], mut x: u64) -> usize {
+    let mut i = 0;
+    while x >= 0x80 {
+        buf[i] = (x as u8) | 0x80;
+        x >>= 7;
+        i += 1;
+    }
+    buf[i] = x as u8;
+    i + 1
+}
+
 /// Encodes an `i32` into `buf` and returns the number of bytes written.
 /// If the buffer is too small, `put_varint32` will panic.
 pub(crate) fn put_varint32(buf: &mut [u8], x: i32) -> usize {
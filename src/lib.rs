@@ -14,15 +14,52 @@
 // You should have received a copy of the GNU Affero General Public
 // License along with this program.
 // If not, see <https://www.gnu.org/licenses/>.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod analyze;
+#[cfg(feature = "std")]
+mod container;
 mod decoder;
+mod diff;
+#[cfg(feature = "std")]
 pub mod emulator;
 mod encoder;
 pub mod encoding;
+mod error;
+#[cfg(feature = "std")]
+mod framing;
+#[cfg(any(test, feature = "internal-testing"))]
+pub mod internal;
 mod jetstream;
-#[cfg(test)]
+#[cfg(feature = "std")]
+mod player;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(all(test, feature = "std"))]
 mod test;
+#[cfg(feature = "std")]
 pub mod testcase;
 
-pub use crate::decoder::Decoder;
-pub use crate::encoder::Encoder;
+#[cfg(feature = "std")]
+pub use crate::analyze::{analyze, CompressionReport};
+#[cfg(feature = "std")]
+pub use crate::container::{decode_container, pack_messages, unpack_messages};
+pub use crate::decoder::{
+    decode_message, DecodedOutput, Decoder, InvalidSamples, MessageInfo, OutputTicket, QualityCheck,
+};
+pub use crate::diff::{diff, Mismatch};
+pub use crate::encoder::{encode_all_messages, encode_message, Encoder};
+#[allow(deprecated)]
+pub use crate::error::{DecodeError, JetstreamError};
+#[cfg(feature = "std")]
+pub use crate::framing::{
+    read_frame, read_frame_with_limit, write_frame, write_padding, MAX_FRAME_LENGTH,
+};
 pub use crate::jetstream::*;
+#[cfg(feature = "std")]
+pub use crate::player::{Clock, Player, SystemClock};
+#[cfg(feature = "std")]
+pub use crate::stream::{DecodedMessage, StreamDecoder, StreamEncoder};
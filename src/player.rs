@@ -0,0 +1,131 @@
+use crate::decoder::Decoder;
+use crate::error::JetstreamError;
+use crate::framing::read_frame;
+use crate::jetstream::DatasetWithQuality;
+use std::io::{self, ErrorKind, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source of time for pacing playback.
+pub trait Clock {
+    /// The time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+
+    /// Blocks for the given duration.
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// A `Clock` using the system monotonic clock.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Replays a recorded stream of framed messages, approximating the original cadence.
+///
+/// Each message is released when the stream time of its first sample, derived from the
+/// sampling rate, has elapsed. A `speed` of `1.0` is real-time and `2.0` is twice as fast.
+/// Frames longer than `MAX_FRAME_LENGTH` are rejected as invalid data.
+pub struct Player<R: Read, C: Clock = SystemClock> {
+    reader: R,
+    decoder: Decoder,
+    speed: f64,
+    clock: C,
+    start: Option<Duration>,
+    stream_time: f64, // seconds
+}
+
+impl<R: Read> Player<R> {
+    /// Creates a player which paces messages using the system clock. The `speed` must be
+    /// greater than zero.
+    pub fn new(reader: R, decoder: Decoder, speed: f64) -> Result<Self, JetstreamError> {
+        Self::with_clock(reader, decoder, speed, SystemClock::default())
+    }
+}
+
+impl<R: Read, C: Clock> Player<R, C> {
+    /// Creates a player which paces messages using the supplied clock. The `speed` must be
+    /// greater than zero.
+    pub fn with_clock(
+        reader: R,
+        decoder: Decoder,
+        speed: f64,
+        clock: C,
+    ) -> Result<Self, JetstreamError> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(JetstreamError::InvalidInput(format!(
+                "playback speed {} is not greater than zero",
+                speed
+            )));
+        }
+
+        Ok(Self {
+            reader,
+            decoder,
+            speed,
+            clock,
+            start: None,
+            stream_time: 0.0,
+        })
+    }
+
+    /// The clock used for pacing playback.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Waits until the next message is due, then returns its decoded samples. Returns `None`
+    /// at the end of the stream. Heartbeats are skipped.
+    pub fn next_message(&mut self) -> io::Result<Option<&[DatasetWithQuality]>> {
+        loop {
+            let msg = match read_frame(&mut self.reader)? {
+                Some(msg) => msg,
+                None => return Ok(None),
+            };
+
+            self.decoder
+                .decode_to_buffer(&msg, msg.len())
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+            if !self.decoder.is_heartbeat() {
+                break;
+            }
+        }
+
+        // wait until the first sample of this message is due
+        let start = *self.start.get_or_insert_with(|| self.clock.now());
+        // a very slow speed can put the stream time beyond what a `Duration` can hold
+        let due = Duration::try_from_secs_f64(self.stream_time / self.speed)
+            .ok()
+            .and_then(|offset| start.checked_add(offset))
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "playback time overflows"))?;
+        let now = self.clock.now();
+        if due > now {
+            self.clock.sleep(due - now);
+        }
+
+        let samples = usize::min(
+            self.decoder.encoded_samples(),
+            self.decoder.samples_per_message,
+        );
+        self.stream_time += samples as f64 / self.decoder.message_sampling_rate() as f64;
+
+        Ok(Some(&self.decoder.out[..samples]))
+    }
+}
@@ -0,0 +1,152 @@
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use crate::error::JetstreamError;
+use crate::framing::{write_frame, MAX_FRAME_LENGTH};
+use crate::jetstream::{uvarint32, DatasetWithQuality};
+use std::io::{self, ErrorKind, Write};
+
+/// The samples from a single message decoded by a `StreamDecoder`.
+#[derive(Clone)]
+pub struct DecodedMessage {
+    pub samples: Vec<DatasetWithQuality>,
+    /// The message was a heartbeat, sent to show the producer is alive, and has no samples.
+    pub heartbeat: bool,
+}
+
+/// Decodes a stream of length-prefixed messages, as written by `write_frame`, which arrives
+/// in chunks of any size.
+pub struct StreamDecoder {
+    pub decoder: Decoder,
+    buf: Vec<u8>,
+    max_frame_length: usize,
+}
+
+impl StreamDecoder {
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            buf: vec![],
+            max_frame_length: MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Sets the longest frame which will be buffered, which is `MAX_FRAME_LENGTH` by default.
+    /// This bounds the memory used by a stream which announces a very long message.
+    pub fn set_max_frame_length(&mut self, max_frame_length: usize) {
+        self.max_frame_length = max_frame_length;
+    }
+
+    /// The longest frame which will be buffered.
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+
+    /// Appends `bytes` to the stream and decodes every message which is now complete, in
+    /// order. Any incomplete message is kept until the rest of it is pushed. A message which
+    /// fails to decode is dropped, and does not prevent later messages from being decoded. A
+    /// corrupt length prefix, or one longer than the maximum frame length, loses track of where
+    /// messages start, so it is returned as an error and the buffered bytes are discarded.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<DecodedMessage, JetstreamError>> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut msgs = vec![];
+        let mut start = 0;
+        while start < self.buf.len() {
+            let (length, len_b) = match uvarint32(&self.buf[start..]) {
+                Ok(prefix) => prefix,
+                Err(JetstreamError::Truncated { .. }) => break, // the length prefix is incomplete
+                Err(err) => {
+                    // the framing is lost, so the buffered bytes cannot be used
+                    msgs.push(Err(err));
+                    start = self.buf.len();
+                    break;
+                }
+            };
+            let length = length as usize;
+            if length > self.max_frame_length {
+                msgs.push(Err(JetstreamError::Corrupt(format!(
+                    "frame length {} exceeds the maximum of {}",
+                    length, self.max_frame_length
+                ))));
+                start = self.buf.len();
+                break;
+            }
+            if length == 0 {
+                start += len_b; // padding
+                continue;
+            }
+            if self.buf.len() < start + len_b + length {
+                break; // the message is incomplete
+            }
+
+            let msg = &self.buf[start + len_b..start + len_b + length];
+            msgs.push(self.decoder.decode_to_buffer(msg, length).map(|_| {
+                let actual_samples = usize::min(
+                    self.decoder.encoded_samples(),
+                    self.decoder.samples_per_message,
+                );
+                DecodedMessage {
+                    samples: self.decoder.out[..actual_samples].to_vec(),
+                    heartbeat: self.decoder.is_heartbeat(),
+                }
+            }));
+            start += len_b + length;
+        }
+        self.buf.drain(..start);
+
+        msgs
+    }
+
+    /// The number of bytes buffered towards the next message.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// Encodes samples into a stream of length-prefixed messages, as read by `StreamDecoder`,
+/// writing each message to `writer` as soon as it is complete.
+pub struct StreamEncoder<W: Write> {
+    pub encoder: Encoder,
+    writer: W,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    pub fn new(encoder: Encoder, writer: W) -> Self {
+        Self { encoder, writer }
+    }
+
+    /// Encodes the next set of samples, writing the message to the sink if it is complete.
+    pub fn write_sample(&mut self, data: &DatasetWithQuality) -> io::Result<()> {
+        let msg = self
+            .encoder
+            .encode(data)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+        match msg {
+            Some(msg) => write_frame(&mut self.writer, msg.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes any buffered samples as a final, shorter message, then flushes the sink.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.encoder.buffered_samples() > 0 {
+            let msg = self
+                .encoder
+                .end_encode()
+                .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+            write_frame(&mut self.writer, msg.as_bytes())?;
+        }
+        self.writer.flush()
+    }
+
+    /// The sink which messages are written to.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Flushes any buffered samples, then returns the sink.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -1,10 +1,26 @@
-use crate::decoder::Decoder;
+use crate::analyze::analyze;
+use crate::container::{decode_container, pack_messages, unpack_messages};
+use crate::decoder::{
+    decode_message, delta_sum_index, Decoder, InvalidSamples, MessageInfo, QualityCheck,
+};
+use crate::diff::{diff, Mismatch};
 use crate::emulator::Emulator;
-use crate::encoder::Encoder;
-use crate::jetstream::DatasetWithQuality;
+use crate::encoder::{encode_all_messages, encode_message, Encoder};
+use crate::encoding::{bitops, simple8b};
+use crate::error::JetstreamError;
+use crate::framing::{read_frame, read_frame_with_limit, write_frame, write_padding};
+use crate::internal::encoder_buffers;
+use crate::jetstream::{
+    put_uvarint32, put_uvarint64, put_varint32, uvarint32, uvarint64, varint32, Channel,
+    Compression, DatasetWithQuality, Datasets, FormatDescriptor, NeutralRefs, QualityWidth,
+    FLAG_ALIGNED_WORDS,
+};
+use crate::player::{Clock, Player};
+use crate::stream::{DecodedMessage, StreamDecoder, StreamEncoder};
 use crate::testcase::{create_emulator, create_input_data, encode_and_decode, TESTS};
 use std::io::stdout;
 use std::io::Write;
+use std::time::Duration;
 use tabwriter::TabWriter;
 
 fn create_input_data_dual_ied(
@@ -90,7 +106,7 @@ pub fn test_encode_decode() {
         "{}\n{}\n",
         [
             "samples", "sampling", "samples", "messages", "quality", "early", "spatial", "size",
-            "size",
+            "size", "encode",
         ]
         .join("\t"),
         [
@@ -103,6 +119,7 @@ pub fn test_encode_decode() {
             "refs",
             "(bytes)",
             "(%)",
+            "(MB/s)",
         ]
         .join("\t")
     )
@@ -152,12 +169,14 @@ pub fn test_encode_decode() {
         );
 
         if test.use_spatial_refs {
-            stream.set_spatial_refs(
-                test.count_of_variables,
-                test.count_of_variables / 8,
-                test.count_of_variables / 8,
-                true,
-            ); // TODO test include_neutral
+            stream
+                .set_spatial_refs(
+                    test.count_of_variables,
+                    test.count_of_variables / 8,
+                    test.count_of_variables / 8,
+                    true,
+                )
+                .unwrap(); // TODO test include_neutral
             stream_decoder.set_spatial_refs(
                 test.count_of_variables,
                 test.count_of_variables / 8,
@@ -176,6 +195,7 @@ pub fn test_encode_decode() {
             test.count_of_variables,
             test.samples_per_message,
             test.early_encoding_stop,
+            true,
         )
         .unwrap();
 
@@ -190,9 +210,13 @@ pub fn test_encode_decode() {
 
         assert!(percent <= test.expected_size);
 
+        let throughput = encode_stats
+            .encode_throughput(test.count_of_variables * encode_stats.samples * 16)
+            .unwrap_or_default();
+
         write!(
             tab,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}\t{:.1}\n",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{:.1}\n",
             encode_stats.samples,
             test.sampling_rate,
             test.samples_per_message,
@@ -201,7 +225,8 @@ pub fn test_encode_decode() {
             test.early_encoding_stop,
             test.use_spatial_refs,
             mean_bytes_per_message,
-            percent
+            percent,
+            throughput
         )
         .unwrap();
     });
@@ -251,7 +276,3752 @@ fn test_wrong_id() {
         test.count_of_variables,
         test.samples_per_message,
         test.early_encoding_stop,
+        false,
     )
     .unwrap_err();
-    assert_eq!(err, "IDs did not match");
+    assert!(matches!(
+        err,
+        JetstreamError::IdMismatch { expected, got } if expected == wrong_id && got == id
+    ));
+    assert_eq!(err.to_string(), "IDs did not match");
+}
+
+#[test]
+fn test_encode_returns_message_when_complete() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    // the first sample is buffered, the second completes the message
+    assert!(enc.encode(&data[0]).unwrap().is_none());
+    let msg = enc.encode(&data[1]).unwrap().unwrap();
+    assert_eq!(msg.samples(), test.samples_per_message);
+    assert_eq!(msg.len(), msg.as_bytes().len());
+    assert!(!msg.is_empty());
+
+    // the deprecated shim keeps the empty-buffer convention
+    #[allow(deprecated)]
+    {
+        let (buf, length) = enc.encode_with_length(&data[2]).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(length, 0);
+
+        let (buf, length) = enc.encode_with_length(&data[3]).unwrap();
+        assert_eq!(buf.len(), length);
+        assert!(length > 0);
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "decoder output was overwritten")]
+fn test_output_ticket_detects_overwrite() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    let messages = data
+        .iter()
+        .filter_map(|d| enc.encode(d).unwrap())
+        .collect::<Vec<_>>();
+
+    dec.decode_to_buffer(messages[0].as_bytes(), messages[0].len())
+        .unwrap();
+    let ticket = dec.ticket();
+    assert_eq!(dec.output(ticket)[1].i32s, data[1].i32s);
+
+    // decoding the next message invalidates the earlier ticket
+    dec.decode_to_buffer(messages[1].as_bytes(), messages[1].len())
+        .unwrap();
+    dec.output(ticket);
+}
+
+#[test]
+fn test_sampling_rate_change() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let samples_per_message = 4;
+
+    let mut enc = Encoder::new(id, count_of_variables, 4800, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, 4800, samples_per_message);
+    dec.infer_timestamps = true;
+
+    // timestamps in nanoseconds, for one message at 4800 Hz followed by one at 9600 Hz
+    let mut ied: Emulator = create_emulator(4800, 0.0);
+    let mut data = create_input_data(&mut ied, 2 * samples_per_message, count_of_variables, false);
+    let start_9600 = 1_000_000_000;
+    for (k, d) in data.iter_mut().enumerate() {
+        d.t = if k < samples_per_message {
+            (k as u64 * 1_000_000_000) / 4800
+        } else {
+            start_9600 + ((k - samples_per_message) as u64 * 1_000_000_000) / 9600
+        };
+    }
+
+    for (k, d) in data.iter().enumerate() {
+        if k == samples_per_message {
+            enc.set_sampling_rate(9600).unwrap();
+        }
+        if let Some(msg) = enc.encode(d).unwrap() {
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+            let first = k + 1 - samples_per_message;
+            assert_eq!(dec.message_sampling_rate(), enc.sampling_rate);
+            for i in 0..samples_per_message {
+                assert_eq!(dec.out[i].t, data[first + i].t);
+                assert_eq!(dec.out[i].i32s, data[first + i].i32s);
+            }
+        }
+    }
+
+    // the rate cannot change part way through a message
+    enc.encode(&data[0]).unwrap();
+    assert!(enc.set_sampling_rate(4800).is_err());
+}
+
+#[test]
+fn test_clear_spatial_refs() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 16;
+    let samples_per_message = 20;
+
+    let mut ied1: Emulator = create_emulator(4000, 0.0);
+    let mut ied2: Emulator = create_emulator(4000, 0.0);
+    let data = create_input_data_dual_ied(
+        &mut ied1,
+        &mut ied2,
+        2 * samples_per_message,
+        count_of_variables,
+        false,
+    );
+
+    let mut enc = Encoder::new(id, count_of_variables, 4000, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, 4000, samples_per_message);
+    enc.set_spatial_refs(count_of_variables, 2, 2, true)
+        .unwrap();
+    dec.set_spatial_refs(count_of_variables, 2, 2, true);
+
+    for (k, d) in data.iter().enumerate() {
+        if let Some(msg) = enc.encode(d).unwrap() {
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+            let first = k + 1 - samples_per_message;
+            for i in 0..samples_per_message {
+                assert_eq!(dec.out[i].i32s, data[first + i].i32s);
+            }
+
+            // disable spatial references for the second message
+            enc.clear_spatial_refs().unwrap();
+            dec.clear_spatial_refs();
+        } else if k == 0 {
+            // cannot reconfigure part way through a message
+            assert!(enc.clear_spatial_refs().is_err());
+            assert!(enc
+                .set_spatial_refs(count_of_variables, 2, 2, true)
+                .is_err());
+        }
+    }
+}
+
+#[test]
+fn test_gzip_truncated_and_corrupt() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let samples_per_message = 5000;
+
+    let mut ied: Emulator = create_emulator(4000, 0.0);
+    let data = create_input_data(&mut ied, samples_per_message, count_of_variables, false);
+
+    let mut enc = Encoder::new(id, count_of_variables, 4000, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, 4000, samples_per_message);
+
+    let msg = data.iter().find_map(|d| enc.encode(d).unwrap()).unwrap();
+    let buf = msg.as_bytes();
+
+    // drop part of the gzip trailer
+    let truncated = &buf[..buf.len() - 4];
+    assert!(matches!(
+        dec.decode_to_buffer(truncated, truncated.len()),
+        Err(JetstreamError::Truncated { .. })
+    ));
+
+    // overwrite the gzip magic number, which follows the 28 byte header
+    let mut corrupt = buf.to_vec();
+    corrupt[28] = !corrupt[28];
+    assert!(matches!(
+        dec.decode_to_buffer(&corrupt, corrupt.len()),
+        Err(JetstreamError::Gzip(_))
+    ));
+
+    dec.decode_to_buffer(buf, buf.len()).unwrap();
+}
+
+#[test]
+fn test_empty_message() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    // a forged message of simple-8b words with no samples decodes to nothing, rather than
+    // panicking; the count of 80 samples is a two byte varint following the 26 byte header
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    let mut forged = msg.as_bytes().to_vec();
+    assert_eq!(forged[26..28], [160, 1]);
+    forged.splice(26..28, [0]);
+    assert_eq!(dec.decode_iter(&forged).unwrap().len(), 0);
+}
+
+#[test]
+fn test_compression() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-800").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut algorithms = vec![Compression::None, Compression::Gzip];
+    if cfg!(feature = "zstd") {
+        algorithms.push(Compression::Zstd);
+    }
+    for compression in algorithms {
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        assert_eq!(enc.compression(), Compression::Gzip);
+        enc.set_compression(compression).unwrap();
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        // below the default threshold, and then above a lower threshold
+        for (threshold, compressed) in [(enc.compression_threshold(), false), (100, true)] {
+            enc.set_compression_threshold(threshold);
+            let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+            let expected = if compressed {
+                compression
+            } else {
+                Compression::None
+            };
+            let info = dec.inspect(msg.as_bytes()).unwrap();
+            assert_eq!(info.compression, expected, "{:?}", compression);
+
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for (k, d) in data.iter().enumerate() {
+                assert_eq!(dec.out[k].i32s, d.i32s, "{:?} at {}", compression, k);
+                assert_eq!(dec.out[k].q, d.q, "{:?} at {}", compression, k);
+            }
+        }
+    }
+
+    // an algorithm without its feature cannot be set, and a message which uses it cannot be
+    // decoded
+    let mut enc = Encoder::new(id, test.count_of_variables, test.sampling_rate, 800);
+    let mut dec = Decoder::new(id, test.count_of_variables, test.sampling_rate, 800);
+    if !cfg!(feature = "zstd") {
+        assert!(matches!(
+            enc.set_compression(Compression::Zstd),
+            Err(JetstreamError::UnsupportedCompression(Compression::Zstd))
+        ));
+    }
+    enc.set_compression_threshold(100);
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    let mut forged = msg.as_bytes().to_vec();
+    let descriptor = forged[17];
+    forged[17] = descriptor & !(0b11 << 5) | (2 << 5);
+    let result = dec.decode_to_buffer(&forged, forged.len());
+    if cfg!(feature = "zstd") {
+        assert!(result.is_err());
+    } else {
+        assert!(matches!(
+            result,
+            Err(JetstreamError::UnsupportedCompression(Compression::Zstd))
+        ));
+    }
+
+    // the reserved compression code is corrupt
+    forged[17] = descriptor | (0b11 << 5);
+    assert!(matches!(
+        dec.decode_to_buffer(&forged, forged.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+}
+
+#[test]
+fn test_compression_threshold() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let sampling_rate = 4000;
+    let samples_per_message = 2000;
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, samples_per_message, count_of_variables, false);
+
+    // the decoder is not told the threshold, and follows the header of each message
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    for (threshold, samples, compressed) in [
+        (usize::MAX, samples_per_message, false),
+        (samples_per_message, samples_per_message, false),
+        (samples_per_message - 1, samples_per_message, true),
+        (0, samples_per_message, true),
+        (1000, 1000, false),
+        (1000, 1001, true),
+    ] {
+        let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+        enc.set_compression_threshold(threshold);
+        let msg = encode_all_messages(&mut enc, &data[..samples])
+            .unwrap()
+            .remove(0);
+
+        let info = dec.inspect(msg.as_bytes()).unwrap();
+        assert_eq!(
+            info.compression != Compression::None,
+            compressed,
+            "{} of {}",
+            threshold,
+            samples
+        );
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, d) in data[..samples].iter().enumerate() {
+            assert_eq!(
+                dec.out[k].i32s, d.i32s,
+                "{} of {} at {}",
+                threshold, samples, k
+            );
+            assert_eq!(dec.out[k].q, d.q, "{} of {} at {}", threshold, samples, k);
+        }
+    }
+
+    // a change part way through a message applies to the whole message
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    for d in &data[..samples_per_message / 2] {
+        assert!(enc.encode(d).unwrap().is_none());
+    }
+    enc.set_compression_threshold(0);
+    let msg = encode_all_messages(&mut enc, &data[samples_per_message / 2..])
+        .unwrap()
+        .remove(0);
+    assert_ne!(
+        dec.inspect(msg.as_bytes()).unwrap().compression,
+        Compression::None
+    );
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    for (k, d) in data.iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, d.i32s, "at {}", k);
+    }
+}
+
+#[test]
+fn test_from_id_bytes() {
+    let id_bytes: [u8; 16] = *uuid::Uuid::new_v4().as_bytes();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::from_id_bytes(
+        id_bytes,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::from_id_bytes(
+        id_bytes,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    assert_eq!(enc.id, uuid::Uuid::from_bytes(id_bytes));
+    assert_eq!(dec.id, uuid::Uuid::from_bytes(id_bytes));
+
+    encode_and_decode(
+        true,
+        &mut data,
+        &mut enc,
+        &mut dec,
+        test.count_of_variables,
+        test.samples_per_message,
+        test.early_encoding_stop,
+        false,
+    )
+    .unwrap();
+}
+
+#[derive(Default)]
+struct MockClock {
+    now: Duration,
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+#[test]
+fn test_flush_timeout() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let sampling_rate = 4000;
+    let samples_per_message = 40;
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, 7, count_of_variables, false);
+
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    enc.flush_timeout = Some(Duration::from_millis(100));
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut clock = MockClock::default();
+
+    // samples arrive every 30 ms, so the fifth is 120 ms after the first
+    let mut flushed = vec![];
+    for d in &data[..5] {
+        if let Some(msg) = enc.encode_at(d, clock.now()).unwrap() {
+            flushed.push(msg);
+        }
+        clock.sleep(Duration::from_millis(30));
+    }
+    assert_eq!(flushed.len(), 1);
+    dec.decode_to_buffer(flushed[0].as_bytes(), flushed[0].len())
+        .unwrap();
+    for (k, d) in data[..5].iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, d.i32s);
+    }
+
+    // if samples stop arriving, the deadline is checked without a new sample
+    let start = clock.now();
+    for d in &data[5..] {
+        assert!(enc.encode_at(d, clock.now()).unwrap().is_none());
+        clock.sleep(Duration::from_millis(30));
+    }
+    assert!(enc
+        .flush_if_due(start + Duration::from_millis(99))
+        .unwrap()
+        .is_none());
+    let msg = enc
+        .flush_if_due(start + Duration::from_millis(100))
+        .unwrap()
+        .unwrap();
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    for (k, d) in data[5..].iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, d.i32s);
+    }
+
+    // nothing is emitted while no samples are buffered
+    assert!(enc
+        .flush_if_due(start + Duration::from_secs(10))
+        .unwrap()
+        .is_none());
+
+    // the two ways of encoding cannot be mixed within a message, as the deadline would be
+    // stale or missing
+    enc.encode_at(&data[0], clock.now()).unwrap();
+    assert!(matches!(
+        enc.encode(&data[1]),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+    enc.cancel_encode();
+    enc.encode(&data[0]).unwrap();
+    assert!(matches!(
+        enc.encode_at(&data[1], clock.now()),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+    assert!(enc
+        .flush_if_due(clock.now() + Duration::from_secs(10))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_player_speed() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let sampling_rate = 4000;
+    let samples_per_message = 40; // 10 ms per message
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, 3 * samples_per_message, count_of_variables, false);
+
+    // record framed messages
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut recording: Vec<u8> = vec![];
+    for d in &data {
+        if let Some(msg) = enc.encode(d).unwrap() {
+            write_frame(&mut recording, msg.as_bytes()).unwrap();
+        }
+    }
+
+    let dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut player = Player::with_clock(&recording[..], dec, 2.0, MockClock::default()).unwrap();
+
+    let mut release_times = vec![];
+    let mut k = 0;
+    while let Some(samples) = player.next_message().unwrap() {
+        assert_eq!(samples.len(), samples_per_message);
+        for sample in samples {
+            assert_eq!(sample.i32s, data[k].i32s);
+            k += 1;
+        }
+        release_times.push(player.clock().now());
+    }
+
+    // messages are released every 5 ms at double speed
+    assert_eq!(
+        release_times,
+        vec![
+            Duration::ZERO,
+            Duration::from_millis(5),
+            Duration::from_millis(10)
+        ]
+    );
+
+    for speed in [0.0, -1.0, f64::NAN] {
+        let dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+        assert!(matches!(
+            Player::with_clock(&recording[..], dec, speed, MockClock::default()),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn test_single_sample_messages() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 16;
+
+    let mut ied1: Emulator = create_emulator(4000, 0.0);
+    let mut ied2: Emulator = create_emulator(4000, 0.0);
+    let data = create_input_data_dual_ied(&mut ied1, &mut ied2, 10, count_of_variables, true);
+
+    // single sample messages take the minimal decode path
+    let mut enc_single = Encoder::new(id, count_of_variables, 4000, 1);
+    let mut dec_single = Decoder::new(id, count_of_variables, 4000, 1);
+
+    // a message ended early after one sample takes the general path
+    let mut enc = Encoder::new(id, count_of_variables, 4000, 4);
+    let mut dec = Decoder::new(id, count_of_variables, 4000, 4);
+
+    enc_single
+        .set_spatial_refs(count_of_variables, 2, 2, true)
+        .unwrap();
+    dec_single.set_spatial_refs(count_of_variables, 2, 2, true);
+    enc.set_spatial_refs(count_of_variables, 2, 2, true)
+        .unwrap();
+    dec.set_spatial_refs(count_of_variables, 2, 2, true);
+
+    for d in &data {
+        let msg_single = enc_single.encode(d).unwrap().unwrap();
+        dec_single
+            .decode_to_buffer(msg_single.as_bytes(), msg_single.len())
+            .unwrap();
+
+        assert!(enc.encode(d).unwrap().is_none());
+        let msg = enc.end_encode().unwrap();
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+        assert_eq!(dec_single.out[0].t, dec.out[0].t);
+        assert_eq!(dec_single.out[0].i32s, dec.out[0].i32s);
+        assert_eq!(dec_single.out[0].q, dec.out[0].q);
+        assert_eq!(dec_single.out[0].i32s, d.i32s);
+        assert_eq!(dec_single.out[0].q, d.q);
+    }
+}
+
+#[test]
+fn test_epoch_timestamp() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+    let epoch: u64 = 1_600_000_000_000_000_000;
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    data.iter_mut().for_each(|d| d.t += epoch + 5000);
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut enc_epoch = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    enc_epoch.set_epoch(epoch);
+
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    enc.encode(&data[0]).unwrap();
+    let msg = enc.encode(&data[1]).unwrap().unwrap();
+    enc_epoch.encode(&data[0]).unwrap();
+    let msg_epoch = enc_epoch.encode(&data[1]).unwrap().unwrap();
+
+    // the 8 byte timestamp is replaced with a 2 byte offset
+    assert_eq!(msg.len() - 6, msg_epoch.len());
+
+    // the epoch is needed to decode the timestamp
+    assert!(matches!(
+        dec.decode_to_buffer(msg_epoch.as_bytes(), msg_epoch.len()),
+        Err(JetstreamError::MissingEpoch)
+    ));
+
+    dec.set_epoch(epoch);
+    dec.decode_to_buffer(msg_epoch.as_bytes(), msg_epoch.len())
+        .unwrap();
+    assert_eq!(dec.out[0].t, data[0].t);
+    assert_eq!(dec.out[1].i32s, data[1].i32s);
+
+    // an offset which overflows the epoch is corrupt
+    dec.set_epoch(u64::MAX - 1000);
+    assert!(matches!(
+        dec.decode_to_buffer(msg_epoch.as_bytes(), msg_epoch.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+}
+
+#[test]
+fn test_little_endian() {
+    for name in ["a10-2", "b4000-80"] {
+        let test = TESTS.get(name).unwrap();
+        let id = uuid::Uuid::new_v4();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data: Vec<DatasetWithQuality> = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+        data.iter_mut()
+            .for_each(|d| d.t += 1_600_000_000_000_000_000);
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.set_little_endian(true);
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.infer_timestamps = true;
+
+        for d in &data[..test.samples_per_message - 1] {
+            assert!(enc.encode(d).unwrap().is_none());
+        }
+        let msg = enc
+            .encode(&data[test.samples_per_message - 1])
+            .unwrap()
+            .unwrap();
+
+        // timestamp follows the flags, ID and format descriptor
+        assert_eq!(msg.as_bytes()[18..26], data[0].t.to_le_bytes(), "{}", name);
+
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        assert_eq!(dec.out[0].t, data[0].t, "{}", name);
+        for (i, d) in data[..test.samples_per_message].iter().enumerate() {
+            assert_eq!(dec.out[i].i32s, d.i32s, "{} at {}", name, i);
+            assert_eq!(dec.out[i].q, d.q, "{} at {}", name, i);
+        }
+    }
+}
+
+#[test]
+fn test_encode_all_messages() {
+    let id = uuid::Uuid::new_v4();
+    let (sampling_rate, count_of_variables, samples_per_message) = (4000, 8, 1000);
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, 4005, count_of_variables, false);
+
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+
+    let messages = encode_all_messages(&mut enc, &data).unwrap();
+    assert_eq!(messages.len(), 5);
+    assert!(messages[..4]
+        .iter()
+        .all(|msg| msg.samples() == samples_per_message));
+    assert_eq!(messages[4].samples(), 5);
+
+    dec.decode_to_buffer(messages[4].as_bytes(), messages[4].len())
+        .unwrap();
+    assert_eq!(dec.encoded_samples(), 5);
+    for i in 0..5 {
+        assert_eq!(dec.out[i].i32s, data[4000 + i].i32s);
+    }
+}
+
+#[test]
+fn test_datasets() {
+    let id = uuid::Uuid::new_v4();
+    let (sampling_rate, count_of_variables, samples_per_message) = (4000, 8, 80);
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, samples_per_message, count_of_variables, true);
+
+    // each sample is a view of the shared storage
+    let mut datasets = Datasets::new(samples_per_message, count_of_variables);
+    assert_eq!(datasets.len(), samples_per_message);
+    for (k, d) in data.iter().enumerate() {
+        datasets.t[k] = d.t;
+        datasets.i32s_mut(k).copy_from_slice(&d.i32s);
+        datasets.q_mut(k).copy_from_slice(&d.q);
+    }
+
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut sample = DatasetWithQuality::new(count_of_variables);
+    let mut msg = None;
+    for k in 0..datasets.len() {
+        datasets.copy_to(k, &mut sample);
+        msg = enc.encode(&sample).unwrap();
+    }
+    let msg = msg.unwrap();
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    for (k, d) in data.iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, datasets.i32s(k));
+        assert_eq!(dec.out[k].q, datasets.q(k));
+        assert_eq!(dec.out[k].i32s, d.i32s);
+    }
+}
+
+#[test]
+fn test_timestamp_gaps() {
+    for name in ["a10-10", "b4000-80"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data: Vec<DatasetWithQuality> = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        // drop a sample, leaving a gap in the timestamps
+        let interval = 1_000_000_000 / test.sampling_rate as u64;
+        data.iter_mut().enumerate().for_each(|(k, d)| {
+            d.t = 1_600_000_000_000_000_000 + k as u64 * interval;
+            if k >= 5 {
+                d.t += interval;
+            }
+        });
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.set_sample_timestamps(true);
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.check_contiguity = true;
+
+        // the encoded timestamps are checked, whether or not timestamps are inferred
+        let messages = encode_all_messages(&mut enc, &data[..test.samples_per_message]).unwrap();
+        assert_eq!(messages.len(), 1);
+        for infer_timestamps in [true, false] {
+            dec.infer_timestamps = infer_timestamps;
+            dec.decode_to_buffer(messages[0].as_bytes(), messages[0].len())
+                .unwrap();
+
+            for (i, d) in data[..test.samples_per_message].iter().enumerate() {
+                assert_eq!(dec.out[i].t, d.t, "{} at {}", name, i);
+                assert_eq!(dec.out[i].i32s, d.i32s, "{} at {}", name, i);
+            }
+            assert_eq!(dec.timestamp_gaps(), &[5], "{}", name);
+        }
+
+        // sample numbers have no gaps, and inferred timestamps are contiguous
+        enc.set_sample_timestamps(false);
+        let messages = encode_all_messages(&mut enc, &data[..test.samples_per_message]).unwrap();
+        for infer_timestamps in [true, false] {
+            dec.infer_timestamps = infer_timestamps;
+            dec.decode_to_buffer(messages[0].as_bytes(), messages[0].len())
+                .unwrap();
+            assert!(dec.timestamp_gaps().is_empty(), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_reset_on_quality_change() {
+    for name in ["a10-10", "b4000-80"] {
+        let test = TESTS.get(name).unwrap();
+        let id = uuid::Uuid::new_v4();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data: Vec<DatasetWithQuality> = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut total_bytes = vec![];
+        for reset in [false, true] {
+            // the first channel becomes invalid part way through, and its value jumps
+            let mut data = data.clone();
+            data.iter_mut().enumerate().for_each(|(k, d)| {
+                if k % test.samples_per_message >= 5 {
+                    d.q[0] = 1;
+                    d.i32s[0] += 300_000_000;
+                }
+            });
+
+            let mut enc = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            enc.set_reset_on_quality_change(reset).unwrap();
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+
+            let encode_stats = encode_and_decode(
+                true,
+                &mut data,
+                &mut enc,
+                &mut dec,
+                test.count_of_variables,
+                test.samples_per_message,
+                false,
+                false,
+            )
+            .unwrap();
+            total_bytes.push(encode_stats.total_bytes);
+
+            // the header flag is written at the end of the message, so the choice cannot change
+            // part way through one
+            enc.encode(&data[0]).unwrap();
+            assert!(matches!(
+                enc.set_reset_on_quality_change(!reset),
+                Err(JetstreamError::InvalidInput(_))
+            ));
+            assert_eq!(enc.reset_on_quality_change(), reset);
+        }
+        assert!(
+            total_bytes[1] < total_bytes[0],
+            "{}: {:?}",
+            name,
+            total_bytes
+        );
+    }
+}
+
+#[test]
+fn test_container() {
+    let test = TESTS.get("a10-2").unwrap();
+    let ids = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+
+    // the second stream carries only the voltages
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let voltages: Vec<DatasetWithQuality> = data
+        .iter()
+        .map(|d| DatasetWithQuality {
+            t: d.t,
+            i32s: d.i32s[4..].to_vec(),
+            f32s: vec![],
+            q: d.q[4..].to_vec(),
+            bools: vec![],
+        })
+        .collect();
+
+    let mut enc_a = Encoder::new(
+        ids[0],
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut enc_b = Encoder::new(ids[1], 4, test.sampling_rate, test.samples_per_message);
+    let mut msgs = encode_all_messages(&mut enc_a, &data).unwrap();
+    msgs.extend(encode_all_messages(&mut enc_b, &voltages).unwrap());
+
+    let container = pack_messages(&msgs);
+    assert_eq!(
+        container.len(),
+        1 + msgs.iter().map(|m| 2 + m.len()).sum::<usize>()
+    );
+
+    // the decoders are in a different order to the messages
+    let mut decoders = [
+        Decoder::new(ids[1], 4, test.sampling_rate, test.samples_per_message),
+        Decoder::new(
+            ids[0],
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        ),
+    ];
+    assert_eq!(decode_container(&container, &mut decoders).unwrap(), [1, 0]);
+    for i in 0..test.samples_per_message {
+        assert_eq!(decoders[0].out[i].i32s, voltages[i].i32s);
+        assert_eq!(decoders[1].out[i].i32s, data[i].i32s);
+    }
+
+    // a message for an unknown stream is rejected
+    assert!(matches!(
+        decode_container(&container, &mut decoders[..1]),
+        Err(JetstreamError::UnknownStream(_))
+    ));
+    assert!(matches!(
+        decode_container(&container[..container.len() - 1], &mut decoders),
+        Err(JetstreamError::Truncated { .. })
+    ));
+
+    // an implausible count of messages is not allocated up front
+    assert!(matches!(
+        unpack_messages(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        Err(JetstreamError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn test_spatial_refs_no_effect() {
+    let id = uuid::Uuid::new_v4();
+    let mut enc = Encoder::new(id, 2, 4000, 2);
+
+    // two variables cannot form a reference to another set of three phases
+    let err = enc.set_spatial_refs(2, 1, 1, false).unwrap_err();
+    assert!(err.to_string().contains("no effect"), "{}", err);
+
+    let mut enc = Encoder::new(id, 8, 4000, 2);
+    assert!(enc.set_spatial_refs(8, 1, 1, true).is_err());
+
+    let mut enc = Encoder::new(id, 16, 4000, 2);
+    assert!(enc.set_spatial_refs(16, 2, 2, true).is_ok());
+}
+
+#[test]
+fn test_read_frame_skips_padding() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples_per_message * 3,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    // pad before, between and after three messages
+    let mut stream = vec![];
+    write_padding(&mut stream, 3).unwrap();
+    for (i, msg) in encode_all_messages(&mut enc, &data)
+        .unwrap()
+        .iter()
+        .enumerate()
+    {
+        write_frame(&mut stream, msg.as_bytes()).unwrap();
+        write_padding(&mut stream, i * 7).unwrap();
+    }
+
+    let mut r = &stream[..];
+    for chunk in data.chunks(test.samples_per_message) {
+        let msg = read_frame(&mut r).unwrap().unwrap();
+        dec.decode_to_buffer(&msg, msg.len()).unwrap();
+        for (i, d) in chunk.iter().enumerate() {
+            assert_eq!(dec.out[i].i32s, d.i32s);
+        }
+    }
+    assert!(read_frame(&mut r).unwrap().is_none());
+
+    // a frame longer than the limit is rejected before it is read
+    let mut frame = vec![];
+    write_frame(&mut frame, &[1, 2, 3]).unwrap();
+    let err = read_frame_with_limit(&mut &frame[..], 2).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(
+        read_frame_with_limit(&mut &frame[..], 3).unwrap(),
+        Some(vec![1, 2, 3])
+    );
+    let err = read_frame(&mut &[0xff, 0xff, 0xff, 0xff, 0x0f][..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_channel_indices() {
+    let channels = [
+        Channel::PhaseAI,
+        Channel::PhaseBI,
+        Channel::PhaseCI,
+        Channel::NeutralI,
+        Channel::PhaseAV,
+        Channel::PhaseBV,
+        Channel::PhaseCV,
+        Channel::NeutralV,
+    ];
+    assert_eq!(channels.len(), Channel::COUNT);
+    for (i, channel) in channels.iter().enumerate() {
+        assert_eq!(channel.index(), i);
+    }
+
+    // currents are first, each followed by the neutral
+    assert_eq!(Channel::NeutralI.index(), 3);
+    assert_eq!(Channel::PhaseAV.index(), 4);
+    assert_eq!(Channel::NeutralV.index(), 7);
+}
+
+#[test]
+fn test_channel_ranges() {
+    for name in ["a10-10", "b4000-80", "b4000-4000s2"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data: Vec<DatasetWithQuality> = if test.count_of_variables == 16 {
+            let mut ied2: Emulator = create_emulator(test.sampling_rate, 0.0);
+            create_input_data_dual_ied(
+                &mut ied,
+                &mut ied2,
+                test.samples,
+                test.count_of_variables,
+                test.quality_change,
+            )
+        } else {
+            create_input_data(
+                &mut ied,
+                test.samples,
+                test.count_of_variables,
+                test.quality_change,
+            )
+        };
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.store_channel_ranges = true;
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        if test.use_spatial_refs {
+            let (count_v, count_i) = (test.count_of_variables / 8, test.count_of_variables / 8);
+            enc.set_spatial_refs(test.count_of_variables, count_v, count_i, true)
+                .unwrap();
+            dec.set_spatial_refs(test.count_of_variables, count_v, count_i, true);
+        }
+        assert_eq!(dec.channel_range(0), None);
+
+        let msg = encode_all_messages(&mut enc, &data[..test.samples_per_message])
+            .unwrap()
+            .remove(0);
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+        for i in 0..test.count_of_variables {
+            let decoded = dec.out[..test.samples_per_message]
+                .iter()
+                .map(|d| d.i32s[i]);
+            let expected = (decoded.clone().min().unwrap(), decoded.max().unwrap());
+            assert_eq!(dec.channel_range(i), Some(expected), "{} at {}", name, i);
+        }
+    }
+}
+
+#[test]
+fn test_stream_decoder() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples_per_message * 2,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut stream = vec![];
+    for msg in encode_all_messages(&mut enc, &data).unwrap() {
+        write_frame(&mut stream, msg.as_bytes()).unwrap();
+    }
+
+    let mut dec = StreamDecoder::new(Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    ));
+
+    // split within the first length prefix, the first message, and the second message
+    let first_len = stream.len() / 2;
+    let splits = [1, first_len - 10, first_len + 30];
+    let mut decoded = vec![];
+    let mut start = 0;
+    for end in splits.into_iter().chain([stream.len()]) {
+        let msgs = dec.push(&stream[start..end]);
+        if end < first_len {
+            assert!(msgs.is_empty());
+            assert_eq!(dec.buffered(), end);
+        }
+        decoded.extend(msgs.into_iter().map(Result::unwrap));
+        start = end;
+    }
+    assert_eq!(dec.buffered(), 0);
+
+    assert_eq!(decoded.len(), 2);
+    let samples: Vec<&DatasetWithQuality> = decoded.iter().flat_map(|m| &m.samples).collect();
+    assert_eq!(samples.len(), data.len());
+    for (decoded, d) in samples.iter().zip(&data) {
+        assert_eq!(decoded.i32s, d.i32s);
+        assert_eq!(decoded.q, d.q);
+    }
+
+    // a frame longer than the maximum is an error, and is not buffered
+    dec.set_max_frame_length(first_len - 10);
+    let msgs = dec.push(&stream[..first_len]);
+    assert_eq!(msgs.len(), 1);
+    assert!(matches!(msgs[0], Err(JetstreamError::Corrupt(_))));
+    assert_eq!(dec.buffered(), 0);
+
+    // the stream decodes again once the maximum allows it
+    dec.set_max_frame_length(stream.len());
+    let msgs = dec.push(&stream);
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs.iter().all(Result::is_ok));
+}
+
+#[test]
+fn test_unreferenced_neutrals() {
+    let test = TESTS.get("b4000-4000s2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut ied2: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data_dual_ied(
+        &mut ied,
+        &mut ied2,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut total_bytes = vec![];
+    for neutral in [
+        NeutralRefs::Excluded,
+        NeutralRefs::Included,
+        NeutralRefs::Unreferenced,
+    ] {
+        let id = uuid::Uuid::new_v4();
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.set_spatial_refs_with(test.count_of_variables, 2, 2, neutral)
+            .unwrap();
+        dec.set_spatial_refs_with(test.count_of_variables, 2, 2, neutral);
+
+        let encode_stats = encode_and_decode(
+            true,
+            &mut data.clone(),
+            &mut enc,
+            &mut dec,
+            test.count_of_variables,
+            test.samples_per_message,
+            false,
+            false,
+        )
+        .unwrap();
+        total_bytes.push(encode_stats.total_bytes);
+    }
+
+    // neutrals are encoded directly, but the phases still reference the previous set
+    assert!(
+        total_bytes[2] < total_bytes[0] && total_bytes[2] < total_bytes[1],
+        "{:?}",
+        total_bytes
+    );
+}
+
+#[test]
+fn test_no_delta_leakage_between_messages() {
+    for name in ["a10-10", "b4000-80"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data: Vec<DatasetWithQuality> = create_input_data(
+            &mut ied,
+            test.samples_per_message * 3,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        // make each message very different from the last
+        data.iter_mut().enumerate().for_each(|(k, d)| {
+            let offset = 1_000_000 * (k / test.samples_per_message) as i32;
+            d.i32s.iter_mut().for_each(|v| *v = offset - *v);
+        });
+
+        let new_encoder = || {
+            Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            )
+        };
+        let mut enc = new_encoder();
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        // a cancelled message must not leak into the next either
+        for d in &data[..test.samples_per_message / 2] {
+            enc.encode(d).unwrap();
+        }
+        enc.cancel_encode();
+
+        for chunk in data.chunks(test.samples_per_message) {
+            let msg = encode_all_messages(&mut enc, chunk).unwrap().remove(0);
+            let fresh = encode_all_messages(&mut new_encoder(), chunk)
+                .unwrap()
+                .remove(0);
+            assert_eq!(msg, fresh, "{}", name);
+
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for (i, d) in chunk.iter().enumerate() {
+                assert_eq!(dec.out[i].i32s, d.i32s, "{} at {}", name, i);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_quality_width() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-10").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let input = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        false,
+    );
+
+    let encode = |width: QualityWidth, quality: u32| {
+        let mut data = input.clone();
+        data.iter_mut().for_each(|d| d.q.fill(quality));
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.set_quality_width(width)?;
+        let msg = encode_all_messages(&mut enc, &data)?.remove(0);
+
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (i, d) in data.iter().enumerate() {
+            assert_eq!(dec.out[i].q, d.q, "{:?} at {}", width, i);
+        }
+        Ok::<_, JetstreamError>(msg.len())
+    };
+
+    // values which fit in one byte as a uvarint are no smaller with a fixed width: the u8 flag
+    // needs a second byte for the header flags, and each variable's value takes a second byte
+    // with u16
+    let uvarint = encode(QualityWidth::U32, 0x41).unwrap();
+    assert_eq!(encode(QualityWidth::U8, 0x41).unwrap(), uvarint + 1);
+    assert_eq!(
+        encode(QualityWidth::U16, 0x41).unwrap(),
+        uvarint + test.count_of_variables
+    );
+
+    // a value with the top bit of a u16 set needs three bytes as a uvarint
+    let uvarint = encode(QualityWidth::U32, 0x8001).unwrap();
+    assert_eq!(
+        encode(QualityWidth::U16, 0x8001).unwrap(),
+        uvarint - test.count_of_variables
+    );
+
+    assert!(encode(QualityWidth::U8, 0x100).is_err());
+    assert!(encode(QualityWidth::U16, 0x1_0000).is_err());
+
+    // a header which sets both the u8 and u16 widths is corrupt
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    enc.set_quality_width(QualityWidth::U8).unwrap();
+    let msg = encode_all_messages(&mut enc, &input).unwrap().remove(0);
+    let mut forged = msg.as_bytes().to_vec();
+    assert_eq!(forged[0] & 0x80, 0x80, "the u8 flag needs a second byte");
+    forged[0] |= 0x40; // FLAG_QUALITY_U16
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    assert!(matches!(
+        dec.decode_to_buffer(&forged, forged.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+}
+
+#[test]
+fn test_simple8b_runs_of_ones() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+
+    // 300 samples end with a run of 240, and 487 samples also need a run of 120
+    for samples in [300, 487, 1000, 2000] {
+        // with three layers of delta encoding, a constant third difference of -1 is zig-zag
+        // encoded as a run of 1, which simple-8b stores in words of 120 or 240 values
+        let mut data = vec![DatasetWithQuality::new(count_of_variables); samples];
+        for i in 0..count_of_variables {
+            let (mut val, mut d1, mut d2) = (1_000_000 * i as i32, 0, 0);
+            for (k, d) in data.iter_mut().enumerate() {
+                d2 -= 1;
+                d1 += d2;
+                val += d1;
+                d.i32s[i] = val;
+                d.t = k as u64;
+            }
+        }
+
+        let mut enc = Encoder::new(id, count_of_variables, 4000, samples);
+        let mut dec = Decoder::new(id, count_of_variables, 4000, samples);
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, d) in data.iter().enumerate() {
+            assert_eq!(dec.out[k].i32s, d.i32s, "{} at {}", samples, k);
+        }
+
+        // once the runs outweigh the header and the first few values, the message is less than
+        // half the size of packing one bit per value, which needs a word for every 60 samples
+        if samples >= 1000 {
+            let one_bit_words = count_of_variables * samples.div_ceil(60);
+            assert!(
+                msg.len() * 2 < one_bit_words * 8,
+                "{}: {} bytes",
+                samples,
+                msg.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_heartbeat() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-10").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        false,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut stream = StreamDecoder::new(dec);
+
+    // a heartbeat part way through a message does not disturb the buffered samples
+    let mut framed = vec![];
+    for (k, d) in data.iter().enumerate() {
+        if k == 3 {
+            let heartbeat = enc.heartbeat();
+            assert_eq!((heartbeat.len(), heartbeat.samples()), (18, 0));
+            write_frame(&mut framed, heartbeat.as_bytes()).unwrap();
+        }
+        if let Some(msg) = enc.encode(d).unwrap() {
+            write_frame(&mut framed, msg.as_bytes()).unwrap();
+        }
+    }
+
+    let msgs: Vec<DecodedMessage> = stream
+        .push(&framed)
+        .into_iter()
+        .map(|msg| msg.unwrap())
+        .collect();
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs[0].heartbeat);
+    assert!(msgs[0].samples.is_empty());
+    assert!(!msgs[1].heartbeat);
+    for (k, d) in data.iter().enumerate() {
+        assert_eq!(msgs[1].samples[k].i32s, d.i32s);
+    }
+
+    // a heartbeat for another stream is rejected like any other message
+    let other = Encoder::new(
+        uuid::Uuid::new_v4(),
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let heartbeat = other.heartbeat();
+    assert!(matches!(
+        stream
+            .decoder
+            .decode_to_buffer(heartbeat.as_bytes(), heartbeat.len()),
+        Err(JetstreamError::IdMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_aligned_words() {
+    for name in ["a10-10", "b4000-80", "b4000-4000", "f40000-40000"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            false,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.align_words = true;
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, d) in data.iter().enumerate() {
+            assert_eq!(dec.out[k].i32s, d.i32s, "{} at {}", name, k);
+        }
+
+        // only uncompressed simple-8b words are aligned
+        let bytes = msg.as_bytes();
+        let (flags, flags_len) = uvarint32(bytes).unwrap();
+        let aligned = test.samples_per_message > 16 && test.samples_per_message <= 4096;
+        assert_eq!(flags & FLAG_ALIGNED_WORDS != 0, aligned, "{}", name);
+        if !aligned {
+            continue;
+        }
+
+        // the header is padded with zeros up to the first word, which holds the first value
+        let (_, samples_len) = varint32(&bytes[flags_len + 16 + 1 + 8..]).unwrap();
+        let header_len = flags_len + 16 + 1 + 8 + samples_len;
+        let words = header_len.next_multiple_of(8);
+        assert!(bytes[header_len..words].iter().all(|&b| b == 0), "{}", name);
+
+        let mut first = None;
+        simple8b::for_each(&bytes[words..], |v| {
+            first = Some(bitops::zig_zag_decode64(v) as i32);
+            false
+        })
+        .unwrap();
+        assert_eq!(first, Some(data[0].i32s[0]), "{}", name);
+    }
+}
+
+#[test]
+fn test_inspect() {
+    for (name, simple8b, compression) in [
+        ("a10-10", false, Compression::None),
+        ("b4000-80", true, Compression::None),
+        ("f40000-40000", true, Compression::Gzip),
+    ] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            false,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        assert_eq!(
+            dec.inspect(msg.as_bytes()).unwrap(),
+            MessageInfo {
+                id,
+                heartbeat: false,
+                samples: test.samples_per_message,
+                sampling_rate: test.sampling_rate,
+                simple8b,
+                compression,
+                delta_encoding_layers: 3,
+                little_endian: false,
+            },
+            "{}",
+            name
+        );
+
+        // a partial message at another rate, in little-endian byte order
+        enc.set_sampling_rate(2 * test.sampling_rate).unwrap();
+        enc.set_little_endian(true);
+        let msg = encode_all_messages(&mut enc, &data[..5]).unwrap().remove(0);
+        let info = dec.inspect(msg.as_bytes()).unwrap();
+        assert_eq!(
+            (
+                info.samples,
+                info.sampling_rate,
+                info.compression,
+                info.little_endian
+            ),
+            (5, 2 * test.sampling_rate, Compression::None, true),
+            "{}",
+            name
+        );
+
+        let info = dec.inspect(enc.heartbeat().as_bytes()).unwrap();
+        assert!(info.heartbeat);
+        assert_eq!(info.samples, 0);
+
+        // messages from other streams and truncated headers
+        let other = Encoder::new(
+            uuid::Uuid::new_v4(),
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        assert_eq!(
+            dec.inspect(other.heartbeat().as_bytes()).unwrap().id,
+            other.id
+        );
+        assert!(matches!(
+            dec.inspect(&msg.as_bytes()[..20]),
+            Err(JetstreamError::Truncated { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_early_flush_around_gzip_threshold() {
+    let id = uuid::Uuid::new_v4();
+    let count_of_variables = 8;
+    let sampling_rate = 4000;
+    let samples_per_message = 4100;
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, samples_per_message, count_of_variables, false);
+
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+
+    // gzip is only used for messages with more than 4096 samples, however full the message
+    // was meant to be
+    for (samples, gzip) in [(10, false), (4096, false), (4097, true), (4100, true)] {
+        let compression = if gzip {
+            Compression::Gzip
+        } else {
+            Compression::None
+        };
+        let msg = encode_all_messages(&mut enc, &data[..samples])
+            .unwrap()
+            .remove(0);
+        let info = dec.inspect(msg.as_bytes()).unwrap();
+        assert_eq!((info.samples, info.compression), (samples, compression));
+
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, d) in data[..samples].iter().enumerate() {
+            assert_eq!(dec.out[k].i32s, d.i32s, "{} at {}", samples, k);
+            assert_eq!(dec.out[k].q, d.q, "{} at {}", samples, k);
+        }
+    }
+}
+
+#[test]
+fn test_invalid_samples() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-10").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        false,
+    );
+
+    // the first variable is marked invalid for the last four samples
+    data.iter_mut().skip(6).for_each(|d| d.q[0] = 0x01);
+
+    for policy in [
+        InvalidSamples::Keep,
+        InvalidSamples::Zero,
+        InvalidSamples::HoldLast,
+    ] {
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.invalid_samples = policy;
+
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, d) in data.iter().enumerate() {
+            let expected = match policy {
+                _ if k < 6 => d.i32s[0],
+                InvalidSamples::Keep => d.i32s[0],
+                InvalidSamples::Zero => 0,
+                InvalidSamples::HoldLast => data[5].i32s[0],
+            };
+            assert_eq!(dec.out[k].i32s[0], expected, "{:?} at {}", policy, k);
+            assert_eq!(dec.out[k].i32s[1..], d.i32s[1..], "{:?} at {}", policy, k);
+            assert_eq!(dec.out[k].q, d.q, "{:?} at {}", policy, k);
+        }
+
+        // the output beyond a message flushed early is treated the same way
+        let msg = encode_all_messages(&mut enc, &data[..3]).unwrap().remove(0);
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for k in 3..test.samples_per_message {
+            let expected = match policy {
+                InvalidSamples::Keep => data[k].i32s[1],
+                InvalidSamples::Zero => 0,
+                InvalidSamples::HoldLast => data[2].i32s[1],
+            };
+            assert_eq!(dec.out[k].i32s[1], expected, "{:?} at {}", policy, k);
+        }
+    }
+}
+
+#[test]
+fn test_stream_encoder() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    // two full messages and a partial one
+    let samples = 2 * test.samples_per_message + 15;
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, samples, test.count_of_variables, false);
+
+    let enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut stream = StreamEncoder::new(enc, vec![]);
+    for (k, d) in data.iter().enumerate() {
+        stream.write_sample(d).unwrap();
+
+        // each message is written as soon as it is complete
+        assert_eq!(
+            stream.get_ref().is_empty(),
+            k + 1 < test.samples_per_message
+        );
+    }
+    let framed = stream.into_inner().unwrap();
+
+    let dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let decoded: Vec<DatasetWithQuality> = StreamDecoder::new(dec)
+        .push(&framed)
+        .into_iter()
+        .flat_map(|msg| msg.unwrap().samples)
+        .collect();
+    assert_eq!(decoded.len(), samples);
+    for (k, d) in data.iter().enumerate() {
+        assert_eq!(decoded[k].i32s, d.i32s, "at {}", k);
+        assert_eq!(decoded[k].q, d.q, "at {}", k);
+    }
+}
+
+#[test]
+fn test_expected_message_bytes() {
+    for name in [
+        "a10-1",
+        "a10-2q",
+        "b4000-80",
+        "b4000-4000",
+        "c4800-20",
+        "f40000-40000",
+    ] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let expected = dec.expected_message_bytes();
+
+        let new_encoder = || {
+            Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            )
+        };
+        let msg = encode_all_messages(&mut new_encoder(), &data)
+            .unwrap()
+            .remove(0);
+        assert!(
+            msg.len() <= expected,
+            "{}: {} > {}",
+            name,
+            msg.len(),
+            expected
+        );
+
+        // the worst case, with every option and noisy values and quality
+        data.iter_mut().enumerate().for_each(|(k, d)| {
+            d.i32s
+                .iter_mut()
+                .for_each(|v| *v += (1 << 24) * (k as i32 & 1));
+            d.q.fill(u32::MAX - (k as u32 & 1));
+            d.t = (k as u64) << 40;
+        });
+        let mut enc = new_encoder();
+        enc.set_sampling_rate(test.sampling_rate + 1).unwrap();
+        enc.set_sample_timestamps(true);
+        enc.store_channel_ranges = true;
+        enc.align_words = true;
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        assert!(
+            msg.len() <= expected,
+            "{}: {} > {}",
+            name,
+            msg.len(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_quality_check() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-10").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let input = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        false,
+    );
+
+    // 0x41 is invalid with the failure detail set, and 0x8000 and 0x02 (the reserved validity)
+    // are malformed
+    for (quality, malformed) in [
+        (0x41, false),
+        (0x0100_2000, false),
+        (0x8000, true),
+        (0x02, true),
+    ] {
+        let mut data = input.clone();
+        data[3].q[0] = quality;
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+
+        for check in [
+            QualityCheck::Ignore,
+            QualityCheck::Warn,
+            QualityCheck::Reject,
+        ] {
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            dec.quality_check = check;
+
+            let result = dec.decode_to_buffer(msg.as_bytes(), msg.len());
+            if malformed && check == QualityCheck::Reject {
+                assert!(matches!(result, Err(JetstreamError::InvalidQuality(q)) if q == quality));
+            } else {
+                result.unwrap();
+                assert_eq!(dec.out[3].q[0], quality);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_analyze() {
+    for name in ["b4000-80", "b4000-4000"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let report = analyze(&mut enc, &data).unwrap();
+
+        assert_eq!(report.samples, test.samples);
+        assert_eq!(
+            report.messages,
+            test.samples.div_ceil(test.samples_per_message)
+        );
+        assert_eq!(
+            report.raw_bytes,
+            test.samples * test.count_of_variables * 16
+        );
+
+        // both compress the data, and jetstream compresses it further than gzip
+        assert!(
+            report.jetstream_ratio() <= test.expected_size / 100.0,
+            "{}",
+            name
+        );
+        assert!(report.jetstream_ratio() < report.gzip_ratio(), "{}", name);
+        assert!(report.gzip_ratio() < 1.0, "{}", name);
+    }
+}
+
+#[test]
+fn test_active_channels() {
+    for name in ["a10-2", "b4000-80", "b4000-4000s2"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data: Vec<DatasetWithQuality> = if test.count_of_variables == 16 {
+            let mut ied2: Emulator = create_emulator(test.sampling_rate, 0.0);
+            create_input_data_dual_ied(
+                &mut ied,
+                &mut ied2,
+                test.samples,
+                test.count_of_variables,
+                test.quality_change,
+            )
+        } else {
+            create_input_data(
+                &mut ied,
+                test.samples,
+                test.count_of_variables,
+                test.quality_change,
+            )
+        };
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.store_channel_ranges = true;
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        if test.use_spatial_refs {
+            let (count_v, count_i) = (test.count_of_variables / 8, test.count_of_variables / 8);
+            enc.set_spatial_refs(test.count_of_variables, count_v, count_i, true)
+                .unwrap();
+            dec.set_spatial_refs(test.count_of_variables, count_v, count_i, true);
+        }
+        assert!(enc.set_active_channels(&[true]).is_err());
+        assert!(enc
+            .set_channel_active(test.count_of_variables, false)
+            .is_err());
+
+        // disable the first current, which is a spatial reference for the others, in the
+        // second message only
+        let inactive = 4;
+        for (m, chunk) in data.chunks(test.samples_per_message).take(3).enumerate() {
+            enc.set_channel_active(inactive, m != 1).unwrap();
+            let msg = encode_all_messages(&mut enc, chunk).unwrap().remove(0);
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+            for i in 0..test.count_of_variables {
+                let absent = m == 1 && i == inactive;
+                assert_eq!(dec.channel_active(i), !absent, "{} at {},{}", name, m, i);
+                assert_eq!(
+                    dec.channel_range(i).is_none(),
+                    absent,
+                    "{} at {},{}",
+                    name,
+                    m,
+                    i
+                );
+                if absent {
+                    continue;
+                }
+                for (k, d) in chunk.iter().enumerate() {
+                    assert_eq!(
+                        dec.out[k].i32s[i], d.i32s[i],
+                        "{} at {},{},{}",
+                        name, m, k, i
+                    );
+                    assert_eq!(dec.out[k].q[i], d.q[i], "{} at {},{},{}", name, m, k, i);
+                }
+            }
+        }
+
+        // the active set can only be changed between messages
+        enc.encode(&data[0]).unwrap();
+        assert!(enc.set_channel_active(inactive, false).is_err());
+    }
+}
+
+#[test]
+fn test_decode_to_f64() {
+    for name in ["a10-2", "b4000-80"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut raw_dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let scale_factors: Vec<f64> = [
+            Channel::PhaseAI,
+            Channel::PhaseBI,
+            Channel::PhaseCI,
+            Channel::NeutralI,
+            Channel::PhaseAV,
+            Channel::PhaseBV,
+            Channel::PhaseCV,
+            Channel::NeutralV,
+        ]
+        .iter()
+        .map(|channel| channel.scale_factor())
+        .collect();
+        assert!(matches!(
+            enc.set_scale_factors(&scale_factors[1..]),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+        assert!(enc.scale_factors().iter().all(|&scale| scale == 1.0));
+        enc.set_scale_factors(&scale_factors).unwrap();
+        dec.set_scale_factors(enc.scale_factors());
+
+        // include a final, shorter message
+        let samples = test.samples_per_message * 2 + 1;
+        for msg in encode_all_messages(&mut enc, &data[..samples]).unwrap() {
+            raw_dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            let scaled = dec.decode_to_f64(msg.as_bytes()).unwrap().to_vec();
+
+            assert_eq!(scaled.len(), msg.samples(), "{}", name);
+            for (k, sample) in scaled.iter().enumerate() {
+                for (i, &value) in sample.iter().enumerate() {
+                    let expected = raw_dec.out[k].i32s[i] as f64 * scale_factors[i];
+                    assert_eq!(value, expected, "{} at {},{}", name, k, i);
+                }
+            }
+            assert_eq!(dec.out_scaled(), scaled);
+        }
+
+        assert!(dec
+            .decode_to_f64(enc.heartbeat().as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[test]
+fn test_bools() {
+    for name in ["a10-2", "b4000-80", "b4000-4000"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let plain_size: usize = encode_all_messages(&mut enc, &data)
+            .unwrap()
+            .iter()
+            .map(|msg| msg.len())
+            .sum();
+
+        // a breaker which opens once, a channel which is always set, and one which toggles
+        // every few samples
+        for (k, d) in data.iter_mut().enumerate() {
+            d.bools = vec![k >= test.samples / 3, true, k % 3 == 0];
+        }
+        assert!(enc.encode(&data[0]).is_err());
+        enc.set_bool_count(3).unwrap();
+        dec.set_bool_count(3);
+
+        let messages = encode_all_messages(&mut enc, &data).unwrap();
+        let mut total_samples = 0;
+        for msg in &messages {
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for k in 0..msg.samples() {
+                let expected = &data[total_samples + k];
+                assert_eq!(dec.out[k].bools, expected.bools, "{} at {}", name, k);
+                assert_eq!(dec.out[k].i32s, expected.i32s, "{} at {}", name, k);
+            }
+            total_samples += msg.samples();
+        }
+
+        // without the toggling channel, the status takes a handful of bytes per message
+        if test.samples_per_message == test.samples {
+            for d in data.iter_mut() {
+                d.bools.pop();
+            }
+            enc.set_bool_count(2).unwrap();
+            let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+            assert!(
+                msg.len() <= plain_size + 8,
+                "{} > {}",
+                msg.len(),
+                plain_size
+            );
+
+            // the decoder must be configured for the same channels
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            assert!(matches!(
+                dec.decode_to_buffer(msg.as_bytes(), msg.len()),
+                Err(JetstreamError::Corrupt(_))
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_plausible_range() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+
+    // allow each variable twice its peak magnitude
+    for i in 0..test.count_of_variables {
+        let peak = data.iter().map(|d| d.i32s[i].abs()).max().unwrap();
+        dec.set_plausible_range(i, Some((-2 * peak, 2 * peak)));
+    }
+
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+
+    // flip a high bit in the payload of the first variable, after the flags, ID, format
+    // descriptor, timestamp and sample count in the header, and the first word
+    let mut corrupted = msg.as_bytes().to_vec();
+    corrupted[1 + 16 + 1 + 8 + 2 + 8 + 1] ^= 0x40;
+    match dec.decode_to_buffer(&corrupted, corrupted.len()) {
+        Err(JetstreamError::Implausible {
+            variable, value, ..
+        }) => {
+            assert_eq!(variable, 0);
+            let peak = data.iter().map(|d| d.i32s[0].abs()).max().unwrap();
+            assert!(value.abs() > 2 * peak);
+        }
+        result => panic!("corruption was not detected: {:?}", result),
+    }
+
+    // without the check, the corrupt values are decoded
+    dec.set_plausible_range(0, None);
+    dec.decode_to_buffer(&corrupted, corrupted.len()).unwrap();
+    assert!(dec
+        .out
+        .iter()
+        .zip(&data)
+        .any(|(d, e)| d.i32s[0] != e.i32s[0]));
+}
+
+#[test]
+fn test_finish() {
+    let id = uuid::Uuid::new_v4();
+    let (sampling_rate, count_of_variables, samples_per_message) = (4000, 8, 80);
+
+    let mut ied: Emulator = create_emulator(sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, samples_per_message, count_of_variables, false);
+    let mut dec = Decoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+
+    // an encoder with no buffered samples has nothing to flush, and no message to end
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    assert!(matches!(
+        enc.end_encode(),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+    assert!(matches!(enc.finish(), Ok(None)));
+
+    // a half-full encoder returns the buffered samples as a partial message
+    let mut enc = Encoder::new(id, count_of_variables, sampling_rate, samples_per_message);
+    let half = samples_per_message / 2;
+    for d in &data[..half] {
+        assert!(matches!(enc.encode(d), Ok(None)));
+    }
+    let msg = enc.finish().unwrap().unwrap();
+    assert_eq!(msg.samples(), half);
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    assert_eq!(dec.encoded_samples(), half);
+    for (decoded, expected) in dec.out.iter().zip(&data[..half]) {
+        assert_eq!(decoded.i32s, expected.i32s);
+    }
+}
+
+#[test]
+fn test_encode_and_decode_message() {
+    for name in ["a10-10", "b4000-4000", "e14400-14400q"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let msg = encode_message(id, test.count_of_variables, test.sampling_rate, &data).unwrap();
+        assert_eq!(msg.samples(), test.samples);
+
+        let decoded = decode_message(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples,
+            msg.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(decoded.len(), test.samples, "{}", name);
+        for (k, (d, expected)) in decoded.iter().zip(&data).enumerate() {
+            assert_eq!(d.i32s, expected.i32s, "{} at {}", name, k);
+            assert_eq!(d.q, expected.q, "{} at {}", name, k);
+        }
+
+        // a message from another stream is rejected
+        let other = uuid::Uuid::new_v4();
+        let result = decode_message(
+            other,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples,
+            msg.as_bytes(),
+        );
+        assert!(matches!(result, Err(JetstreamError::IdMismatch { .. })));
+    }
+    assert!(encode_message(uuid::Uuid::new_v4(), 8, 4000, &[]).is_err());
+}
+
+#[test]
+fn test_decode_iter() {
+    for name in ["a10-2", "b4000-80", "e14400-14400"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        // a full message, then one which is stopped early
+        let samples = usize::min(test.samples, test.samples_per_message + 1);
+        let mut total_samples = 0;
+        for msg in encode_all_messages(&mut enc, &data[..samples]).unwrap() {
+            let samples = dec.decode_iter(msg.as_bytes()).unwrap();
+            assert_eq!(samples.len(), msg.samples(), "{}", name);
+
+            let mut count = 0;
+            for (decoded, expected) in samples.zip(&data[total_samples..]) {
+                assert_eq!(decoded.i32s, expected.i32s, "{} at {}", name, count);
+                assert_eq!(decoded.q, expected.q, "{} at {}", name, count);
+                count += 1;
+            }
+            assert_eq!(count, msg.samples(), "{}", name);
+            total_samples += count;
+        }
+        assert_eq!(total_samples, samples, "{}", name);
+
+        assert_eq!(
+            dec.decode_iter(enc.heartbeat().as_bytes()).unwrap().count(),
+            0
+        );
+    }
+}
+
+#[test]
+fn test_quality_first() {
+    for name in ["a10-2q", "a8-8q", "d4000-4000q", "e14400-14400q"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+        for (k, d) in data.iter_mut().enumerate() {
+            d.bools = vec![k % 5 == 0];
+        }
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        enc.quality_first = true;
+        enc.align_words = true;
+        enc.set_bool_count(1).unwrap();
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.set_bool_count(1);
+
+        let messages = encode_all_messages(&mut enc, &data).unwrap();
+        let mut total_samples = 0;
+        for msg in &messages {
+            let (flags, _) = uvarint32(msg.as_bytes()).unwrap();
+            assert_eq!(flags & FLAG_ALIGNED_WORDS, 0, "{}", name);
+
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for k in 0..msg.samples() {
+                let expected = &data[total_samples + k];
+                assert_eq!(dec.out[k].i32s, expected.i32s, "{} at {}", name, k);
+                assert_eq!(dec.out[k].q, expected.q, "{} at {}", name, k);
+                assert_eq!(dec.out[k].bools, expected.bools, "{} at {}", name, k);
+            }
+            total_samples += msg.samples();
+        }
+        assert_eq!(total_samples, test.samples, "{}", name);
+
+        // the quality can be read from an uncompressed message with the samples cut short,
+        // which shows that they are not parsed
+        if enc.compression_for(test.samples_per_message) != Compression::None {
+            continue;
+        }
+        let msg = &messages[0];
+        let truncated = &msg.as_bytes()[..msg.len() - 8];
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        dec.set_bool_count(1);
+        dec.decode_quality(truncated).unwrap();
+        for (k, expected) in data[..msg.samples()].iter().enumerate() {
+            assert_eq!(dec.out[k].q, expected.q, "{} at {}", name, k);
+            assert_eq!(dec.out[k].bools, expected.bools, "{} at {}", name, k);
+            assert!(dec.out[k].i32s.iter().all(|&v| v == 0), "{} at {}", name, k);
+        }
+    }
+}
+
+#[test]
+fn test_diff() {
+    let test = TESTS.get("a10-10").unwrap();
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    assert!(diff(&data, &data).is_empty());
+
+    // a single variable differs, and the timestamps are ignored
+    let mut other = data.clone();
+    other[4].i32s[Channel::PhaseBV.index()] += 1;
+    other[7].t += 1;
+    assert_eq!(
+        diff(&data, &other),
+        vec![Mismatch {
+            sample: 4,
+            channel: Channel::PhaseBV.index(),
+            a_val: data[4].i32s[5],
+            b_val: data[4].i32s[5] + 1,
+            a_quality: 0,
+            b_quality: 0,
+        }]
+    );
+
+    // quality differences are reported too, and only the common samples are compared
+    other[9].q[0] = 1;
+    let mismatches = diff(&data, &other[..8]);
+    assert_eq!(mismatches.len(), 1);
+    let mismatches = diff(&data, &other);
+    assert_eq!(mismatches.len(), 2);
+    assert_eq!((mismatches[1].sample, mismatches[1].channel), (9, 0));
+    assert_eq!(mismatches[1].b_quality, 1);
+}
+
+#[test]
+fn test_xor_from_header() {
+    for name in ["a10-2", "b4000-80", "e14400-14400"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+
+        // the decoder is not configured for XOR, but follows the header of each message
+        for use_xor in [true, false, true] {
+            enc.use_xor = use_xor;
+            let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            assert_eq!(dec.use_xor, use_xor, "{}", name);
+            assert!(diff(&data, &dec.out).is_empty(), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_varint_boundaries() {
+    // every value fits in the five bytes reserved for a 32-bit varint
+    let mut buf = [0; 5];
+
+    for x in [i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX] {
+        let n = put_varint32(&mut buf, x);
+        assert_eq!(varint32(&buf[..n]).unwrap(), (x, n), "{}", x);
+    }
+
+    // values either side of each 7-bit boundary, where the encoded length changes
+    let mut values = vec![0, 1, u32::MAX - 1, u32::MAX];
+    for bits in [7, 14, 21, 28] {
+        values.extend([(1 << bits) - 1, 1 << bits]);
+    }
+    for x in values {
+        let n = put_uvarint32(&mut buf, x);
+        let expected = usize::max(1, (32 - x.leading_zeros() as usize).div_ceil(7));
+        assert_eq!(n, expected, "{}", x);
+        assert_eq!(uvarint32(&buf[..n]).unwrap(), (x, n), "{}", x);
+
+        // the zigzag encoding of a signed value uses the same bytes
+        let n = put_varint32(&mut buf, x as i32);
+        assert_eq!(varint32(&buf[..n]).unwrap(), (x as i32, n), "{}", x);
+    }
+}
+
+#[test]
+fn test_varint_overflow() {
+    let mut buf = [0; 10];
+    let n = put_uvarint32(&mut buf, u32::MAX);
+    assert_eq!(uvarint32(&buf).unwrap(), (u32::MAX, n));
+    let n = put_uvarint64(&mut buf, u64::MAX);
+    assert_eq!(uvarint64(&buf).unwrap(), (u64::MAX, n));
+
+    // a value which does not fit is corrupt, and one which is cut short is truncated
+    let ones = [0xff; 10];
+    assert!(matches!(uvarint32(&ones), Err(JetstreamError::Corrupt(_))));
+    assert!(matches!(
+        uvarint32(&[0xff, 0xff, 0xff, 0xff, 0x10]),
+        Err(JetstreamError::Corrupt(_))
+    ));
+    assert!(matches!(uvarint64(&ones), Err(JetstreamError::Corrupt(_))));
+    assert!(matches!(
+        varint32(&ones[..3]),
+        Err(JetstreamError::Truncated { .. })
+    ));
+    assert!(matches!(
+        uvarint32(&[]),
+        Err(JetstreamError::Truncated { .. })
+    ));
+
+    // a malformed message is rejected by the decoder rather than panicking
+    let id = uuid::Uuid::new_v4();
+    let mut dec = Decoder::new(id, 8, 4000, 2);
+    assert!(matches!(
+        dec.decode_to_buffer(&ones, ones.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+    assert!(matches!(
+        dec.inspect(&ones),
+        Err(JetstreamError::Corrupt(_))
+    ));
+
+    // a corrupt length prefix discards the stream buffer
+    let mut stream = StreamDecoder::new(dec);
+    let results = stream.push(&ones);
+    assert_eq!(results.len(), 1);
+    assert!(matches!(results[0], Err(JetstreamError::Corrupt(_))));
+    assert_eq!(stream.buffered(), 0);
+
+    // values at the limits of an i32 wrap in the deltas and spatial references, and are
+    // recovered exactly
+    let mut enc = Encoder::new(id, 16, 4000, 80);
+    enc.set_spatial_refs(16, 2, 2, true).unwrap();
+    let mut dec = Decoder::new(id, 16, 4000, 80);
+    dec.set_spatial_refs(16, 2, 2, true);
+    let mut data = vec![DatasetWithQuality::new(16); 80];
+    for (k, d) in data.iter_mut().enumerate() {
+        d.t = k as u64;
+        for (i, value) in d.i32s.iter_mut().enumerate() {
+            *value = if (k + i) % 2 == 0 { i32::MAX } else { i32::MIN };
+        }
+    }
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    for (k, d) in data.iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, d.i32s, "at {}", k);
+    }
+
+    // a corrupt payload decodes to garbage or is rejected, rather than overflowing
+    let test = TESTS.get("b4000-80").unwrap();
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut data = create_input_data(&mut ied, 80, 8, false);
+    for d in data.iter_mut() {
+        d.i32s.extend_from_within(..);
+        d.q.extend_from_within(..);
+    }
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    for position in 28..msg.len() {
+        for mask in [0xff, 0x80, 0x01] {
+            let mut corrupt = msg.as_bytes().to_vec();
+            corrupt[position] ^= mask;
+            let _ = dec.decode_to_buffer(&corrupt, corrupt.len());
+        }
+    }
+}
+
+#[test]
+fn test_channel_stats() {
+    let id = uuid::Uuid::new_v4();
+    let mut enc = Encoder::new(id, 3, 4000, 4);
+    let mut data = vec![DatasetWithQuality::new(3); 10];
+    for (k, d) in data.iter_mut().enumerate() {
+        // a ramp, a stuck channel, and one which rails at the limit
+        d.i32s = vec![k as i32 - 2, 7, if k < 5 { 32767 } else { -(k as i32) }];
+    }
+
+    // statistics are only kept once enabled
+    enc.encode(&data[0]).unwrap();
+    assert_eq!(enc.channel_stats(0), None);
+    enc.set_channel_stats(true);
+    assert_eq!(enc.channel_stats(0), None);
+
+    // they span messages, including the partial message still being buffered
+    for d in &data[1..] {
+        enc.encode(d).unwrap();
+    }
+    let ramp = enc.channel_stats(0).unwrap();
+    assert_eq!((ramp.min, ramp.max, ramp.samples), (-1, 7, 9));
+    assert_eq!(ramp.mean(), 3.0);
+    let stuck = enc.channel_stats(1).unwrap();
+    assert_eq!((stuck.min, stuck.max, stuck.mean()), (7, 7, 7.0));
+    let railing = enc.channel_stats(2).unwrap();
+    assert_eq!((railing.min, railing.max), (-9, 32767));
+    assert_eq!(
+        railing.mean(),
+        (4.0 * 32767.0 - (5..10).sum::<i32>() as f64) / 9.0
+    );
+
+    // an inactive variable is not included, and disabling discards the statistics
+    enc.end_encode().unwrap();
+    enc.set_channel_active(1, false).unwrap();
+    enc.encode(&data[0]).unwrap();
+    assert_eq!(enc.channel_stats(1).unwrap().samples, 9);
+    assert_eq!(enc.channel_stats(0).unwrap().samples, 10);
+    enc.set_channel_stats(false);
+    assert_eq!(enc.channel_stats(0), None);
+}
+
+#[test]
+fn test_truncated_at_every_offset() {
+    for (name, features) in [
+        ("a10-2q", false),
+        ("a10-2q", true),
+        ("b4000-80", false),
+        ("b4000-80", true),
+        ("e14400-14400", false),
+    ] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let mut data = create_input_data(
+            &mut ied,
+            test.samples_per_message,
+            test.count_of_variables,
+            true,
+        );
+
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let mut dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        if features {
+            // every optional field of the header and payload
+            for (k, d) in data.iter_mut().enumerate() {
+                d.bools = vec![k % 3 == 0];
+            }
+            enc.set_bool_count(1).unwrap();
+            dec.set_bool_count(1);
+            enc.store_channel_ranges = true;
+            enc.align_words = true;
+            enc.set_sample_timestamps(true);
+            enc.set_quality_width(QualityWidth::U16).unwrap();
+            enc.set_channel_active(Channel::NeutralV.index(), false)
+                .unwrap();
+        }
+        let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+        let buf = msg.as_bytes();
+        dec.decode_to_buffer(buf, buf.len()).unwrap();
+
+        // the whole of an uncompressed message is read, so every cut is reported as such
+        let gzip = enc.compression_for(test.samples_per_message) != Compression::None;
+        let step = if gzip { buf.len() / 100 } else { 1 };
+        for cut in (0..buf.len()).step_by(step) {
+            let result = dec.decode_to_buffer(&buf[..cut], cut);
+            if gzip {
+                assert!(result.is_err(), "{} cut at {}", name, cut);
+            } else {
+                assert!(
+                    matches!(
+                        result,
+                        Err(JetstreamError::Truncated { needed, got }) if got == cut && needed > cut
+                    ),
+                    "{} cut at {}: {:?}",
+                    name,
+                    cut,
+                    result
+                );
+            }
+            let _ = dec.inspect(&buf[..cut]);
+        }
+    }
+}
+
+#[test]
+fn test_truncated_payload() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        false,
+    );
+
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    enc.set_quality_width(QualityWidth::U16).unwrap();
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    let buf = msg.as_bytes();
+
+    // the quality section follows the words, as a two byte value and a one byte run length of
+    // zero for each variable; without the final word, the samples are cut short
+    let quality_len = test.count_of_variables * 3;
+    let cut = buf.len() - quality_len - 8;
+    assert!(matches!(
+        dec.decode_to_buffer(&buf[..cut], cut),
+        Err(JetstreamError::Truncated { needed, got }) if needed == cut + 8 && got == cut
+    ));
+
+    // the quality section is cut off within the value of the last variable
+    let cut = buf.len() - 2;
+    let err = dec.decode_to_buffer(&buf[..cut], cut).unwrap_err();
+    assert!(matches!(
+        err,
+        JetstreamError::Truncated { needed, got } if needed == cut + 1 && got == cut
+    ));
+    assert_eq!(
+        err.to_string(),
+        format!("truncated message: needed {} bytes, got {}", cut + 1, cut)
+    );
+}
+
+#[test]
+fn test_decoder_reset() {
+    let mut dec: Option<Decoder> = None;
+    for name in ["a10-2q", "b4000-4000", "a8-8q", "e14400-14400q"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let messages = encode_all_messages(&mut enc, &data).unwrap();
+
+        // reuse the decoder from the previous stream, which had different settings
+        let dec = match dec.as_mut() {
+            Some(dec) => {
+                dec.set_bool_count(1);
+                dec.reset(
+                    id,
+                    test.count_of_variables,
+                    test.sampling_rate,
+                    test.samples_per_message,
+                );
+                dec
+            }
+            None => dec.insert(Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            )),
+        };
+        assert_eq!(dec.encoded_samples(), 0, "{}", name);
+        assert_eq!(dec.out.len(), test.samples_per_message, "{}", name);
+
+        let mut total_samples = 0;
+        for msg in &messages {
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for k in 0..msg.samples() {
+                assert_eq!(
+                    dec.out[k].i32s,
+                    data[total_samples + k].i32s,
+                    "{} sample {}",
+                    name,
+                    total_samples + k
+                );
+                assert_eq!(dec.out[k].q, data[total_samples + k].q, "{}", name);
+            }
+            total_samples += msg.samples();
+        }
+        assert_eq!(total_samples, test.samples, "{}", name);
+    }
+
+    // resetting to the same sizes reuses the output buffers
+    let test = TESTS.get("b4000-80").unwrap();
+    let id = uuid::Uuid::new_v4();
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let (out, values) = (dec.out.as_ptr(), dec.out[0].i32s.as_ptr());
+    dec.reset(
+        uuid::Uuid::new_v4(),
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    assert_eq!(dec.out.as_ptr(), out);
+    assert_eq!(dec.out[0].i32s.as_ptr(), values);
+}
+
+#[test]
+fn test_container_channel_counts() {
+    let test = TESTS.get("b4000-80").unwrap();
+    let ids = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+
+    // an 8-channel stream, and a 4-channel stream of the currents
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data: Vec<DatasetWithQuality> = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let currents: Vec<DatasetWithQuality> = data
+        .iter()
+        .map(|d| DatasetWithQuality {
+            t: d.t,
+            i32s: d.i32s[..4].to_vec(),
+            f32s: vec![],
+            q: d.q[..4].to_vec(),
+            bools: vec![],
+        })
+        .collect();
+
+    let mut enc_a = Encoder::new(
+        ids[0],
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut enc_b = Encoder::new(ids[1], 4, test.sampling_rate, test.samples_per_message);
+    let mut msgs = encode_all_messages(&mut enc_b, &currents).unwrap();
+    msgs.extend(encode_all_messages(&mut enc_a, &data).unwrap());
+
+    let container = pack_messages(&msgs);
+    let counts: Vec<usize> = unpack_messages(&container)
+        .unwrap()
+        .iter()
+        .map(|(i32_count, _)| *i32_count)
+        .collect();
+    assert_eq!(counts, [4, test.count_of_variables]);
+
+    // decoders which assume the wrong number of variables are not resized from the sub-headers
+    let mut decoders = [
+        Decoder::new(ids[0], 4, test.sampling_rate, test.samples_per_message),
+        Decoder::new(
+            ids[1],
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        ),
+    ];
+    assert!(matches!(
+        decode_container(&container, &mut decoders),
+        Err(JetstreamError::Corrupt(_))
+    ));
+    assert_eq!(decoders[0].i32_count, 4);
+
+    let mut decoders = [
+        Decoder::new(
+            ids[0],
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        ),
+        Decoder::new(ids[1], 4, test.sampling_rate, test.samples_per_message),
+    ];
+    assert_eq!(decode_container(&container, &mut decoders).unwrap(), [1, 0]);
+    for i in 0..test.samples_per_message {
+        assert_eq!(decoders[0].out[i].i32s, data[i].i32s);
+        assert_eq!(decoders[1].out[i].i32s, currents[i].i32s);
+        assert_eq!(decoders[1].out[i].q, currents[i].q);
+    }
+}
+
+#[test]
+fn test_delta_layers() {
+    let id = uuid::Uuid::new_v4();
+    let (sampling_rate, samples_per_message) = (4000, 80);
+
+    // a slowly rising temperature, in steps of one
+    let mut data = vec![DatasetWithQuality::new(2); samples_per_message * 4];
+    for (k, d) in data.iter_mut().enumerate() {
+        d.t = k as u64;
+        d.i32s = vec![2000 + k as i32 / 10, -(k as i32 / 7)];
+    }
+
+    let mut sizes = vec![];
+    for layers in 1..=4 {
+        let mut enc =
+            Encoder::with_delta_layers(id, 2, sampling_rate, samples_per_message, layers).unwrap();
+        let messages = encode_all_messages(&mut enc, &data).unwrap();
+
+        // the header records the number of layers, so a default decoder follows it
+        let mut dec = Decoder::new(id, 2, sampling_rate, samples_per_message);
+        let mut total_samples = 0;
+        for msg in &messages {
+            assert_eq!(
+                dec.inspect(msg.as_bytes()).unwrap().delta_encoding_layers,
+                layers
+            );
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            for k in 0..msg.samples() {
+                assert_eq!(dec.out[k].i32s, data[total_samples + k].i32s, "{}", layers);
+            }
+            total_samples += msg.samples();
+        }
+        assert_eq!(total_samples, data.len());
+        sizes.push(messages.iter().map(|m| m.len()).sum::<usize>());
+    }
+    assert!(sizes[0] < sizes[2], "{:?}", sizes);
+
+    // a decoder configured for the default number of layers decodes a default stream
+    let mut enc = Encoder::new(id, 2, sampling_rate, samples_per_message);
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    let mut dec = Decoder::with_delta_layers(id, 2, sampling_rate, samples_per_message, 3).unwrap();
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    assert_eq!(
+        dec.out[samples_per_message - 1].i32s,
+        data[samples_per_message - 1].i32s
+    );
+
+    for layers in [0, 5] {
+        assert!(matches!(
+            Encoder::with_delta_layers(id, 2, sampling_rate, samples_per_message, layers),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            Decoder::with_delta_layers(id, 2, sampling_rate, samples_per_message, layers),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn test_compact_id() {
+    let test = TESTS.get("b4000-80").unwrap();
+    let id = uuid::Uuid::from_u64_pair(0x1234_5678_9abc_def0, 0);
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let full = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    enc.compact_id = true;
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+
+    // the ID is 8 bytes shorter, although the flag needs another byte or two of header flags
+    let (_, full_flags_len) = uvarint32(full.as_bytes()).unwrap();
+    let (_, flags_len) = uvarint32(msg.as_bytes()).unwrap();
+    assert_eq!(msg.len() - flags_len, full.len() - full_flags_len - 8);
+
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    assert_eq!(dec.inspect(msg.as_bytes()).unwrap().id, id);
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    for (out, d) in dec.out.iter().zip(&data) {
+        assert_eq!(out.i32s, d.i32s);
+    }
+
+    let heartbeat = enc.heartbeat();
+    let (_, flags_len) = uvarint32(heartbeat.as_bytes()).unwrap();
+    assert_eq!(heartbeat.len(), flags_len + 8);
+    dec.decode_to_buffer(heartbeat.as_bytes(), heartbeat.len())
+        .unwrap();
+    assert!(dec.is_heartbeat());
+
+    // a decoder for another stream rejects the compact ID
+    let other = uuid::Uuid::from_u64_pair(0x1234_5678_9abc_def1, 0);
+    let mut dec = Decoder::new(
+        other,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    match dec.decode_to_buffer(msg.as_bytes(), msg.len()) {
+        Err(JetstreamError::IdMismatch { expected, got }) => {
+            assert_eq!(expected, other);
+            assert_eq!(got, id);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // a compact ID is cut short after 8 bytes
+    assert!(matches!(
+        dec.decode_to_buffer(&msg.as_bytes()[..flags_len + 7], flags_len + 7),
+        Err(JetstreamError::Truncated { needed, .. }) if needed == flags_len + 8
+    ));
+}
+
+#[test]
+fn test_format_descriptor() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let mut enc = Encoder::with_delta_layers(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+        2,
+    )
+    .unwrap();
+    enc.use_xor = true;
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+
+    // a default decoder configures itself from the descriptor
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let info = dec.inspect(msg.as_bytes()).unwrap();
+    assert_eq!((info.delta_encoding_layers, info.simple8b), (2, true));
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    assert!(dec.use_xor);
+    for (out, d) in dec.out.iter().zip(&data) {
+        assert_eq!(out.i32s, d.i32s);
+    }
+
+    // a decoder for fewer samples rejects the message, rather than corrupting it
+    let mut small = Decoder::new(id, test.count_of_variables, test.sampling_rate, 10);
+    assert!(matches!(
+        small.decode_to_buffer(msg.as_bytes(), msg.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+
+    // reserved bits and unsupported numbers of layers are rejected
+    let (_, flags_len) = uvarint32(msg.as_bytes()).unwrap();
+    for descriptor in [0x20, 0x80, 0x00, 0x05] {
+        let mut corrupted = msg.as_bytes().to_vec();
+        corrupted[flags_len + 16] = descriptor;
+        assert!(
+            matches!(
+                dec.decode_to_buffer(&corrupted, corrupted.len()),
+                Err(JetstreamError::Corrupt(_))
+            ),
+            "{:#x}",
+            descriptor
+        );
+    }
+}
+
+#[test]
+fn test_prewarm() {
+    for name in ["a10-2", "b4000-80", "e14400-14400"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+        let new_encoder = || {
+            Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            )
+        };
+
+        // prewarming does not change the messages, even after a previous message
+        let expected = encode_all_messages(&mut new_encoder(), &data).unwrap();
+        let mut enc = new_encoder();
+        enc.prewarm().unwrap();
+        assert_eq!(
+            encode_all_messages(&mut enc, &data).unwrap(),
+            expected,
+            "{}",
+            name
+        );
+        enc.prewarm().unwrap();
+        assert_eq!(
+            encode_all_messages(&mut enc, &data).unwrap(),
+            expected,
+            "{}",
+            name
+        );
+
+        // buffered samples would be lost
+        enc.encode(&data[0]).unwrap();
+        assert!(matches!(
+            enc.prewarm(),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn test_xor_round_trip() {
+    for name in [
+        "a10-1",
+        "a10-2q",
+        "a8-8q",
+        "b4000-80",
+        "d4000-4000q",
+        "e14400-14400",
+    ] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        // each number of delta layers exercises a different depth of the XOR accumulation,
+        // and restarting at quality changes exercises clearing it
+        for layers in 1..=4 {
+            let new_encoder = || {
+                let mut enc = Encoder::with_delta_layers(
+                    id,
+                    test.count_of_variables,
+                    test.sampling_rate,
+                    test.samples_per_message,
+                    layers,
+                )
+                .unwrap();
+                enc.set_xor(true).unwrap();
+                enc.set_reset_on_quality_change(test.quality_change)
+                    .unwrap();
+                enc
+            };
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            dec.set_xor(true);
+
+            let messages = encode_all_messages(&mut new_encoder(), &data).unwrap();
+            let mut decoded = vec![];
+            for msg in &messages {
+                dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+                decoded.extend_from_slice(&dec.out[..msg.samples()]);
+            }
+            assert!(dec.use_xor);
+            assert!(
+                diff(&data, &decoded).is_empty(),
+                "{} with {} layers",
+                name,
+                layers
+            );
+
+            // encoding the decoded samples again reproduces the messages byte for byte
+            let again = encode_all_messages(&mut new_encoder(), &decoded).unwrap();
+            assert_eq!(again, messages, "{} with {} layers", name, layers);
+        }
+    }
+
+    // the transform cannot change part way through a message
+    let mut enc = Encoder::new(uuid::Uuid::new_v4(), 8, 4000, 2);
+    enc.encode(&DatasetWithQuality::new(8)).unwrap();
+    assert!(matches!(
+        enc.set_xor(true),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_float_channels() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+    let scales = [0.01, 0.001];
+    let count = test.count_of_variables + scales.len();
+
+    // the waveforms, with a slowly rising temperature and a small, noisy measurement
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let waveforms = create_input_data(&mut ied, test.samples, test.count_of_variables, false);
+    let data: Vec<DatasetWithQuality> = waveforms
+        .iter()
+        .enumerate()
+        .map(|(k, w)| {
+            let mut d = DatasetWithQuality::with_floats(test.count_of_variables, scales.len());
+            d.t = w.t;
+            d.i32s.copy_from_slice(&w.i32s);
+            d.f32s = vec![20.0 + k as f32 * 0.0013, (k as f32 * 0.7).sin() * 0.25];
+            d
+        })
+        .collect();
+
+    let mut enc = Encoder::new(id, count, test.sampling_rate, test.samples_per_message);
+    enc.set_float_channels(&scales).unwrap();
+    let mut dec = Decoder::new(id, count, test.sampling_rate, test.samples_per_message);
+    dec.set_float_channels(&scales);
+
+    let mut total_samples = 0;
+    for msg in encode_all_messages(&mut enc, &data).unwrap() {
+        dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+        for (k, out) in dec.out[..msg.samples()].iter().enumerate() {
+            let d = &data[total_samples + k];
+            assert_eq!(out.i32s[..test.count_of_variables], d.i32s);
+
+            // each float is within half of its scale factor, allowing for rounding to f32
+            for ((&decoded, &original), &scale) in out.f32s.iter().zip(&d.f32s).zip(&scales) {
+                let bound = scale / 2.0 + original.abs() as f64 * f32::EPSILON as f64;
+                assert!(
+                    (decoded as f64 - original as f64).abs() <= bound,
+                    "{} decoded as {}",
+                    original,
+                    decoded
+                );
+            }
+        }
+        total_samples += msg.samples();
+    }
+    assert_eq!(total_samples, data.len());
+
+    // the floats must match the configured channels, and the factors must be positive
+    let mut d = DatasetWithQuality::with_floats(test.count_of_variables, 1);
+    assert!(matches!(
+        enc.encode(&d),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+    d.f32s.push(0.0);
+    d.i32s.pop();
+    assert!(matches!(
+        enc.encode(&d),
+        Err(JetstreamError::InvalidInput(_))
+    ));
+    for bad in [0.0, -1.0, f64::NAN] {
+        assert!(matches!(
+            enc.set_float_channels(&[bad]),
+            Err(JetstreamError::InvalidInput(_))
+        ));
+    }
+    assert!(enc.set_float_channels(&[1.0; 11]).is_err());
+}
+
+#[test]
+fn test_delta_sum_index() {
+    for layers in 0..=5usize {
+        // one running sum is kept for each layer after the first
+        let sums = layers.saturating_sub(1);
+        for j in 0..=layers + 2 {
+            let index = delta_sum_index(j, layers);
+            if j == 0 || sums == 0 {
+                assert_eq!(index, None, "{} samples with {} layers", j, layers);
+            } else {
+                assert_eq!(index, Some(usize::min(j, sums) - 1));
+                assert!(index.unwrap() < sums);
+            }
+        }
+    }
+
+    // the deepest sum is reached once each layer has a previous difference
+    assert_eq!(delta_sum_index(1, 3), Some(0));
+    assert_eq!(delta_sum_index(2, 3), Some(1));
+    assert_eq!(delta_sum_index(usize::MAX, 3), Some(1));
+}
+
+#[test]
+fn test_max_message_samples() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+    let mut enc = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut dec = Decoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    dec.max_message_samples = Some(test.samples_per_message);
+
+    let msg = encode_all_messages(&mut enc, &data[..test.samples_per_message])
+        .unwrap()
+        .remove(0);
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    assert_eq!(dec.encoded_samples(), test.samples_per_message);
+
+    // the sample count follows the flags, ID, format descriptor and timestamp
+    let bytes = msg.as_bytes();
+    assert!(bytes[0] < 0x80);
+    let mut forged = bytes[..26].to_vec();
+    let mut count = [0; 5];
+    let len = put_varint32(&mut count, 1_000_000_000);
+    forged.extend_from_slice(&count[..len]);
+    forged.extend_from_slice(&bytes[27..]);
+
+    assert!(matches!(
+        dec.decode_to_buffer(&forged, forged.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+
+    // a negative count is rejected too
+    let len = put_varint32(&mut count, -1);
+    forged.splice(26..26 + 5, count[..len].iter().copied());
+    assert!(matches!(
+        dec.decode_to_buffer(&forged, forged.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
+
+    // the decoder is unaffected by the rejected messages
+    dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+    assert_eq!(dec.encoded_samples(), test.samples_per_message);
+    for (k, d) in data[..test.samples_per_message].iter().enumerate() {
+        assert_eq!(dec.out[k].i32s, d.i32s);
+    }
+}
+
+#[test]
+fn test_encoder_buffers() {
+    for name in ["a10-2", "b4000-80"] {
+        let id = uuid::Uuid::new_v4();
+        let test = TESTS.get(name).unwrap();
+
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+        let mut enc = Encoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        let dec = Decoder::new(
+            id,
+            test.count_of_variables,
+            test.sampling_rate,
+            test.samples_per_message,
+        );
+        assert!(encoder_buffers(&enc).use_buf_a, "{}", name);
+
+        let mut previous: Option<Vec<u8>> = None;
+        for (m, chunk) in data[..test.samples_per_message * 3]
+            .chunks(test.samples_per_message)
+            .enumerate()
+        {
+            let use_buf_a = encoder_buffers(&enc).use_buf_a;
+            for d in &chunk[..chunk.len() - 1] {
+                assert!(enc.encode(d).unwrap().is_none());
+
+                // the previous message is not overwritten while the next is encoded
+                let buffers = encoder_buffers(&enc);
+                assert_eq!(buffers.use_buf_a, use_buf_a, "{}", name);
+                if let Some(previous) = &previous {
+                    assert_eq!(&buffers.inactive()[..previous.len()], previous, "{}", name);
+                }
+            }
+            let msg = enc.encode(&chunk[chunk.len() - 1]).unwrap().unwrap();
+            let info = dec.inspect(msg.as_bytes()).unwrap();
+
+            // the message was written to the active buffer, before switching to the other
+            let buffers = encoder_buffers(&enc);
+            assert_eq!(buffers.use_buf_a, !use_buf_a, "{} message {}", name, m);
+            assert_eq!(buffers.len, 0);
+            let buf = buffers.inactive();
+            assert_eq!(info.compression, Compression::None);
+            assert_eq!(&buf[..msg.len()], msg.as_bytes(), "{} message {}", name, m);
+
+            // the header holds the flags, ID, format descriptor, timestamp and sample count
+            let (_, mut length) = uvarint32(buf).unwrap();
+            assert_eq!(&buf[length..length + 16], id.as_bytes());
+            assert_eq!(info.id, id);
+            length += 16;
+            let descriptor = FormatDescriptor::read(buf, length).unwrap();
+            assert_eq!(descriptor.delta_layers, info.delta_encoding_layers);
+            assert_eq!(descriptor.simple8b, info.simple8b);
+            assert!(!descriptor.xor);
+            length += 1;
+            assert_eq!(
+                buf[length..length + 8],
+                chunk[0].t.to_be_bytes(),
+                "{}",
+                name
+            );
+            length += 8;
+            let (samples, _) = varint32(&buf[length..]).unwrap();
+            assert_eq!(samples as usize, info.samples);
+            assert_eq!(info.samples, test.samples_per_message);
+
+            previous = Some(msg.as_bytes().to_vec());
+        }
+    }
+}
+
+#[test]
+fn test_decoder_format_switch() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("b4000-80").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(
+        &mut ied,
+        test.samples,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    // varint messages of two samples, and simple-8b messages of 80 samples or ended early
+    let mut small = Encoder::new(id, test.count_of_variables, test.sampling_rate, 2);
+    let mut large = Encoder::new(
+        id,
+        test.count_of_variables,
+        test.sampling_rate,
+        test.samples_per_message,
+    );
+    let mut messages = vec![];
+    for (m, chunk) in data[..test.samples_per_message * 4]
+        .chunks(test.samples_per_message)
+        .enumerate()
+    {
+        let msg = match m % 3 {
+            0 => encode_all_messages(&mut small, &chunk[..2])
+                .unwrap()
+                .remove(0),
+            1 => encode_all_messages(&mut large, chunk).unwrap().remove(0),
+            _ => {
+                for d in &chunk[..10] {
+                    assert!(large.encode(d).unwrap().is_none());
+                }
+                large.end_encode().unwrap()
+            }
+        };
+        messages.push((msg, chunk));
+    }
+
+    // a single decoder follows the format of each message
+    for spm in [test.samples_per_message, 16] {
+        let mut dec = Decoder::new(id, test.count_of_variables, test.sampling_rate, spm);
+        for (m, (msg, chunk)) in messages.iter().enumerate() {
+            let info = dec.inspect(msg.as_bytes()).unwrap();
+            assert_eq!(info.simple8b, m % 3 != 0);
+
+            if msg.samples() > spm {
+                assert!(matches!(
+                    dec.decode_to_buffer(msg.as_bytes(), msg.len()),
+                    Err(JetstreamError::Corrupt(_))
+                ));
+                continue;
+            }
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            assert_eq!(dec.encoded_samples(), msg.samples());
+            for (k, d) in chunk[..msg.samples()].iter().enumerate() {
+                assert_eq!(dec.out[k].i32s, d.i32s, "{} message {} at {}", spm, m, k);
+                assert_eq!(dec.out[k].q, d.q, "{} message {} at {}", spm, m, k);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_timestamp_field() {
+    let id = uuid::Uuid::new_v4();
+    let test = TESTS.get("a10-2").unwrap();
+
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let mut data = create_input_data(
+        &mut ied,
+        test.samples_per_message,
+        test.count_of_variables,
+        test.quality_change,
+    );
+
+    for t in [0xDEAD_BEEF_CAFE_BABE, u64::MAX] {
+        for little_endian in [false, true] {
+            data[0].t = t;
+            let mut enc = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            enc.set_little_endian(little_endian);
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+
+            // eight bytes follow the flags, ID and format descriptor
+            let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+            let (_, flags_len) = uvarint32(msg.as_bytes()).unwrap();
+            let field = &msg.as_bytes()[flags_len + 17..flags_len + 25];
+            if little_endian {
+                assert_eq!(field, t.to_le_bytes());
+            } else {
+                assert_eq!(field, t.to_be_bytes());
+            }
+
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            assert_eq!(dec.out[0].t, t, "{:#x} little endian {}", t, little_endian);
+
+            // inferred timestamps wrap rather than overflowing
+            dec.infer_timestamps = true;
+            dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+            let interval = 1_000_000_000 / test.sampling_rate as u64;
+            assert_eq!(dec.out[1].t, t.wrapping_add(interval), "{:#x}", t);
+        }
+    }
+}
+
+#[test]
+fn test_count_final_quality_run() {
+    let id = uuid::Uuid::new_v4();
+    for name in ["a10-2q", "a8-8q", "b4000-80"] {
+        let test = TESTS.get(name).unwrap();
+        let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+        let data = create_input_data(
+            &mut ied,
+            test.samples,
+            test.count_of_variables,
+            test.quality_change,
+        );
+
+        let mut outputs = vec![];
+        for count_final_quality_run in [false, true] {
+            let mut enc = Encoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+            enc.count_final_quality_run = count_final_quality_run;
+            let mut dec = Decoder::new(
+                id,
+                test.count_of_variables,
+                test.sampling_rate,
+                test.samples_per_message,
+            );
+
+            let mut out = vec![];
+            let mut msgs = encode_all_messages(&mut enc, &data).unwrap();
+            // a final, shorter message must not fill its quality from the previous message
+            for d in &data[..test.samples_per_message.div_ceil(2)] {
+                assert!(enc.encode(d).unwrap().is_none());
+            }
+            msgs.push(enc.end_encode().unwrap());
+            for msg in &msgs {
+                dec.decode_to_buffer(msg.as_bytes(), msg.len()).unwrap();
+                out.extend(dec.out.iter().map(|d| (d.i32s.clone(), d.q.clone())));
+            }
+            outputs.push(out);
+        }
+        assert_eq!(outputs[0], outputs[1], "{}", name);
+    }
+
+    // a counted run longer than the rest of the message is corrupt
+    let test = TESTS.get("a10-2").unwrap();
+    let mut ied: Emulator = create_emulator(test.sampling_rate, 0.0);
+    let data = create_input_data(&mut ied, 2, test.count_of_variables, false);
+    let mut enc = Encoder::new(id, test.count_of_variables, test.sampling_rate, 2);
+    enc.count_final_quality_run = true;
+    let mut dec = Decoder::new(id, test.count_of_variables, test.sampling_rate, 2);
+    let msg = encode_all_messages(&mut enc, &data).unwrap().remove(0);
+    let mut bytes = msg.as_bytes().to_vec();
+    assert_eq!(bytes[bytes.len() - 2..], [0, 2]);
+    *bytes.last_mut().unwrap() = 3;
+    assert!(matches!(
+        dec.decode_to_buffer(&bytes, bytes.len()),
+        Err(JetstreamError::Corrupt(_))
+    ));
 }
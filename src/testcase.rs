@@ -1,10 +1,12 @@
 use crate::decoder::Decoder;
 use crate::emulator::{Emulator, ThreePhaseEmulation};
 use crate::encoder::Encoder;
+use crate::error::JetstreamError;
 use crate::jetstream::DatasetWithQuality;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::time::Instant;
 
 #[derive(Default)]
 pub struct TestCase {
@@ -284,18 +286,24 @@ pub fn create_emulator(sampling_rate: usize, phase_offset_deg: f64) -> Emulator
 
         ..Default::default()
     });
-    emu.i = Some(ThreePhaseEmulation {
+    let mut i = ThreePhaseEmulation {
         pos_seq_mag: 500.0,
         phase_offset: phase_offset_deg * PI / 180.0,
-        harmonic_numbers: vec![5.0, 7.0, 11.0, 13.0, 17.0, 19.0, 23.0, 25.0],
-        harmonic_mags: vec![
-            0.2164, 0.1242, 0.0892, 0.0693, 0.0541, 0.0458, 0.0370, 0.0332,
-        ],
-        harmonic_angs: vec![171.5, 100.4, -52.4, 128.3, 80.0, 2.9, -146.8, 133.9],
         noise_max: 0.00001,
 
         ..Default::default()
-    });
+    };
+    i.set_harmonics(&[
+        (5.0, 0.2164, 171.5),
+        (7.0, 0.1242, 100.4),
+        (11.0, 0.0892, -52.4),
+        (13.0, 0.0693, 128.3),
+        (17.0, 0.0541, 80.0),
+        (19.0, 0.0458, 2.9),
+        (23.0, 0.0370, -146.8),
+        (25.0, 0.0332, 133.9),
+    ]);
+    emu.i = Some(i);
 
     emu
 }
@@ -314,22 +322,12 @@ pub fn create_input_data(
         // compute emulated waveform data
         ied.step();
 
+        // set waveform data
+        d.i32s[..8].copy_from_slice(&ied.dataset_9_2_le().i32s);
+
         // calculate timestamp
         d.t = k as u64;
 
-        let i = ied.i.as_ref().unwrap();
-        let v = ied.v.as_ref().unwrap();
-
-        // set waveform data
-        d.i32s[0] = (i.a * 1000.0) as i32;
-        d.i32s[1] = (i.b * 1000.0) as i32;
-        d.i32s[2] = (i.c * 1000.0) as i32;
-        d.i32s[3] = ((i.a + i.b + i.c) * 1000.0) as i32;
-        d.i32s[4] = (v.a * 100.0) as i32;
-        d.i32s[5] = (v.b * 100.0) as i32;
-        d.i32s[6] = (v.c * 100.0) as i32;
-        d.i32s[7] = ((v.a + v.b + v.c) * 100.0) as i32;
-
         // set quality data
         d.q[0] = 0;
         d.q[1] = 0;
@@ -357,10 +355,22 @@ pub struct EncodeStats {
     pub messages: usize,
     pub total_bytes: usize,
     pub total_header_bytes: usize,
+    pub encode_nanos: Option<u64>, // only recorded if encode timing is enabled
+}
+
+impl EncodeStats {
+    /// Returns the encoding throughput in MB/s, given the size of the input data in bytes,
+    /// if encode timing was enabled.
+    pub fn encode_throughput(&self, input_bytes: usize) -> Option<f64> {
+        self.encode_nanos
+            .filter(|&nanos| nanos > 0)
+            .map(|nanos| (input_bytes as f64 / 1e6) / (nanos as f64 / 1e9))
+    }
 }
 
 const EARLY_ENCODING_STOP_SAMPLES: usize = 100;
 
+#[allow(clippy::too_many_arguments)]
 pub fn encode_and_decode(
     compare: bool,
     data: &mut [DatasetWithQuality],
@@ -369,34 +379,41 @@ pub fn encode_and_decode(
     _count_of_variables: usize,
     _samples_per_message: usize,
     early_encoding_stop: bool,
-) -> Result<EncodeStats, String> {
+    time_encoding: bool,
+) -> Result<EncodeStats, JetstreamError> {
     let mut encode_stats = EncodeStats {
         samples: 0,
         messages: 0,
         total_bytes: 0,
         total_header_bytes: 0,
+        encode_nanos: if time_encoding { Some(0) } else { None },
     };
     let mut total_samples_read = 0;
 
     for i in 0..data.len() {
         encode_stats.samples += 1;
-        let (buf, length) = enc.encode(data.get_mut(i).unwrap())?;
+        let start = encode_stats.encode_nanos.map(|_| Instant::now());
+        let msg = enc.encode(data.get_mut(i).unwrap())?;
 
         // simulate encoding stopping early
-        let (buf, length) =
-            if early_encoding_stop && length != 0 && i == (EARLY_ENCODING_STOP_SAMPLES - 1) {
-                enc.end_encode()?
-            } else {
-                (buf, length)
-            };
+        let msg = if early_encoding_stop && msg.is_some() && i == (EARLY_ENCODING_STOP_SAMPLES - 1)
+        {
+            Some(enc.end_encode()?)
+        } else {
+            msg
+        };
+
+        if let (Some(start), Some(nanos)) = (start, encode_stats.encode_nanos.as_mut()) {
+            *nanos += start.elapsed().as_nanos() as u64;
+        }
 
-        if length > 0 {
+        if let Some(msg) = msg {
             // generate average stats
             encode_stats.messages += 1;
-            encode_stats.total_bytes += length;
+            encode_stats.total_bytes += msg.len();
             encode_stats.total_header_bytes += 24;
 
-            dec.decode_to_buffer(&buf, length)?;
+            dec.decode_to_buffer(msg.as_bytes(), msg.len())?;
 
             // compare decoded output
             if compare {